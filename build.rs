@@ -0,0 +1,34 @@
+//! Records the git hash and the enabled cargo features of the build, which
+//! are returned by the `version` RPC
+//!
+//! The git hash can be given by the `GIT_HASH` environment variable when
+//! building without the repository, such as in the Docker image.
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let git_hash = env::var("GIT_HASH")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(&["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }).unwrap_or_else(|| "unknown".to_string());
+
+    let mut features = env::vars()
+        .filter(|(key, _)| key.starts_with("CARGO_FEATURE_"))
+        .map(|(key, _)| key["CARGO_FEATURE_".len()..].to_lowercase().replace('_', "-"))
+        .collect::<Vec<_>>();
+    features.sort();
+
+    println!("cargo:rustc-env=CONTROLLER_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=CONTROLLER_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+    }
+}
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE threads
+  DROP FOREIGN KEY threads_answer_fk,
+  DROP COLUMN answer_id;
+
+ALTER TABLE categories
+  DROP COLUMN qa;
@@ -0,0 +1,10 @@
+ALTER TABLE categories
+  ADD COLUMN qa BOOLEAN NOT NULL DEFAULT 0;
+
+ALTER TABLE threads
+  ADD COLUMN answer_id INT UNSIGNED NULL,
+
+  ADD CONSTRAINT threads_answer_fk
+    FOREIGN KEY (answer_id)
+    REFERENCES comments(id)
+    ON DELETE SET NULL;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE notifications;
+DROP TABLE category_subscriptions;
+DROP TABLE thread_subscriptions;
@@ -0,0 +1,56 @@
+CREATE TABLE thread_subscriptions (
+
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NOT NULL,
+
+  PRIMARY KEY (user_id, thread_id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE
+);
+
+CREATE TABLE category_subscriptions (
+
+  user_id INT UNSIGNED NOT NULL,
+  category_id INT UNSIGNED NOT NULL,
+
+  PRIMARY KEY (user_id, category_id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (category_id)
+    REFERENCES categories(id)
+    ON DELETE CASCADE
+);
+
+CREATE TABLE notifications (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NOT NULL,
+  comment_id INT UNSIGNED NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+  seen BOOLEAN NOT NULL DEFAULT 0,
+
+  PRIMARY KEY (id),
+  INDEX (user_id, seen),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE notifications
+  DROP COLUMN mention;
+
+DROP TABLE mentions;
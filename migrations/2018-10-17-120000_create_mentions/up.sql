@@ -0,0 +1,20 @@
+CREATE TABLE mentions (
+
+  comment_id INT UNSIGNED NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (comment_id, user_id),
+  INDEX (user_id),
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE
+);
+
+ALTER TABLE notifications
+  ADD COLUMN mention BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,16 @@
+CREATE TABLE thread_reads (
+
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NOT NULL,
+  comment_id INT UNSIGNED NOT NULL,
+
+  PRIMARY KEY (user_id, thread_id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE
+);
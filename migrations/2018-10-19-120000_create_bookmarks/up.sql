@@ -0,0 +1,22 @@
+CREATE TABLE bookmarks (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NULL,
+  comment_id INT UNSIGNED NULL,
+  note VARCHAR(255) NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id),
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE
+);
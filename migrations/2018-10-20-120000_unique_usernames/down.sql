@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+-- The duplicate usernames which were renamed are not restored, and the audit
+-- log is kept for `2018-10-22-120000_audit_log` to drop
+ALTER TABLE users
+  DROP INDEX users_username_unique;
@@ -0,0 +1,37 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  action VARCHAR(32) NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  actor_id INT UNSIGNED NULL,
+  details TEXT NOT NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+  INDEX (user_id)
+);
+
+ALTER TABLE users
+  MODIFY username VARCHAR(20) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL;
+
+INSERT INTO audit_log (action, user_id, details)
+  SELECT DISTINCT 'rename_user', u.id,
+    CONCAT('duplicate username ', u.username, ' renamed to ',
+      LEFT(u.username, 19 - LENGTH(u.id)), '_', u.id)
+  FROM users u
+  JOIN users o
+    ON o.username = u.username
+    AND o.id < u.id;
+
+UPDATE users u
+  JOIN (
+    SELECT DISTINCT d.id FROM users d
+    JOIN users o
+      ON o.username = d.username
+      AND o.id < d.id
+  ) duplicates
+    ON duplicates.id = u.id
+  SET u.username = CONCAT(LEFT(u.username, 19 - LENGTH(u.id)), '_', u.id);
+
+ALTER TABLE users
+  ADD CONSTRAINT users_username_unique UNIQUE (username);
@@ -0,0 +1,15 @@
+CREATE TABLE username_history (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  user_id INT UNSIGNED NOT NULL,
+  username VARCHAR(20) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL,
+  changed DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+  INDEX (user_id, changed),
+  INDEX (username, changed),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE
+);
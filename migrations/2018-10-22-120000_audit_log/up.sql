@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  action VARCHAR(32) NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  actor_id INT UNSIGNED NULL,
+  details TEXT NOT NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+  INDEX (user_id)
+);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+  DROP COLUMN joined,
+  DROP COLUMN thread_count,
+  DROP COLUMN comment_count,
+  DROP COLUMN last_active;
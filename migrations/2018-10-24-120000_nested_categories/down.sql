@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE categories
+  DROP FOREIGN KEY categories_parent_fk,
+  DROP COLUMN parent_id;
@@ -0,0 +1,7 @@
+ALTER TABLE categories
+  ADD COLUMN parent_id INT UNSIGNED NULL,
+
+  ADD CONSTRAINT categories_parent_fk
+    FOREIGN KEY (parent_id)
+    REFERENCES categories(id)
+    ON DELETE CASCADE;
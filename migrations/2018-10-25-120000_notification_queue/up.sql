@@ -0,0 +1,16 @@
+CREATE TABLE notification_queue (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  thread_id INT UNSIGNED NOT NULL,
+  comment_id INT UNSIGNED NULL,
+
+  PRIMARY KEY (id),
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE
+);
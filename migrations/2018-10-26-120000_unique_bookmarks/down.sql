@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE bookmarks
+  DROP INDEX bookmarks_target_unique,
+  DROP COLUMN target_comment_id,
+  DROP COLUMN target_thread_id;
@@ -0,0 +1,13 @@
+DELETE b FROM bookmarks b
+  JOIN bookmarks o
+    ON o.user_id = b.user_id
+    AND o.comment_id <=> b.comment_id
+    AND (b.comment_id IS NOT NULL OR o.thread_id <=> b.thread_id)
+    AND o.id > b.id;
+
+ALTER TABLE bookmarks
+  ADD COLUMN target_thread_id INT UNSIGNED
+    AS (IF(comment_id IS NULL, thread_id, 0)) STORED,
+  ADD COLUMN target_comment_id INT UNSIGNED AS (IFNULL(comment_id, 0)) STORED,
+  ADD CONSTRAINT bookmarks_target_unique
+    UNIQUE (user_id, target_thread_id, target_comment_id);
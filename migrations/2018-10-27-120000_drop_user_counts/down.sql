@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+  ADD COLUMN thread_count INT UNSIGNED NOT NULL DEFAULT 0,
+  ADD COLUMN comment_count INT UNSIGNED NOT NULL DEFAULT 0,
+  ADD COLUMN last_active DATETIME NULL;
+
+UPDATE users SET
+  thread_count = (SELECT COUNT(*) FROM threads WHERE threads.user_id = users.id),
+  comment_count = (SELECT COUNT(*) FROM comments WHERE comments.user_id = users.id),
+  last_active = GREATEST(
+    COALESCE((SELECT MAX(timestamp) FROM threads WHERE threads.user_id = users.id), '1000-01-01'),
+    COALESCE((SELECT MAX(timestamp) FROM comments WHERE comments.user_id = users.id), '1000-01-01')
+  );
+
+UPDATE users SET
+  last_active = NULL
+  WHERE last_active = '1000-01-01';
@@ -0,0 +1,4 @@
+ALTER TABLE users
+  DROP COLUMN thread_count,
+  DROP COLUMN comment_count,
+  DROP COLUMN last_active;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE notification_queue
+  DROP COLUMN attempts;
@@ -0,0 +1,2 @@
+ALTER TABLE notification_queue
+  ADD COLUMN attempts INT UNSIGNED NOT NULL DEFAULT 0;
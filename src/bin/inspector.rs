@@ -8,6 +8,7 @@ extern crate tarpc;
 extern crate datatypes;
 extern crate futures;
 extern crate rustyline;
+#[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate failure;
@@ -26,6 +27,12 @@ use datatypes::content::requests::*;
 use datatypes::content::responses::*;
 
 use failure::Fallible;
+
+// The payloads of the RPCs which are not a part of `datatypes`
+#[allow(dead_code)]
+#[path = "../payloads.rs"]
+mod payloads;
+use self::payloads::*;
 macro_rules! enum_str {
     {
         $( #[ $( $attr:meta ),* ] )*
@@ -65,7 +72,11 @@ enum_str! {
         Insert => "insert",
         Hide => "hide",
         Edit => "edit",
-        Delete => "delete"
+        Delete => "delete",
+        Status => "status",
+        Metrics => "metrics",
+        LogLevel => "log-level",
+        FlushCache => "flush-cache"
     }
 }
 
@@ -114,6 +125,11 @@ fn cmd_handler<'a>(state: &State, s: &'a str) -> Fallible<()> {
 
         (Mode::Search, Cmd::Get) => run_search(args),
 
+        (_, Cmd::Status) => run_status(args),
+        (_, Cmd::Metrics) => run_metrics(args),
+        (_, Cmd::LogLevel) => run_log_level(args),
+        (_, Cmd::FlushCache) => run_flush_cache(args),
+
         (m, c) => Err(format_err!(
             "Unimplemented command '{}' for mode '{}'",
             c,
@@ -429,6 +445,46 @@ fn run_search<'a>(mut args: impl Iterator<Item = &'a str>) -> Fallible<()> {
     Ok(())
 }
 
+// Status
+
+fn run_status<'a>(mut _args: impl Iterator<Item = &'a str>) -> Fallible<()> {
+    run_client_action(|client| client.health(()));
+    run_client_action(|client| client.readiness(()));
+    run_client_action(|client| client.version(()));
+    Ok(())
+}
+
+fn run_metrics<'a>(mut _args: impl Iterator<Item = &'a str>) -> Fallible<()> {
+    if let Some(client) = connect() {
+        match client.metrics(()) {
+            Ok(metrics) => print!("{}", metrics),
+            Err(error) => println!("The server responded with error: {:#?}", error),
+        }
+    }
+    Ok(())
+}
+
+/// Sets the level of a module, or the default level without a module
+///
+/// A level of `default` removes the level of the module.
+fn run_log_level<'a>(mut args: impl Iterator<Item = &'a str>) -> Fallible<()> {
+    let level: String = get_next_field!(args, level)?;
+    let module = args.next().map(String::from);
+
+    let payload = SetLogLevelPayload {
+        level: if level == "default" { None } else { Some(level) },
+        module,
+    };
+
+    run_client_action(|client| client.set_log_level(payload));
+    Ok(())
+}
+
+fn run_flush_cache<'a>(mut _args: impl Iterator<Item = &'a str>) -> Fallible<()> {
+    run_client_action(|client| client.flush_cache(()));
+    Ok(())
+}
+
 service! {
     rpc get_user(payload: GetUserPayload) -> UserPayload | ContentError;
     rpc add_user(payload: AddUserPayload) -> UserPayload | ContentError;
@@ -455,6 +511,76 @@ service! {
     rpc hide_comment(payload: HideCommentPayload) -> CommentPayload | ContentError;
 
     rpc search(payload: SearchPayload) -> SearchResultsPayload | ContentError;
+
+    rpc set_category_qa(payload: Traced<SetQaPayload>) -> CategoryPayload | ServiceError;
+    rpc get_threads_in_category_by_answer(payload: Traced<GetAnsweredThreadsPayload>) -> Vec<ThreadPayload> | ServiceError;
+    rpc accept_answer(payload: Traced<AcceptAnswerPayload>) -> AnswerPayload | ServiceError;
+    rpc get_answer(payload: Traced<GetThreadPayload>) -> AnswerPayload | ServiceError;
+
+    rpc subscribe_thread(payload: Traced<SubscribeThreadPayload>) -> () | ServiceError;
+    rpc subscribe_category(payload: Traced<SubscribeCategoryPayload>) -> () | ServiceError;
+    rpc get_notifications(payload: Traced<GetNotificationsPayload>) -> Vec<NotificationPayload> | ServiceError;
+    rpc read_notifications(payload: Traced<ReadNotificationsPayload>) -> () | ServiceError;
+    rpc count_unread_notifications(payload: Traced<GetUserPayload>) -> u32 | ServiceError;
+
+    rpc get_mentions(payload: Traced<GetMentionsPayload>) -> Vec<CommentPayload> | ServiceError;
+
+    rpc mark_thread_read(payload: Traced<MarkThreadReadPayload>) -> () | ServiceError;
+    rpc get_threads_in_category_for_user(payload: Traced<GetUserThreadsPayload>) -> Vec<UnreadThreadPayload> | ServiceError;
+    rpc get_unread_threads(payload: Traced<GetUnreadThreadsPayload>) -> Vec<UnreadThreadPayload> | ServiceError;
+
+    rpc add_bookmark(payload: Traced<AddBookmarkPayload>) -> BookmarkPayload | ServiceError;
+    rpc remove_bookmark(payload: Traced<RemoveBookmarkPayload>) -> () | ServiceError;
+    rpc get_bookmarks(payload: Traced<GetBookmarksPayload>) -> Vec<BookmarkPayload> | ServiceError;
+
+    rpc get_user_by_username(payload: Traced<GetUserByUsernamePayload>) -> UserPayload | ServiceError;
+    rpc get_users(payload: Traced<GetUsersPayload>) -> Vec<UserPayload> | ServiceError;
+
+    rpc rename_user(payload: Traced<RenameUserPayload>) -> UserPayload | ServiceError;
+    rpc get_username_history(payload: Traced<GetUserPayload>) -> Vec<UsernameChangePayload> | ServiceError;
+
+    rpc delete_user(payload: Traced<DeleteUserPayload>) -> () | ServiceError;
+    rpc export_user_data(payload: Traced<GetUserPayload>) -> String | ServiceError;
+
+    rpc get_user_profile(payload: Traced<GetUserPayload>) -> UserProfilePayload | ServiceError;
+    rpc get_user_activity(payload: Traced<GetUserActivityPayload>) -> Vec<ActivityPayload> | ServiceError;
+
+    rpc health(payload: ()) -> HealthPayload | ServiceError;
+    rpc readiness(payload: ()) -> ReadinessPayload | ServiceError;
+    rpc version(payload: ()) -> VersionPayload | ServiceError;
+    rpc metrics(payload: ()) -> String | ServiceError;
+    rpc set_log_level(payload: SetLogLevelPayload) -> LogLevelsPayload | ServiceError;
+    rpc flush_cache(payload: ()) -> () | ServiceError;
+
+    rpc moderate_answer(payload: Traced<ModerateAnswerPayload>) -> AnswerPayload | ServiceError;
+
+    // The RPCs of `datatypes`, taking the trace id of the caller and failing
+    // with a `ServiceError`
+    rpc get_user_traced(payload: Traced<GetUserPayload>) -> UserPayload | ServiceError;
+    rpc add_user_traced(payload: Traced<AddUserPayload>) -> UserPayload | ServiceError;
+    rpc edit_user_traced(payload: Traced<EditUserPayload>) -> UserPayload | ServiceError;
+
+    rpc get_category_traced(payload: Traced<GetCategoryPayload>) -> CategoryPayload | ServiceError;
+    rpc get_all_categories_traced(payload: Traced<GetHiddenPayload>) -> Vec<CategoryPayload> | ServiceError;
+    rpc add_category_traced(payload: Traced<AddCategoryPayload>) -> CategoryPayload | ServiceError;
+    rpc edit_category_traced(payload: Traced<EditCategoryPayload>) -> CategoryPayload | ServiceError;
+    rpc hide_category_traced(payload: Traced<HideCategoryPayload>) -> CategoryPayload | ServiceError;
+
+    rpc get_thread_traced(payload: Traced<GetThreadPayload>) -> ThreadPayload | ServiceError;
+    rpc get_threads_in_category_traced(payload: Traced<GetThreadsPayload>) -> Vec<ThreadPayload> | ServiceError;
+    rpc get_all_threads_traced(payload: Traced<GetHiddenPayload>) -> Vec<ThreadPayload> | ServiceError;
+    rpc add_thread_traced(payload: Traced<AddThreadPayload>) -> ThreadPayload | ServiceError;
+    rpc edit_thread_traced(payload: Traced<EditThreadPayload>) -> ThreadPayload | ServiceError;
+    rpc hide_thread_traced(payload: Traced<HideThreadPayload>) -> ThreadPayload | ServiceError;
+
+    rpc get_comment_traced(payload: Traced<GetCommentPayload>) -> CommentPayload | ServiceError;
+    rpc get_comments_in_thread_traced(payload: Traced<GetCommentsPayload>) -> Vec<CommentPayload> | ServiceError;
+    rpc get_all_comments_traced(payload: Traced<GetHiddenPayload>) -> Vec<CommentPayload> | ServiceError;
+    rpc add_comment_traced(payload: Traced<AddCommentPayload>) -> CommentPayload | ServiceError;
+    rpc edit_comment_traced(payload: Traced<EditCommentPayload>) -> CommentPayload | ServiceError;
+    rpc hide_comment_traced(payload: Traced<HideCommentPayload>) -> CommentPayload | ServiceError;
+
+    rpc search_traced(payload: Traced<SearchPayload>) -> SearchResultsPayload | ServiceError;
 }
 
 // Connect to server
@@ -0,0 +1,241 @@
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Integer, Unsigned};
+use failure::ResultExt;
+
+use super::{in_visible_thread, max_rows, DbConn, MAX_ACTIVITY_LIMIT};
+use crate::types::{Activity, ActivityItem, Comment, Thread, UserStats};
+use crate::{IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// The `kind` of an `ActivityItem` which is a thread
+const THREAD_KIND: i64 = 0;
+
+/// The conditions on threads and comments which leave out the hidden ones,
+/// and the ones in hidden threads or categories, unless `include_hidden` is
+/// set
+fn visible_filters(include_hidden: bool) -> (String, String) {
+    if include_hidden {
+        (String::new(), String::new())
+    } else {
+        (
+            format!(" AND {}", in_visible_thread("threads.id")),
+            format!(
+                " AND comments.hidden = 0 AND {}",
+                in_visible_thread("comments.thread_id")
+            ),
+        )
+    }
+}
+
+/// Gets a page of the threads and comments of a user, newest first
+pub fn get_user_activity(
+    con: &DbConn,
+    user_id: UserId,
+    include_hidden: bool,
+    offset: u32,
+    limit: u32,
+) -> IntResult<Vec<Activity>> {
+    use super::schema::{comments, threads};
+
+    trace!(
+        "Getting activity of user ({}), {}",
+        user_id,
+        fmt_hidden!(include_hidden)
+    );
+
+    let (thread_filter, comment_filter) = visible_filters(include_hidden);
+
+    let items: Vec<ActivityItem> = sql_query(format!(
+        "SELECT CAST({thread} AS SIGNED) AS kind, id, timestamp FROM threads \
+         WHERE user_id = ?{thread_filter} \
+         UNION ALL \
+         SELECT CAST({comment} AS SIGNED) AS kind, id, timestamp FROM comments \
+         WHERE user_id = ?{comment_filter} \
+         ORDER BY timestamp DESC, kind ASC, id DESC LIMIT ? OFFSET ?",
+        thread = THREAD_KIND,
+        comment = THREAD_KIND + 1,
+        thread_filter = thread_filter,
+        comment_filter = comment_filter
+    )).bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<BigInt, _>(i64::from(limit).min(max_rows(&MAX_ACTIVITY_LIMIT)))
+    .bind::<BigInt, _>(i64::from(offset))
+    .load(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!("Unable to get activity of user ({}): {}", user_id, e);
+        e
+    })?;
+
+    let (thread_items, comment_items): (Vec<_>, Vec<_>) =
+        items.iter().partition(|item| item.kind == THREAD_KIND);
+    let thread_ids = thread_items.iter().map(|item| item.id).collect::<Vec<_>>();
+    let comment_ids = comment_items.iter().map(|item| item.id).collect::<Vec<_>>();
+
+    let mut threads = threads::table
+        .filter(threads::id.eq_any(&thread_ids))
+        .get_results::<Thread>(con)
+        .context(IntErrorKind::QueryError)?;
+    let mut comments = comments::table
+        .filter(comments::id.eq_any(&comment_ids))
+        .get_results::<Comment>(con)
+        .context(IntErrorKind::QueryError)?;
+
+    // Keep the order of the items, which is by time
+    Ok(items
+        .into_iter()
+        .filter_map(|item| {
+            if item.kind == THREAD_KIND {
+                threads
+                    .iter()
+                    .position(|t| t.id == item.id)
+                    .map(|i| Activity::Thread(threads.swap_remove(i)))
+            } else {
+                comments
+                    .iter()
+                    .position(|c| c.id == item.id)
+                    .map(|i| Activity::Comment(comments.swap_remove(i)))
+            }
+        }).collect())
+}
+
+/// Counts the threads and comments of a user and finds the latest of them,
+/// leaving out the hidden ones unless `include_hidden` is set
+pub fn get_user_stats(con: &DbConn, user_id: UserId, include_hidden: bool) -> IntResult<UserStats> {
+    trace!(
+        "Getting stats of user ({}), {}",
+        user_id,
+        fmt_hidden!(include_hidden)
+    );
+
+    let (thread_filter, comment_filter) = visible_filters(include_hidden);
+
+    sql_query(format!(
+        "SELECT CAST(COALESCE(SUM(kind = {thread}), 0) AS SIGNED) AS thread_count, \
+         CAST(COALESCE(SUM(kind <> {thread}), 0) AS SIGNED) AS comment_count, \
+         MAX(timestamp) AS last_active FROM ( \
+         SELECT {thread} AS kind, timestamp FROM threads WHERE user_id = ?{thread_filter} \
+         UNION ALL \
+         SELECT {comment} AS kind, timestamp FROM comments WHERE user_id = ?{comment_filter} \
+         ) content",
+        thread = THREAD_KIND,
+        comment = THREAD_KIND + 1,
+        thread_filter = thread_filter,
+        comment_filter = comment_filter
+    )).bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<Unsigned<Integer>, _>(*user_id)
+    .get_result(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!("Unable to get stats of user ({}): {}", user_id, e);
+        e.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{categories, comments, establish_connection, threads, users};
+    use crate::types::{
+        InsertCategory, InsertComment, InsertThread, InsertUser, UpdateCategory, UpdateComment,
+    };
+
+    #[test]
+    fn activity_and_counts() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // User
+        let insert_data = InsertUser {
+            id: 48,
+            username: "ActiveUser".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let user = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Comments, the latter hidden
+        let mut comment_ids = Vec::new();
+        for _ in 0..2 {
+            let insert_data = InsertComment {
+                thread_id: thread.id,
+                user_id: user.id,
+                parent_id: None,
+                content: "TestContent".to_string(),
+            };
+            let returned_data = comments::insert_comment(&con, insert_data);
+            assert!(returned_data.is_ok());
+            comment_ids.push(returned_data.unwrap().id);
+        }
+        let update_data = UpdateComment {
+            id: comment_ids[1],
+            content: None,
+            hidden: Some(true),
+        };
+        assert!(comments::update_comment(&con, user.id.into(), update_data).is_ok());
+
+        // Stats, including hidden
+        let returned_data = get_user_stats(&con, user.id.into(), true);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.thread_count, 1);
+        assert_eq!(returned_data.comment_count, 2);
+        assert!(returned_data.last_active.is_some());
+
+        // Stats, excluding hidden
+        let returned_data = get_user_stats(&con, user.id.into(), false);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.thread_count, 1);
+        assert_eq!(returned_data.comment_count, 1);
+
+        // Activity, excluding hidden
+        let returned_data = get_user_activity(&con, user.id.into(), false, 0, 10);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().len(), 2);
+
+        // Activity, including hidden
+        let returned_data = get_user_activity(&con, user.id.into(), true, 0, 10);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().len(), 3);
+
+        // Nothing is visible in a hidden category
+        let update_data = UpdateCategory {
+            id: category.id,
+            title: None,
+            description: None,
+            hidden: Some(true),
+        };
+        assert!(categories::update_category(&con, update_data).is_ok());
+        let returned_data = get_user_activity(&con, user.id.into(), false, 0, 10);
+        assert!(returned_data.is_ok());
+        assert!(returned_data.unwrap().is_empty());
+        let returned_data = get_user_stats(&con, user.id.into(), false);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.thread_count, 0);
+        assert_eq!(returned_data.comment_count, 0);
+        assert_eq!(returned_data.last_active, None);
+    }
+}
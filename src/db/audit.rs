@@ -0,0 +1,22 @@
+use diesel::prelude::*;
+use failure::ResultExt;
+
+use super::DbConn;
+use crate::types::InsertAuditEntry;
+use crate::{IntErrorKind, IntResult};
+
+/// Records an administrative action in the audit log
+pub fn insert_audit_entry(con: &DbConn, entry: &InsertAuditEntry) -> IntResult<usize> {
+    use super::schema::audit_log::dsl;
+
+    trace!("Inserting audit entry: {:?}", entry);
+
+    diesel::insert_into(dsl::audit_log)
+        .values(entry)
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to insert audit entry ({}): {}", entry.action, e);
+            e.into()
+        })
+}
@@ -0,0 +1,302 @@
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
+use diesel::sql_types::Bool;
+use failure::ResultExt;
+
+use super::{in_visible_thread, max_rows, DbConn, MAX_BOOKMARK_LIMIT};
+use crate::types::{Bookmark, InsertBookmark};
+use crate::{IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// Inserts a new bookmark into the bookmark table
+///
+/// If the user has already bookmarked the same thread or comment, the note of
+/// the existing bookmark is updated instead.
+pub fn insert_bookmark(con: &DbConn, bookmark: impl Into<InsertBookmark>) -> IntResult<Bookmark> {
+    use super::schema::bookmarks::dsl;
+    let bookmark = bookmark.into();
+    let user_id = bookmark.user_id;
+
+    trace!("Inserting bookmark");
+
+    if bookmark.thread_id.is_none() && bookmark.comment_id.is_none() {
+        return Err(IntErrorKind::InvalidId.into());
+    }
+
+    let find_existing = || {
+        let query = dsl::bookmarks
+            .filter(dsl::user_id.eq(user_id))
+            .select(dsl::id)
+            .into_boxed();
+
+        match (bookmark.thread_id, bookmark.comment_id) {
+            (_, Some(comment_id)) => query.filter(dsl::comment_id.eq(comment_id)),
+            (thread_id, None) => query
+                .filter(dsl::thread_id.eq(thread_id.unwrap_or_default()))
+                .filter(dsl::comment_id.is_null()),
+        }.first::<u32>(con)
+    };
+    let update_note = |id| {
+        diesel::update(dsl::bookmarks)
+            .filter(dsl::id.eq(id))
+            .set(dsl::note.eq(&bookmark.note))
+            .execute(con)
+            .map(|_| id)
+    };
+
+    let existing = find_existing()
+        .optional()
+        .context(IntErrorKind::QueryError)?;
+
+    let id = match existing {
+        Some(id) => update_note(id),
+        None => match diesel::insert_into(dsl::bookmarks)
+            .values(&bookmark)
+            .execute(con)
+        {
+            // Another request bookmarked the same target in the meantime
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                find_existing().and_then(update_note)
+            }
+            result => result.and_then(|_| find_existing()),
+        },
+    }.context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!("Unable to insert bookmark: {}", e);
+        e
+    })?;
+
+    dsl::bookmarks
+        .filter(dsl::id.eq(id))
+        .first(con)
+        .optional()
+        .context(IntErrorKind::QueryError)?
+        .ok_or(IntErrorKind::ContentNotFound)
+        .map_err(|e| {
+            error!("Unable to get bookmark after insertion: {}", e);
+            e.into()
+        })
+}
+
+/// Gets a page of the bookmarks of a user, newest first
+///
+/// Unless `include_hidden` is set, bookmarks of hidden comments, and of
+/// threads and comments in hidden threads or categories, are left out.
+pub fn get_bookmarks(
+    con: &DbConn,
+    user_id: UserId,
+    include_hidden: bool,
+    offset: u32,
+    limit: u32,
+) -> IntResult<Vec<Bookmark>> {
+    use super::schema::bookmarks::{self, dsl};
+    use super::schema::comments;
+
+    trace!(
+        "Getting bookmarks of user ({}) [{}]",
+        user_id,
+        fmt_hidden!(include_hidden)
+    );
+
+    let mut query = dsl::bookmarks
+        .left_join(comments::table)
+        .filter(dsl::user_id.eq(*user_id))
+        .select(bookmarks::all_columns)
+        .order(dsl::id.desc())
+        .offset(i64::from(offset))
+        .limit(i64::from(limit).min(max_rows(&MAX_BOOKMARK_LIMIT)))
+        .into_boxed();
+
+    if !include_hidden {
+        // Bookmarks of comments have no thread of their own
+        query = query
+            .filter(comments::hidden.is_null().or(comments::hidden.eq(false)))
+            .filter(sql::<Bool>(&in_visible_thread(
+                "COALESCE(bookmarks.thread_id, comments.thread_id)",
+            )));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get bookmarks of user ({}): {}", user_id, e);
+            e.into()
+        })
+}
+
+/// Deletes a bookmark of a user from the bookmark table
+pub fn delete_bookmark(con: &DbConn, user_id: UserId, id: u32) -> IntResult<usize> {
+    use super::schema::bookmarks::dsl;
+
+    trace!("Deleting bookmark ({}) of user ({})", id, user_id);
+
+    let num_deleted = diesel::delete(dsl::bookmarks)
+        .filter(dsl::id.eq(id))
+        .filter(dsl::user_id.eq(*user_id))
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to delete bookmark ({}): {}", id, e);
+            e
+        })?;
+
+    if num_deleted == 0 {
+        Err(IntErrorKind::ContentNotFound)?
+    } else {
+        Ok(num_deleted)
+    }
+}
+
+/// Deletes all the bookmarks of a user from the bookmark table
+pub fn delete_user_bookmarks(con: &DbConn, user_id: UserId) -> IntResult<usize> {
+    use super::schema::bookmarks::dsl;
+
+    trace!("Deleting all bookmarks of user ({})", user_id);
+
+    diesel::delete(dsl::bookmarks)
+        .filter(dsl::user_id.eq(*user_id))
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to delete bookmarks of user ({}): {}", user_id, e);
+            e.into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{categories, comments, establish_connection, threads, users};
+    use crate::types::{
+        InsertCategory, InsertComment, InsertThread, InsertUser, UpdateCategory, UpdateThread,
+    };
+
+    #[test]
+    fn insert_get_and_delete() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // User
+        let insert_data = InsertUser {
+            id: 46,
+            username: "BookmarkUser".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let user = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Bookmark
+        let insert_data = InsertBookmark {
+            user_id: user.id,
+            thread_id: Some(thread.id),
+            comment_id: None,
+            note: Some("TestNote".to_string()),
+        };
+        let returned_data = insert_bookmark(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let bookmark = returned_data.unwrap();
+
+        // Bookmark again updates the note
+        let insert_data = InsertBookmark {
+            user_id: user.id,
+            thread_id: Some(thread.id),
+            comment_id: None,
+            note: Some("OtherNote".to_string()),
+        };
+        let returned_data = insert_bookmark(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.id, bookmark.id);
+        assert_eq!(returned_data.note, Some("OtherNote".to_string()));
+
+        // Hidden threads are filtered out
+        let update_data = UpdateThread {
+            id: thread.id,
+            title: None,
+            description: None,
+            hidden: Some(true),
+        };
+        assert!(threads::update_thread(&con, user.id.into(), update_data).is_ok());
+        let returned_data = get_bookmarks(&con, user.id.into(), false, 0, 10);
+        assert!(returned_data.is_ok());
+        assert!(returned_data.unwrap().is_empty());
+        let returned_data = get_bookmarks(&con, user.id.into(), true, 0, 10);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().len(), 1);
+
+        // Comments in hidden categories are filtered out
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let other_category = returned_data.unwrap();
+        let insert_data = InsertThread {
+            category_id: other_category.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let insert_data = InsertComment {
+            thread_id: returned_data.unwrap().id,
+            user_id: user.id,
+            parent_id: None,
+            content: "TestContent".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let insert_data = InsertBookmark {
+            user_id: user.id,
+            thread_id: None,
+            comment_id: Some(returned_data.unwrap().id),
+            note: None,
+        };
+        assert!(insert_bookmark(&con, insert_data).is_ok());
+        let returned_data = get_bookmarks(&con, user.id.into(), false, 0, 10);
+        assert_eq!(returned_data.unwrap().len(), 1);
+
+        let update_data = UpdateCategory {
+            id: other_category.id,
+            title: None,
+            description: None,
+            hidden: Some(true),
+        };
+        assert!(categories::update_category(&con, update_data).is_ok());
+        let returned_data = get_bookmarks(&con, user.id.into(), false, 0, 10);
+        assert!(returned_data.is_ok());
+        assert!(returned_data.unwrap().is_empty());
+        let returned_data = get_bookmarks(&con, user.id.into(), true, 0, 10);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().len(), 2);
+
+        // Delete
+        assert!(delete_bookmark(&con, user.id.into(), bookmark.id).is_ok());
+        assert!(delete_bookmark(&con, user.id.into(), bookmark.id).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use diesel::prelude::*;
 use failure::ResultExt;
 
-use super::{DbConn, MAX_CATEGORY_LIMIT};
+use super::{max_rows, DbConn, MAX_CATEGORY_LIMIT};
 use crate::types::{Category, InsertCategory, UpdateCategory};
 use crate::{IntErrorKind, IntResult};
 
@@ -66,10 +66,10 @@ pub fn get_all_categories(con: &DbConn, include_hidden: bool) -> IntResult<Vec<C
     trace!("Getting all categories, include hidden: {}", include_hidden);
 
     if include_hidden {
-        dsl::categories.limit(MAX_CATEGORY_LIMIT).get_results(con)
+        dsl::categories.limit(max_rows(&MAX_CATEGORY_LIMIT)).get_results(con)
     } else {
         dsl::categories
-            .limit(MAX_CATEGORY_LIMIT)
+            .limit(max_rows(&MAX_CATEGORY_LIMIT))
             .filter(dsl::hidden.eq(false))
             .get_results(con)
     }.context(IntErrorKind::QueryError)
@@ -112,6 +112,25 @@ pub fn update_category(con: &DbConn, category: impl Into<UpdateCategory>) -> Int
         })
 }
 
+/// Sets whether an existing category is a Q&A category
+pub fn set_category_qa(con: &DbConn, id: CategoryId, qa: bool) -> IntResult<Category> {
+    use super::schema::categories::dsl;
+
+    trace!("Setting Q&A of category ({}) to {}", id, qa);
+
+    let updated = diesel::update(dsl::categories)
+        .filter(dsl::id.eq(*id))
+        .set(dsl::qa.eq(qa))
+        .execute(con)
+        .context(IntErrorKind::QueryError)?;
+
+    if updated == 0 {
+        return Err(IntErrorKind::ContentNotFound.into());
+    }
+
+    get_category(con, id, true)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,6 +150,8 @@ mod tests {
             title: "TestTitle".to_string(),
             description: "TestDescription".to_string(),
             hidden: false,
+            qa: false,
+            parent_id: None,
         };
 
         // Insert
@@ -172,6 +193,8 @@ mod tests {
             title: "OtherTitle".to_string(),
             description: "OtherDescription".to_string(),
             hidden: true,
+            qa: false,
+            parent_id: None,
         };
 
         // Insert
@@ -221,4 +244,25 @@ mod tests {
         // Fail to get
         assert!(get_category(&con, returned_data.id.into(), false).is_err());
     }
+
+    #[test]
+    fn qa() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+
+        // Insert
+        let returned_data = insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert!(!returned_data.qa);
+
+        // Enable Q&A
+        let returned_data = set_category_qa(&con, returned_data.id.into(), true);
+        assert!(returned_data.is_ok());
+        assert!(returned_data.unwrap().qa);
+    }
 }
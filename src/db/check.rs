@@ -0,0 +1,244 @@
+//! Checks of invariants which the schema does not enforce, along with safe
+//! repairs of the violations
+use diesel::prelude::*;
+use diesel::sql_query;
+use failure::ResultExt;
+
+use super::DbConn;
+use crate::types::RowId;
+use crate::{IntError, IntErrorKind, IntResult};
+
+/// How serious a violation of an invariant is
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The data is inconsistent
+    Error,
+    /// The data is consistent, but likely not intended
+    Warning,
+}
+
+/// An invariant of the database
+pub struct Check {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub severity: Severity,
+    /// Selects the ids of the rows which violate the invariant, as `id`
+    find: &'static str,
+    /// Repairs the violations, if there is a safe way to do so
+    ///
+    /// Repairs which can not be undone, like hiding content which can not be
+    /// told apart from content hidden on purpose afterwards, are left to be
+    /// done by hand.
+    fix: Option<&'static str>,
+}
+
+pub const CHECKS: &[Check] = &[
+    Check {
+        name: "reply_in_other_thread",
+        description: "comments replying to a comment in another thread",
+        severity: Severity::Error,
+        find: "SELECT c.id FROM comments c JOIN comments p ON p.id = c.parent_id \
+               WHERE p.thread_id <> c.thread_id ORDER BY c.id",
+        fix: Some(
+            "UPDATE comments c JOIN comments p ON p.id = c.parent_id \
+             SET c.parent_id = NULL WHERE p.thread_id <> c.thread_id",
+        ),
+    },
+    Check {
+        name: "answer_in_other_thread",
+        description: "threads with an accepted answer from another thread",
+        severity: Severity::Error,
+        find: "SELECT t.id FROM threads t JOIN comments c ON c.id = t.answer_id \
+               WHERE c.thread_id <> t.id ORDER BY t.id",
+        fix: Some(
+            "UPDATE threads t JOIN comments c ON c.id = t.answer_id \
+             SET t.answer_id = NULL WHERE c.thread_id <> t.id",
+        ),
+    },
+    Check {
+        name: "visible_thread_in_hidden_category",
+        description: "visible threads in hidden categories",
+        severity: Severity::Error,
+        find: "SELECT t.id FROM threads t JOIN categories g ON g.id = t.category_id \
+               WHERE t.hidden = 0 AND g.hidden = 1 ORDER BY t.id",
+        fix: None,
+    },
+    Check {
+        name: "visible_comment_in_hidden_thread",
+        description: "visible comments in hidden threads or categories",
+        severity: Severity::Error,
+        find: "SELECT c.id FROM comments c JOIN threads t ON t.id = c.thread_id \
+               JOIN categories g ON g.id = t.category_id \
+               WHERE c.hidden = 0 AND (t.hidden = 1 OR g.hidden = 1) ORDER BY c.id",
+        fix: None,
+    },
+    Check {
+        name: "bookmark_without_target",
+        description: "bookmarks of neither or both a thread and a comment",
+        severity: Severity::Error,
+        find: "SELECT id FROM bookmarks \
+               WHERE (thread_id IS NULL) = (comment_id IS NULL) ORDER BY id",
+        fix: Some("DELETE FROM bookmarks WHERE (thread_id IS NULL) = (comment_id IS NULL)"),
+    },
+    Check {
+        name: "unreferenced_user",
+        description: "users without any content, bookmarks, subscriptions or read markers",
+        severity: Severity::Warning,
+        find: "SELECT u.id FROM users u WHERE \
+               NOT EXISTS (SELECT 1 FROM threads WHERE user_id = u.id) \
+               AND NOT EXISTS (SELECT 1 FROM comments WHERE user_id = u.id) \
+               AND NOT EXISTS (SELECT 1 FROM bookmarks WHERE user_id = u.id) \
+               AND NOT EXISTS (SELECT 1 FROM thread_subscriptions WHERE user_id = u.id) \
+               AND NOT EXISTS (SELECT 1 FROM category_subscriptions WHERE user_id = u.id) \
+               AND NOT EXISTS (SELECT 1 FROM thread_reads WHERE user_id = u.id) \
+               ORDER BY u.id",
+        fix: None,
+    },
+];
+
+/// The violations of a check
+#[derive(Serialize, Debug)]
+pub struct Violations {
+    pub check: &'static str,
+    pub description: &'static str,
+    pub severity: Severity,
+    /// The ids of the violating rows
+    pub ids: Vec<u32>,
+    /// The number of repaired rows, if the violations were repaired
+    pub fixed: Option<usize>,
+}
+
+impl Violations {
+    /// Whether the check found errors which were not repaired
+    pub fn failed(&self) -> bool {
+        self.severity == Severity::Error && !self.ids.is_empty() && self.fixed.is_none()
+    }
+}
+
+/// Runs all the checks, and repairs the violations which can be repaired if
+/// `fix` is set
+///
+/// Everything happens in a single transaction, so that the repairs are either
+/// all applied or not at all.
+pub fn check(con: &DbConn, fix: bool) -> IntResult<Vec<Violations>> {
+    trace!("Checking database, fix: {}", fix);
+
+    con.transaction::<_, IntError, _>(|| {
+        CHECKS
+            .iter()
+            .map(|check| -> IntResult<Violations> {
+                let ids = sql_query(check.find)
+                    .load::<RowId>(con)
+                    .context(IntErrorKind::QueryError)?
+                    .into_iter()
+                    .map(|row| row.id)
+                    .collect::<Vec<_>>();
+
+                let fixed = match check.fix {
+                    Some(query) if fix && !ids.is_empty() => Some(
+                        sql_query(query)
+                            .execute(con)
+                            .context(IntErrorKind::QueryError)?,
+                    ),
+                    _ => None,
+                };
+
+                Ok(Violations {
+                    check: check.name,
+                    description: check.description,
+                    severity: check.severity,
+                    ids,
+                    fixed,
+                })
+            }).collect()
+    }).map_err(|e| {
+        error!("Unable to check database: {}", e);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{categories, comments, establish_connection, threads, users};
+    use crate::types::{InsertCategory, InsertComment, InsertThread, InsertUser};
+
+    #[test]
+    fn reply_in_other_thread() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // Everything is rolled back, so that the repairs do not touch the rows
+        // of other tests
+        con.begin_test_transaction().unwrap();
+
+        // User
+        let insert_data = InsertUser {
+            id: 50,
+            username: "TestUser50".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let user = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Threads
+        let mut thread_ids = Vec::new();
+        for _ in 0..2 {
+            let insert_data = InsertThread {
+                category_id: category.id,
+                user_id: user.id,
+                title: "TestTitle".to_string(),
+                description: "TestDescription".to_string(),
+            };
+            let returned_data = threads::insert_thread(&con, insert_data);
+            assert!(returned_data.is_ok());
+            thread_ids.push(returned_data.unwrap().id);
+        }
+
+        // A reply to a comment in the other thread
+        let insert_data = InsertComment {
+            thread_id: thread_ids[0],
+            user_id: user.id,
+            parent_id: None,
+            content: "TestContent".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let parent = returned_data.unwrap();
+        let insert_data = InsertComment {
+            thread_id: thread_ids[1],
+            user_id: user.id,
+            parent_id: Some(parent.id),
+            content: "TestContent".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let reply = returned_data.unwrap();
+
+        // Found
+        let returned_data = check(&con, false);
+        assert!(returned_data.is_ok());
+        let violations = returned_data.unwrap();
+        let violation = violations
+            .iter()
+            .find(|v| v.check == "reply_in_other_thread")
+            .unwrap();
+        assert!(violation.ids.contains(&reply.id));
+        assert!(violation.fixed.is_none());
+
+        // Fixed
+        assert!(check(&con, true).is_ok());
+        let returned_data = comments::get_comment(&con, reply.id.into(), true);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().parent_id, None);
+    }
+}
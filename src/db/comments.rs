@@ -1,7 +1,7 @@
 use diesel::prelude::*;
 use failure::ResultExt;
 
-use super::{DbConn, MAX_COMMENT_LIMIT};
+use super::{max_rows, DbConn, MAX_COMMENT_LIMIT};
 use crate::types::{Comment, InsertComment, UpdateComment};
 use crate::{IntErrorKind, IntResult};
 
@@ -66,10 +66,10 @@ pub fn get_all_comments(con: &DbConn, include_hidden: bool) -> IntResult<Vec<Com
     trace!("Getting all comments, include hidden: {}", include_hidden);
 
     if include_hidden {
-        dsl::comments.limit(MAX_COMMENT_LIMIT).get_results(con)
+        dsl::comments.limit(max_rows(&MAX_COMMENT_LIMIT)).get_results(con)
     } else {
         dsl::comments
-            .limit(MAX_COMMENT_LIMIT)
+            .limit(max_rows(&MAX_COMMENT_LIMIT))
             .filter(dsl::hidden.eq(false))
             .get_results(con)
     }.context(IntErrorKind::QueryError)
@@ -80,31 +80,47 @@ pub fn get_all_comments(con: &DbConn, include_hidden: bool) -> IntResult<Vec<Com
 }
 
 /// Gets all the comments in a thread from the comment table
+///
+/// If the thread has an accepted answer, the answer is pinned as the first
+/// comment.
 pub fn get_comments_in_thread(
     con: &DbConn,
     id: ThreadId,
     include_hidden: bool,
 ) -> IntResult<Vec<Comment>> {
     use super::schema::comments::dsl;
+    use super::schema::threads;
 
     trace!("Getting comments in thread ({})", id);
 
-    if include_hidden {
-        dsl::comments
-            .limit(MAX_COMMENT_LIMIT)
-            .filter(dsl::thread_id.eq(*id))
-            .get_results(con)
-    } else {
-        dsl::comments
-            .limit(MAX_COMMENT_LIMIT)
-            .filter(dsl::thread_id.eq(*id))
-            .filter(dsl::hidden.eq(false))
-            .get_results(con)
-    }.context(IntErrorKind::QueryError)
-    .map_err(|e| {
-        error!("Unable to get comments in thread ({}): {}", id, e);
-        e.into()
-    })
+    let answer_id = threads::table
+        .filter(threads::id.eq(*id))
+        .select(threads::answer_id)
+        .first::<Option<u32>>(con)
+        .optional()
+        .context(IntErrorKind::QueryError)?
+        .and_then(|answer_id| answer_id);
+
+    let mut query = dsl::comments
+        .limit(max_rows(&MAX_COMMENT_LIMIT))
+        .filter(dsl::thread_id.eq(*id))
+        .into_boxed();
+
+    if !include_hidden {
+        query = query.filter(dsl::hidden.eq(false));
+    }
+
+    if let Some(answer_id) = answer_id {
+        query = query.order((dsl::id.eq(answer_id).desc(), dsl::id.asc()));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get comments in thread ({}): {}", id, e);
+            e.into()
+        })
 }
 
 /// Clears the comment table
@@ -163,7 +179,7 @@ mod tests {
         // User
         let insert_data = InsertUser {
             id: 20,
-            username: "TestUser".to_string(),
+            username: "TestUser20".to_string(),
         };
         let returned_data = users::insert_user(&con, insert_data);
         assert!(returned_data.is_ok());
@@ -259,7 +275,7 @@ mod tests {
         // User
         let insert_data = InsertUser {
             id: 21,
-            username: "TestUser".to_string(),
+            username: "TestUser21".to_string(),
         };
         let returned_data = users::insert_user(&con, insert_data);
         assert!(returned_data.is_ok());
@@ -333,7 +349,7 @@ mod tests {
         // User
         let insert_data = InsertUser {
             id: 32,
-            username: "TestUser".to_string(),
+            username: "TestUser32".to_string(),
         };
         let returned_data = users::insert_user(&con, insert_data);
         assert!(returned_data.is_ok());
@@ -0,0 +1,352 @@
+//! Batched reads of whole tables, or everything belonging to a user, for
+//! exporting data out of the database
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use failure::ResultExt;
+
+use super::DbConn;
+use crate::types::{Category, Comment, Thread, User, UserRecords};
+use crate::{IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// Gets a batch of the threads of a user, hidden ones included, which have an
+/// id above `after`, in order of id
+pub fn get_user_threads(
+    con: &DbConn,
+    user_id: UserId,
+    after: u32,
+    limit: i64,
+) -> IntResult<Vec<Thread>> {
+    use super::schema::threads::dsl;
+
+    trace!("Getting threads of user ({}) after ({})", user_id, after);
+
+    dsl::threads
+        .filter(dsl::user_id.eq(*user_id))
+        .filter(dsl::id.gt(after))
+        .order(dsl::id.asc())
+        .limit(limit)
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get threads of user ({}): {}", user_id, e);
+            e.into()
+        })
+}
+
+/// Gets a batch of the comments of a user, hidden ones included, which have an
+/// id above `after`, in order of id
+pub fn get_user_comments(
+    con: &DbConn,
+    user_id: UserId,
+    after: u32,
+    limit: i64,
+) -> IntResult<Vec<Comment>> {
+    use super::schema::comments::dsl;
+
+    trace!("Getting comments of user ({}) after ({})", user_id, after);
+
+    dsl::comments
+        .filter(dsl::user_id.eq(*user_id))
+        .filter(dsl::id.gt(after))
+        .order(dsl::id.asc())
+        .limit(limit)
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get comments of user ({}): {}", user_id, e);
+            e.into()
+        })
+}
+
+/// Gets the records of a user which are neither threads nor comments
+pub fn get_user_records(con: &DbConn, user_id: UserId) -> IntResult<UserRecords> {
+    use super::schema::{
+        audit_log, bookmarks, category_subscriptions, mentions, notifications, thread_reads,
+        thread_subscriptions,
+    };
+
+    trace!("Getting records of user ({})", user_id);
+
+    let records = UserRecords {
+        username_history: super::users::get_username_history(con, user_id)?,
+        thread_subscriptions: thread_subscriptions::table
+            .filter(thread_subscriptions::user_id.eq(*user_id))
+            .load(con)?,
+        category_subscriptions: category_subscriptions::table
+            .filter(category_subscriptions::user_id.eq(*user_id))
+            .load(con)?,
+        thread_reads: thread_reads::table
+            .filter(thread_reads::user_id.eq(*user_id))
+            .load(con)?,
+        notifications: notifications::table
+            .filter(notifications::user_id.eq(*user_id))
+            .order(notifications::id.asc())
+            .load(con)?,
+        mentions: mentions::table
+            .filter(mentions::user_id.eq(*user_id))
+            .load(con)?,
+        bookmarks: bookmarks::table
+            .filter(bookmarks::user_id.eq(*user_id))
+            .order(bookmarks::id.asc())
+            .load(con)?,
+        audit_log: audit_log::table
+            .filter(
+                audit_log::user_id
+                    .eq(*user_id)
+                    .or(audit_log::actor_id.eq(*user_id)),
+            ).order(audit_log::id.asc())
+            .load(con)?,
+    };
+
+    Ok(records)
+}
+
+/// Runs `f` in a read only transaction which sees the database as it was when
+/// the transaction started
+///
+/// Exports read in many batches, and without the transaction rows which are
+/// written between the batches could refer to rows missing from the export.
+pub fn in_snapshot<T>(con: &DbConn, f: impl FnOnce() -> IntResult<T>) -> IntResult<T> {
+    trace!("Starting consistent snapshot");
+
+    con.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .and_then(|_| con.batch_execute("START TRANSACTION WITH CONSISTENT SNAPSHOT, READ ONLY"))
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to start consistent snapshot: {}", e);
+            e
+        })?;
+
+    let result = f();
+
+    let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    con.batch_execute(end)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to end consistent snapshot: {}", e);
+            e
+        })?;
+
+    result
+}
+
+/// Gets a batch of the users which have an id above `after`, or from the
+/// first one if it is `None`, in order of id
+pub fn get_users_after(
+    con: &DbConn,
+    after: Option<u32>,
+    limit: i64,
+) -> IntResult<Vec<User>> {
+    use super::schema::users::dsl;
+
+    trace!("Getting users after ({:?})", after);
+
+    let mut query = dsl::users.order(dsl::id.asc()).limit(limit).into_boxed();
+    if let Some(after) = after {
+        query = query.filter(dsl::id.gt(after));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get users after ({:?}): {}", after, e);
+            e.into()
+        })
+}
+
+/// Gets a batch of the categories, hidden ones included, which have an id
+/// above `after`, or from the first one if it is `None`, in order of id
+pub fn get_categories_after(
+    con: &DbConn,
+    after: Option<u32>,
+    limit: i64,
+) -> IntResult<Vec<Category>> {
+    use super::schema::categories::dsl;
+
+    trace!("Getting categories after ({:?})", after);
+
+    let mut query = dsl::categories.order(dsl::id.asc()).limit(limit).into_boxed();
+    if let Some(after) = after {
+        query = query.filter(dsl::id.gt(after));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get categories after ({:?}): {}", after, e);
+            e.into()
+        })
+}
+
+/// Gets a batch of the threads, hidden ones included, which have an id above
+/// `after`, or from the first one if it is `None`, in order of id
+pub fn get_threads_after(
+    con: &DbConn,
+    after: Option<u32>,
+    limit: i64,
+) -> IntResult<Vec<Thread>> {
+    use super::schema::threads::dsl;
+
+    trace!("Getting threads after ({:?})", after);
+
+    let mut query = dsl::threads.order(dsl::id.asc()).limit(limit).into_boxed();
+    if let Some(after) = after {
+        query = query.filter(dsl::id.gt(after));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get threads after ({:?}): {}", after, e);
+            e.into()
+        })
+}
+
+/// Gets a batch of the comments, hidden ones included, which have an id above
+/// `after`, or from the first one if it is `None`, in order of id
+///
+/// As a comment is always inserted after its parent, the parent of a comment
+/// comes before the comment.
+pub fn get_comments_after(
+    con: &DbConn,
+    after: Option<u32>,
+    limit: i64,
+) -> IntResult<Vec<Comment>> {
+    use super::schema::comments::dsl;
+
+    trace!("Getting comments after ({:?})", after);
+
+    let mut query = dsl::comments.order(dsl::id.asc()).limit(limit).into_boxed();
+    if let Some(after) = after {
+        query = query.filter(dsl::id.gt(after));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get comments after ({:?}): {}", after, e);
+            e.into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{audit, categories, comments, establish_connection, threads, users};
+    use crate::types::{
+        InsertAuditEntry, InsertCategory, InsertComment, InsertThread, InsertUser, UpdateComment,
+    };
+
+    #[test]
+    fn user_content_in_batches() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // User
+        let insert_data = InsertUser {
+            id: 47,
+            username: "ExportUser".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let user = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Comments, one of them hidden
+        let mut comment_ids = Vec::new();
+        for _ in 0..3 {
+            let insert_data = InsertComment {
+                thread_id: thread.id,
+                user_id: user.id,
+                parent_id: None,
+                content: "TestContent".to_string(),
+            };
+            let returned_data = comments::insert_comment(&con, insert_data);
+            assert!(returned_data.is_ok());
+            comment_ids.push(returned_data.unwrap().id);
+        }
+        let update_data = UpdateComment {
+            id: comment_ids[0],
+            content: None,
+            hidden: Some(true),
+        };
+        assert!(comments::update_comment(&con, user.id.into(), update_data).is_ok());
+
+        // Threads
+        let returned_data = get_user_threads(&con, user.id.into(), 0, 10);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap()[0].id, thread.id);
+
+        // Comments in batches of two
+        let returned_data = get_user_comments(&con, user.id.into(), 0, 2);
+        assert!(returned_data.is_ok());
+        let first = returned_data.unwrap();
+        assert_eq!(first.len(), 2);
+        let returned_data = get_user_comments(&con, user.id.into(), first[1].id, 2);
+        assert!(returned_data.is_ok());
+        let second = returned_data.unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].id, comment_ids[2]);
+
+        // Audit entries of actions on the user, by the user and on others
+        for &(user_id, actor_id) in &[(user.id, None), (1, Some(user.id)), (1, None)] {
+            let insert_data = InsertAuditEntry {
+                action: "test".to_string(),
+                user_id,
+                actor_id,
+                details: String::new(),
+            };
+            assert!(audit::insert_audit_entry(&con, &insert_data).is_ok());
+        }
+
+        // Records
+        let returned_data = get_user_records(&con, user.id.into());
+        assert!(returned_data.is_ok());
+        let records = returned_data.unwrap();
+        assert_eq!(records.thread_subscriptions.len(), 0);
+        assert_eq!(records.audit_log.len(), 2);
+        assert_eq!(records.audit_log[0].user_id, user.id);
+        assert_eq!(records.audit_log[1].actor_id, Some(user.id));
+    }
+
+    #[test]
+    fn tables_from_first_id() {
+        use crate::db::schema::users::dsl;
+
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // The first batch starts at the lowest id, which may be the
+        // placeholder user 0
+        let first_id = dsl::users
+            .select(diesel::dsl::min(dsl::id))
+            .first::<Option<u32>>(&con)
+            .unwrap();
+        let returned_data = get_users_after(&con, None, 1);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().first().map(|user| user.id), first_id);
+    }
+}
@@ -0,0 +1,128 @@
+//! Batched inserts of whole rows, which keep the ids and timestamps of the
+//! rows, for importing data into the database
+use diesel::prelude::*;
+use failure::ResultExt;
+
+use super::DbConn;
+use crate::types::{Category, Comment, Thread, User};
+use crate::{IntErrorKind, IntResult};
+
+/// Inserts users into the user table
+pub fn insert_users(con: &DbConn, users: &[User]) -> IntResult<usize> {
+    use super::schema::users::dsl;
+
+    trace!("Inserting {} users", users.len());
+
+    diesel::insert_into(dsl::users)
+        .values(users)
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to insert users: {}", e);
+            e.into()
+        })
+}
+
+/// Inserts categories into the category table
+pub fn insert_categories(con: &DbConn, categories: &[Category]) -> IntResult<usize> {
+    use super::schema::categories::dsl;
+
+    trace!("Inserting {} categories", categories.len());
+
+    diesel::insert_into(dsl::categories)
+        .values(categories)
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to insert categories: {}", e);
+            e.into()
+        })
+}
+
+/// Inserts threads into the thread table
+///
+/// The accepted answers of the threads have to be inserted without
+/// `answer_id`, and be set with `set_answers` once the comments are inserted.
+pub fn insert_threads(con: &DbConn, threads: &[Thread]) -> IntResult<usize> {
+    use super::schema::threads::dsl;
+
+    trace!("Inserting {} threads", threads.len());
+
+    diesel::insert_into(dsl::threads)
+        .values(threads)
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to insert threads: {}", e);
+            e.into()
+        })
+}
+
+/// Inserts comments into the comment table
+///
+/// The parent of a comment has to be inserted before the comment, either in
+/// an earlier batch or earlier in the same batch.
+pub fn insert_comments(con: &DbConn, comments: &[Comment]) -> IntResult<usize> {
+    use super::schema::comments::dsl;
+
+    trace!("Inserting {} comments", comments.len());
+
+    diesel::insert_into(dsl::comments)
+        .values(comments)
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to insert comments: {}", e);
+            e.into()
+        })
+}
+
+/// Sets the accepted answers of threads, given as pairs of thread and comment
+/// ids
+pub fn set_answers(con: &DbConn, answers: &[(u32, u32)]) -> IntResult<usize> {
+    use super::schema::threads::dsl;
+
+    trace!("Setting {} answers", answers.len());
+
+    let mut updated = 0;
+    for &(thread_id, comment_id) in answers {
+        updated += diesel::update(dsl::threads)
+            .filter(dsl::id.eq(thread_id))
+            .set(dsl::answer_id.eq(comment_id))
+            .execute(con)
+            .context(IntErrorKind::QueryError)
+            .map_err(|e| {
+                error!("Unable to set answer of thread ({}): {}", thread_id, e);
+                e
+            })?;
+    }
+
+    Ok(updated)
+}
+
+/// Gets the highest ids of the users, categories, threads and comments, which
+/// are zero for empty tables
+pub fn get_max_ids(con: &DbConn) -> IntResult<(u32, u32, u32, u32)> {
+    use super::schema::{categories, comments, threads, users};
+    use diesel::dsl::max;
+
+    trace!("Getting max ids");
+
+    let user_id = users::table.select(max(users::id)).first::<Option<u32>>(con)?;
+    let category_id = categories::table
+        .select(max(categories::id))
+        .first::<Option<u32>>(con)?;
+    let thread_id = threads::table
+        .select(max(threads::id))
+        .first::<Option<u32>>(con)?;
+    let comment_id = comments::table
+        .select(max(comments::id))
+        .first::<Option<u32>>(con)?;
+
+    Ok((
+        user_id.unwrap_or(0),
+        category_id.unwrap_or(0),
+        thread_id.unwrap_or(0),
+        comment_id.unwrap_or(0),
+    ))
+}
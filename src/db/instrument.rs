@@ -0,0 +1,260 @@
+//! Timing of the statements run on database connections, logging the ones
+//! which are slower than a configurable threshold
+use diesel::connection::{AnsiTransactionManager, Connection, SimpleConnection};
+use diesel::deserialize::{Queryable, QueryableByName};
+use diesel::mysql::{Mysql, MysqlConnection};
+use diesel::query_builder::{AsQuery, AstPass, QueryFragment, QueryId};
+use diesel::result::{ConnectionResult, QueryResult};
+use diesel::sql_types::{HasSqlType, Text};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config::DatabaseConfig;
+
+/// The number of milliseconds above which statements are logged, or 0 to
+/// log none
+static SLOW_QUERY_MS: AtomicUsize = AtomicUsize::new(500);
+/// Whether to log the query plan of slow statements
+static EXPLAIN_SLOW_QUERIES: AtomicBool = AtomicBool::new(false);
+
+// Statistics of all the statements since startup
+static STATEMENTS: AtomicUsize = AtomicUsize::new(0);
+static SLOW_STATEMENTS: AtomicUsize = AtomicUsize::new(0);
+static STATEMENT_MICROS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the threshold of slow statements and whether to explain them
+pub fn set_slow_query_log(config: &DatabaseConfig) {
+    SLOW_QUERY_MS.store(config.slow_query_ms as usize, Ordering::Relaxed);
+    EXPLAIN_SLOW_QUERIES.store(config.explain_slow_queries, Ordering::Relaxed);
+}
+
+/// The number of statements run, the number of slow statements and the total
+/// time spent running statements
+pub fn statistics() -> (usize, usize, Duration) {
+    let micros = STATEMENT_MICROS.load(Ordering::Relaxed) as u64;
+    (
+        STATEMENTS.load(Ordering::Relaxed),
+        SLOW_STATEMENTS.load(Ordering::Relaxed),
+        Duration::from_micros(micros),
+    )
+}
+
+/// A MySQL connection which times every statement
+///
+/// Statements which take longer than the threshold are logged along with the
+/// `db` function running them, their parameters and the number of rows.
+/// String parameters are logged as their length only, as short titles and
+/// comments are as private as long ones. The statements of `batch_execute`,
+/// which are used for transactions and migrations, are not timed.
+pub struct InstrumentedConnection {
+    inner: MysqlConnection,
+}
+
+/// The plan of a statement, as given by `EXPLAIN FORMAT=JSON`
+#[derive(QueryableByName)]
+struct QueryPlan {
+    #[sql_type = "Text"]
+    #[column_name = "EXPLAIN"]
+    plan: String,
+}
+
+/// Wraps a statement in `EXPLAIN FORMAT=JSON`, keeping its parameters
+struct Explain<'a, T: 'a>(&'a T);
+
+impl<'a, T: QueryFragment<Mysql>> QueryFragment<Mysql> for Explain<'a, T> {
+    fn walk_ast(&self, mut out: AstPass<Mysql>) -> QueryResult<()> {
+        out.push_sql("EXPLAIN FORMAT=JSON ");
+        self.0.walk_ast(out.reborrow())
+    }
+}
+
+impl<'a, T> QueryId for Explain<'a, T> {
+    type QueryId = ();
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+/// Replaces the string literals of logged parameters with their length
+fn redact_strings(params: &str) -> String {
+    let mut out = String::with_capacity(params.len());
+    let mut chars = params.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            out.push(c);
+            continue;
+        }
+
+        let mut literal = String::new();
+        let mut escaped = false;
+        for c in &mut chars {
+            if c == '"' && !escaped {
+                break;
+            }
+            escaped = c == '\\' && !escaped;
+            literal.push(c);
+        }
+        out.push_str(&format!("<{} chars>", literal.chars().count()));
+    }
+    out
+}
+
+/// The innermost function of the `db` module on the stack, which is the one
+/// running the current statement
+fn calling_function() -> String {
+    let own_module = module_path!();
+    let db_module = own_module.trim_end_matches("instrument");
+    let mut caller = None;
+
+    backtrace::trace(|frame| {
+        backtrace::resolve(frame.ip(), |symbol| {
+            if caller.is_some() {
+                return;
+            }
+            if let Some(name) = symbol.name() {
+                let name = format!("{:#}", name);
+                if name.starts_with(db_module) && !name.starts_with(own_module) {
+                    caller = Some(name.trim_end_matches("::{{closure}}").to_string());
+                }
+            }
+        });
+        caller.is_none()
+    });
+    caller.unwrap_or_else(|| "an unknown function".to_string())
+}
+
+impl InstrumentedConnection {
+    /// Runs a statement, logging it if it is slow
+    fn timed<Q, R>(
+        &self,
+        query: &Q,
+        run: impl FnOnce(&MysqlConnection) -> QueryResult<R>,
+        rows: impl FnOnce(&R) -> usize,
+    ) -> QueryResult<R>
+    where
+        Q: QueryFragment<Mysql> + QueryId,
+    {
+        let started = Instant::now();
+        let result = run(&self.inner);
+        let elapsed = started.elapsed();
+
+        let micros = elapsed.as_secs() as usize * 1_000_000 + elapsed.subsec_micros() as usize;
+        STATEMENTS.fetch_add(1, Ordering::Relaxed);
+        STATEMENT_MICROS.fetch_add(micros, Ordering::Relaxed);
+
+        let threshold = SLOW_QUERY_MS.load(Ordering::Relaxed);
+        if threshold > 0 && micros >= threshold * 1000 {
+            SLOW_STATEMENTS.fetch_add(1, Ordering::Relaxed);
+            self.log_slow(query, micros / 1000, result.as_ref().map(rows).ok());
+        } else {
+            trace!("Statement took {} µs", micros);
+        }
+        result
+    }
+
+    fn log_slow<Q>(&self, query: &Q, millis: usize, rows: Option<usize>)
+    where
+        Q: QueryFragment<Mysql> + QueryId,
+    {
+        let statement = diesel::debug_query::<Mysql, _>(query).to_string();
+        let mut parts = statement.splitn(2, " -- binds: ");
+        let sql = parts.next().unwrap_or("");
+        let params = redact_strings(parts.next().unwrap_or("[]"));
+        let rows = rows.map_or("failed".to_string(), |rows| format!("{} rows", rows));
+
+        warn!(
+            "Slow statement in {} took {} ms ({}): {} -- params: {}",
+            calling_function(),
+            millis,
+            rows,
+            sql,
+            params
+        );
+
+        if EXPLAIN_SLOW_QUERIES.load(Ordering::Relaxed) {
+            match self.inner.query_by_name::<_, QueryPlan>(&Explain(query)) {
+                Ok(plans) => {
+                    for plan in plans {
+                        warn!("Plan of slow statement: {}", plan.plan);
+                    }
+                }
+                Err(e) => debug!("Unable to explain slow statement: {}", e),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for InstrumentedConnection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InstrumentedConnection").finish()
+    }
+}
+
+impl SimpleConnection for InstrumentedConnection {
+    fn batch_execute(&self, query: &str) -> QueryResult<()> {
+        self.inner.batch_execute(query)
+    }
+}
+
+impl Connection for InstrumentedConnection {
+    type Backend = Mysql;
+    type TransactionManager = AnsiTransactionManager;
+
+    fn establish(database_url: &str) -> ConnectionResult<Self> {
+        MysqlConnection::establish(database_url).map(|inner| InstrumentedConnection { inner })
+    }
+
+    fn execute(&self, query: &str) -> QueryResult<usize> {
+        self.timed(
+            &diesel::sql_query(query),
+            |con| con.execute(query),
+            |&rows| rows,
+        )
+    }
+
+    fn query_by_index<T, U>(&self, source: T) -> QueryResult<Vec<U>>
+    where
+        T: AsQuery,
+        T::Query: QueryFragment<Mysql> + QueryId,
+        Mysql: HasSqlType<T::SqlType>,
+        U: Queryable<T::SqlType, Mysql>,
+    {
+        let query = source.as_query();
+        self.timed(&query, |con| con.query_by_index(&query), Vec::len)
+    }
+
+    fn query_by_name<T, U>(&self, source: &T) -> QueryResult<Vec<U>>
+    where
+        T: QueryFragment<Mysql> + QueryId,
+        U: QueryableByName<Mysql>,
+    {
+        self.timed(source, |con| con.query_by_name(source), Vec::len)
+    }
+
+    fn execute_returning_count<T>(&self, source: &T) -> QueryResult<usize>
+    where
+        T: QueryFragment<Mysql> + QueryId,
+    {
+        self.timed(
+            source,
+            |con| con.execute_returning_count(source),
+            |&rows| rows,
+        )
+    }
+
+    fn transaction_manager(&self) -> &AnsiTransactionManager {
+        self.inner.transaction_manager()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_params() {
+        let params = "[5, \"short \\\" title\", \"Hi\", None]";
+
+        assert_eq!(redact_strings(params), "[5, <14 chars>, <2 chars>, None]");
+    }
+}
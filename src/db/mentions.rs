@@ -0,0 +1,192 @@
+use diesel::prelude::*;
+use failure::ResultExt;
+
+use super::{max_rows, DbConn, MAX_COMMENT_LIMIT};
+use crate::types::{Comment, InsertMention, InsertNotification};
+use crate::{IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// Stores the mentions of the given usernames in a comment and adds an entry
+/// to the mentions inbox of each mentioned user
+///
+/// Usernames which do not belong to any user are ignored, as is the author of
+/// the comment. Users which have already been mentioned in the comment are
+/// skipped, so editing a comment only notifies the newly mentioned users.
+/// Returns the ids of the newly mentioned users.
+pub fn add_mentions(con: &DbConn, comment: &Comment, usernames: &[String]) -> IntResult<Vec<u32>> {
+    use super::schema::mentions::dsl;
+    use super::schema::{notifications, users};
+
+    if usernames.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    trace!("Adding mentions {:?} to comment ({})", usernames, comment.id);
+
+    let already_mentioned = dsl::mentions
+        .filter(dsl::comment_id.eq(comment.id))
+        .select(dsl::user_id)
+        .get_results::<u32>(con)
+        .context(IntErrorKind::QueryError)?;
+
+    let user_ids = users::table
+        .filter(users::username.eq_any(usernames))
+        .filter(users::id.ne(comment.user_id))
+        .select(users::id)
+        .get_results::<u32>(con)
+        .context(IntErrorKind::QueryError)?
+        .into_iter()
+        .filter(|id| !already_mentioned.contains(id))
+        .collect::<Vec<u32>>();
+
+    if user_ids.is_empty() {
+        return Ok(user_ids);
+    }
+
+    let mentions = user_ids
+        .iter()
+        .map(|&user_id| InsertMention {
+            comment_id: comment.id,
+            user_id,
+        }).collect::<Vec<_>>();
+
+    let inbox = user_ids
+        .iter()
+        .map(|&user_id| InsertNotification {
+            user_id,
+            thread_id: comment.thread_id,
+            comment_id: Some(comment.id),
+            mention: true,
+        }).collect::<Vec<_>>();
+
+    diesel::insert_into(dsl::mentions)
+        .values(&mentions)
+        .execute(con)
+        .and_then(|_| {
+            diesel::insert_into(notifications::table)
+                .values(&inbox)
+                .execute(con)
+        }).context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to add mentions to comment ({}): {}", comment.id, e);
+            e
+        })?;
+
+    Ok(user_ids)
+}
+
+/// Gets a page of the comments where a user was mentioned, newest first
+pub fn get_mentioned_comments(
+    con: &DbConn,
+    user_id: UserId,
+    include_hidden: bool,
+    offset: u32,
+    limit: u32,
+) -> IntResult<Vec<Comment>> {
+    use super::schema::comments;
+    use super::schema::mentions::dsl;
+
+    trace!(
+        "Getting comments mentioning user ({}) [{}]",
+        user_id,
+        fmt_hidden!(include_hidden)
+    );
+
+    let mut query = comments::table
+        .inner_join(dsl::mentions)
+        .filter(dsl::user_id.eq(*user_id))
+        .select(comments::all_columns)
+        .order(comments::id.desc())
+        .offset(i64::from(offset))
+        .limit(i64::from(limit).min(max_rows(&MAX_COMMENT_LIMIT)))
+        .into_boxed();
+
+    if !include_hidden {
+        query = query.filter(comments::hidden.eq(false));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get comments mentioning user ({}): {}", user_id, e);
+            e.into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{categories, comments, establish_connection, threads, users};
+    use crate::types::{InsertCategory, InsertComment, InsertThread, InsertUser};
+
+    #[test]
+    fn mention_once() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // Users
+        let insert_data = InsertUser {
+            id: 42,
+            username: "MentionAuthor".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let author = returned_data.unwrap();
+
+        let insert_data = InsertUser {
+            id: 43,
+            username: "MentionTarget".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let target = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: author.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Comment
+        let insert_data = InsertComment {
+            thread_id: thread.id,
+            user_id: author.id,
+            parent_id: None,
+            content: "Hello @mentiontarget".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let comment = returned_data.unwrap();
+
+        // Mention
+        let usernames = vec!["mentiontarget".to_string(), "MentionAuthor".to_string()];
+        let returned_data = add_mentions(&con, &comment, &usernames);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), vec![target.id]);
+
+        // Mention again
+        let returned_data = add_mentions(&con, &comment, &usernames);
+        assert!(returned_data.is_ok());
+        assert!(returned_data.unwrap().is_empty());
+
+        // Get
+        let returned_data = get_mentioned_comments(&con, target.id.into(), false, 0, 10);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), vec![comment]);
+    }
+}
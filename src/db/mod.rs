@@ -1,37 +1,139 @@
-use diesel::mysql::MysqlConnection;
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
-use diesel::r2d2::{self, ConnectionManager};
+use diesel::r2d2::{self, ConnectionManager, ManageConnection};
 
 use failure::ResultExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
+use crate::config::{DatabaseConfig, Limits};
 use crate::{IntErrorKind, IntResult};
 
+pub mod activity;
+pub mod audit;
+pub mod bookmarks;
 pub mod categories;
+pub mod check;
 pub mod comments;
+pub mod export;
+pub mod import;
+pub mod instrument;
+pub mod mentions;
+pub mod notifications;
+pub mod reads;
+pub mod reset;
 pub mod schema;
 pub mod search;
+pub mod subscriptions;
 pub mod threads;
 pub mod users;
 
-pub type DbConn = MysqlConnection;
-pub type DbPool = diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::MysqlConnection>>;
+pub use self::instrument::set_slow_query_log;
 
-const MAX_THREAD_LIMIT: i64 = 30;
-const MAX_CATEGORY_LIMIT: i64 = 30;
-const MAX_COMMENT_LIMIT: i64 = 30;
-const MAX_SEARCH_LIMIT: i64 = 30;
+pub type DbConn = instrument::InstrumentedConnection;
+pub type DbPool = diesel::r2d2::Pool<DbConnectionManager>;
+
+// The maximum number of rows returned by a query, which are set from the
+// configuration on startup by `set_limits`
+static MAX_THREAD_LIMIT: AtomicUsize = AtomicUsize::new(30);
+static MAX_CATEGORY_LIMIT: AtomicUsize = AtomicUsize::new(30);
+static MAX_COMMENT_LIMIT: AtomicUsize = AtomicUsize::new(30);
+static MAX_SEARCH_LIMIT: AtomicUsize = AtomicUsize::new(30);
+static MAX_NOTIFICATION_LIMIT: AtomicUsize = AtomicUsize::new(30);
+static MAX_BOOKMARK_LIMIT: AtomicUsize = AtomicUsize::new(30);
+static MAX_USER_LIMIT: AtomicUsize = AtomicUsize::new(30);
+static MAX_ACTIVITY_LIMIT: AtomicUsize = AtomicUsize::new(30);
+
+/// The number of days a user has to wait between renames
+const RENAME_COOLDOWN_DAYS: i64 = 30;
+/// The number of days an old username stays reserved for its previous owner
+const USERNAME_RESERVATION_DAYS: i64 = 90;
+
+/// Sets the maximum number of rows returned by queries
+pub fn set_limits(limits: &Limits) {
+    let set = |limit: &AtomicUsize, value: u32| limit.store(value as usize, Ordering::Relaxed);
+    set(&MAX_THREAD_LIMIT, limits.threads);
+    set(&MAX_CATEGORY_LIMIT, limits.categories);
+    set(&MAX_COMMENT_LIMIT, limits.comments);
+    set(&MAX_SEARCH_LIMIT, limits.search);
+    set(&MAX_NOTIFICATION_LIMIT, limits.notifications);
+    set(&MAX_BOOKMARK_LIMIT, limits.bookmarks);
+    set(&MAX_USER_LIMIT, limits.users);
+    set(&MAX_ACTIVITY_LIMIT, limits.activity);
+}
+
+/// Gets one of the maximum numbers of rows returned by queries
+fn max_rows(limit: &AtomicUsize) -> i64 {
+    limit.load(Ordering::Relaxed) as i64
+}
+
+/// A raw SQL condition which holds if the thread with the id given by the
+/// expression `thread_id` is neither hidden nor in a hidden category
+fn in_visible_thread(thread_id: &str) -> String {
+    format!(
+        "NOT EXISTS (SELECT 1 FROM threads t \
+         JOIN categories g ON g.id = t.category_id \
+         WHERE t.id = {} AND (t.hidden = 1 OR g.hidden = 1))",
+        thread_id
+    )
+}
 
 /// Establishes a connection to the database
 pub fn establish_connection(database_url: &str) -> IntResult<DbConn> {
-    MysqlConnection::establish(database_url)
+    DbConn::establish(database_url)
         .context(IntErrorKind::ConnectionError)
         .map_err(|e| e.into())
 }
 
+/// A connection manager which checks connections with a configurable query
+#[derive(Debug)]
+pub struct DbConnectionManager {
+    inner: ConnectionManager<DbConn>,
+    test_query: String,
+}
+
+impl ManageConnection for DbConnectionManager {
+    type Connection = DbConn;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<DbConn, r2d2::Error> {
+        self.inner.connect()
+    }
+
+    fn is_valid(&self, con: &mut DbConn) -> Result<(), r2d2::Error> {
+        con.batch_execute(&self.test_query).map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, con: &mut DbConn) -> bool {
+        self.inner.has_broken(con)
+    }
+}
+
+/// Converts a number of seconds to a duration, where 0 means no duration
+fn seconds(seconds: u64) -> Option<Duration> {
+    if seconds == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(seconds))
+    }
+}
+
 /// Makes a pool of connections to the database
-pub fn setup_connection_pool(database_url: &str) -> IntResult<DbPool> {
-    let manager = ConnectionManager::<DbConn>::new(database_url);
-    let diesel_db_config = r2d2::Pool::builder();
+///
+/// Getting a connection from the pool fails after `connection_timeout`
+/// seconds if all the connections are in use.
+pub fn setup_connection_pool(config: &DatabaseConfig) -> IntResult<DbPool> {
+    let manager = DbConnectionManager {
+        inner: ConnectionManager::new(config.url.as_str()),
+        test_query: config.test_query.clone(),
+    };
+    let diesel_db_config = r2d2::Pool::builder()
+        .max_size(config.pool_size)
+        .min_idle(config.min_idle)
+        .connection_timeout(Duration::from_secs(config.connection_timeout))
+        .max_lifetime(seconds(config.max_lifetime))
+        .idle_timeout(seconds(config.idle_timeout))
+        .test_on_check_out(config.test_on_checkout);
 
     diesel_db_config
         .build(manager)
@@ -39,6 +141,29 @@ pub fn setup_connection_pool(database_url: &str) -> IntResult<DbPool> {
         .map_err(|e| e.into())
 }
 
+/// Limits the time each statement on a connection may take, or lifts the
+/// limit if `limit` is `None`
+///
+/// Statements which run longer are interrupted by the database and fail. As
+/// pooled connections are reused, every user of the pool sets its own limit.
+pub fn limit_statement_time(con: &DbConn, limit: Option<Duration>) -> IntResult<()> {
+    // The limit is in seconds, and 0 means no limit
+    let seconds = match limit {
+        Some(limit) => {
+            let millis = (limit.as_secs() * 1000 + u64::from(limit.subsec_millis())).max(1);
+            format!("{}.{:03}", millis / 1000, millis % 1000)
+        }
+        None => "0".to_string(),
+    };
+
+    con.batch_execute(&format!("SET SESSION max_statement_time = {}", seconds))
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to limit the time of statements: {}", e);
+            e.into()
+        })
+}
+
 #[cfg(test)]
 mod _tests {
     use super::*;
@@ -51,4 +176,15 @@ mod _tests {
         assert!(categories::delete_all_categories(&con).is_ok());
         assert!(users::delete_all_users(&con).is_ok());
     }
+
+    #[test]
+    fn statement_time() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        assert!(limit_statement_time(&con, Some(Duration::from_millis(100))).is_ok());
+        assert!(con.batch_execute("SELECT SLEEP(1)").is_err());
+
+        assert!(limit_statement_time(&con, None).is_ok());
+        assert!(con.batch_execute("SELECT SLEEP(0.2)").is_ok());
+    }
 }
@@ -0,0 +1,461 @@
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Integer, Unsigned};
+use failure::ResultExt;
+
+use super::{max_rows, DbConn, MAX_NOTIFICATION_LIMIT};
+use crate::types::{Comment, Notification, QueuedNotification, Thread};
+use crate::{IntError, IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// The number of failed attempts after which queued notifications are parked,
+/// staying in the queue without being delivered
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+
+/// Queues the notifications of the subscribers about a new thread, or a new
+/// comment if `comment_id` is given
+///
+/// The subscribers are notified later by `deliver_queued_notifications`, so
+/// that the fan-out to the subscribers of popular threads and categories is
+/// not a part of the transaction adding the thread or comment.
+pub fn queue_notifications(
+    con: &DbConn,
+    thread_id: u32,
+    comment_id: Option<u32>,
+) -> IntResult<usize> {
+    use super::schema::notification_queue::dsl;
+
+    trace!(
+        "Queueing notifications about thread ({}), comment ({:?})",
+        thread_id,
+        comment_id
+    );
+
+    diesel::insert_into(dsl::notification_queue)
+        .values((dsl::thread_id.eq(thread_id), dsl::comment_id.eq(comment_id)))
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to queue notifications about thread ({}): {}", thread_id, e);
+            e.into()
+        })
+}
+
+/// Notifies the subscribers about up to `limit` of the queued threads and
+/// comments, oldest first, and returns the number of them
+///
+/// Each thread or comment is delivered and removed from the queue in its own
+/// transaction. One which fails is skipped and counted as failed, and it is
+/// parked after `MAX_DELIVERY_ATTEMPTS` failures, so that it does not hold up
+/// the rest of the queue.
+pub fn deliver_queued_notifications(con: &DbConn, limit: i64) -> IntResult<usize> {
+    use super::schema::notification_queue::dsl;
+
+    let queued = dsl::notification_queue
+        .filter(dsl::attempts.lt(MAX_DELIVERY_ATTEMPTS))
+        .select(dsl::id)
+        .order(dsl::id.asc())
+        .limit(limit)
+        .load::<u32>(con)
+        .context(IntErrorKind::QueryError)?;
+
+    for &id in &queued {
+        if let Err(e) = deliver_queued_notification(con, id) {
+            error!("Unable to deliver queued notification ({}): {}", id, e);
+            diesel::update(dsl::notification_queue.filter(dsl::id.eq(id)))
+                .set(dsl::attempts.eq(dsl::attempts + 1))
+                .execute(con)
+                .context(IntErrorKind::QueryError)?;
+        }
+    }
+
+    Ok(queued.len())
+}
+
+/// Notifies the subscribers about a queued thread or comment and removes it
+/// from the queue
+///
+/// The entry is locked first, so that when several instances deliver the
+/// queue, only the first one to lock it delivers it.
+fn deliver_queued_notification(con: &DbConn, id: u32) -> IntResult<()> {
+    use super::schema::notification_queue::dsl;
+
+    con.transaction::<_, IntError, _>(|| {
+        let entry = dsl::notification_queue
+            .filter(dsl::id.eq(id))
+            .for_update()
+            .first::<QueuedNotification>(con)
+            .optional()
+            .context(IntErrorKind::QueryError)?;
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                trace!("Queued notification ({}) was delivered by another instance", id);
+                return Ok(());
+            }
+        };
+
+        let notified = match entry.comment_id {
+            Some(comment_id) => {
+                let comment = super::comments::get_comment(con, comment_id.into(), true)?;
+                notify_thread_subscribers(con, &comment)?
+            }
+            None => {
+                let thread = super::threads::get_thread(con, entry.thread_id.into(), true)?;
+                notify_category_subscribers(con, &thread)?
+            }
+        };
+        trace!("Delivered queued notification ({}) to {} users", entry.id, notified);
+
+        diesel::delete(dsl::notification_queue.filter(dsl::id.eq(entry.id)))
+            .execute(con)
+            .context(IntErrorKind::QueryError)?;
+        Ok(())
+    })
+}
+
+/// Notifies all the subscribers of a thread, except the author, about a new
+/// comment
+///
+/// The fan-out is done by a single `INSERT ... SELECT`, so the cost of a
+/// popular thread stays within the database instead of growing the number of
+/// round trips.
+pub fn notify_thread_subscribers(con: &DbConn, comment: &Comment) -> IntResult<usize> {
+    trace!(
+        "Notifying subscribers of thread ({}) about comment ({})",
+        comment.thread_id,
+        comment.id
+    );
+
+    sql_query(
+        "INSERT INTO notifications (user_id, thread_id, comment_id) \
+         SELECT user_id, thread_id, ? FROM thread_subscriptions \
+         WHERE thread_id = ? AND user_id <> ?",
+    ).bind::<Unsigned<Integer>, _>(comment.id)
+    .bind::<Unsigned<Integer>, _>(comment.thread_id)
+    .bind::<Unsigned<Integer>, _>(comment.user_id)
+    .execute(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!(
+            "Unable to notify subscribers of thread ({}): {}",
+            comment.thread_id, e
+        );
+        e.into()
+    })
+}
+
+/// Notifies all the subscribers of a category, except the author, about a
+/// new thread
+pub fn notify_category_subscribers(con: &DbConn, thread: &Thread) -> IntResult<usize> {
+    trace!(
+        "Notifying subscribers of category ({}) about thread ({})",
+        thread.category_id,
+        thread.id
+    );
+
+    sql_query(
+        "INSERT INTO notifications (user_id, thread_id) \
+         SELECT user_id, ? FROM category_subscriptions \
+         WHERE category_id = ? AND user_id <> ?",
+    ).bind::<Unsigned<Integer>, _>(thread.id)
+    .bind::<Unsigned<Integer>, _>(thread.category_id)
+    .bind::<Unsigned<Integer>, _>(thread.user_id)
+    .execute(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!(
+            "Unable to notify subscribers of category ({}): {}",
+            thread.category_id, e
+        );
+        e.into()
+    })
+}
+
+/// Gets a page of the notifications of a user, newest first
+pub fn get_notifications(
+    con: &DbConn,
+    user_id: UserId,
+    unread_only: bool,
+    mentions_only: bool,
+    offset: u32,
+    limit: u32,
+) -> IntResult<Vec<Notification>> {
+    use super::schema::notifications::dsl;
+
+    trace!(
+        "Getting notifications of user ({}), unread only: {}, mentions only: {}",
+        user_id,
+        unread_only,
+        mentions_only
+    );
+
+    let mut query = dsl::notifications
+        .filter(dsl::user_id.eq(*user_id))
+        .order(dsl::id.desc())
+        .offset(i64::from(offset))
+        .limit(i64::from(limit).min(max_rows(&MAX_NOTIFICATION_LIMIT)))
+        .into_boxed();
+
+    if unread_only {
+        query = query.filter(dsl::seen.eq(false));
+    }
+
+    if mentions_only {
+        query = query.filter(dsl::mention.eq(true));
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get notifications of user ({}): {}", user_id, e);
+            e.into()
+        })
+}
+
+/// Marks notifications of a user as read, or all of them if `ids` is `None`
+pub fn mark_notifications_read(
+    con: &DbConn,
+    user_id: UserId,
+    ids: Option<&[u32]>,
+) -> IntResult<usize> {
+    use super::schema::notifications::dsl;
+
+    trace!("Marking notifications {:?} of user ({}) read", ids, user_id);
+
+    let unread = dsl::notifications
+        .filter(dsl::user_id.eq(*user_id))
+        .filter(dsl::seen.eq(false));
+
+    if let Some(ids) = ids {
+        diesel::update(unread.filter(dsl::id.eq_any(ids)))
+            .set(dsl::seen.eq(true))
+            .execute(con)
+    } else {
+        diesel::update(unread).set(dsl::seen.eq(true)).execute(con)
+    }.context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!(
+            "Unable to mark notifications of user ({}) read: {}",
+            user_id, e
+        );
+        e.into()
+    })
+}
+
+/// Counts the unread notifications of a user
+pub fn count_unread_notifications(con: &DbConn, user_id: UserId) -> IntResult<i64> {
+    use super::schema::notifications::dsl;
+
+    trace!("Counting unread notifications of user ({})", user_id);
+
+    dsl::notifications
+        .filter(dsl::user_id.eq(*user_id))
+        .filter(dsl::seen.eq(false))
+        .count()
+        .get_result(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!(
+                "Unable to count unread notifications of user ({}): {}",
+                user_id, e
+            );
+            e.into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{categories, comments, establish_connection, subscriptions, threads, users};
+    use crate::types::{InsertCategory, InsertComment, InsertThread, InsertUser};
+
+    #[test]
+    fn fan_out() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // Users
+        let insert_data = InsertUser {
+            id: 40,
+            username: "TestUser40".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let author = returned_data.unwrap();
+
+        let insert_data = InsertUser {
+            id: 41,
+            username: "TestUser41".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let subscriber = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: author.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Subscribe
+        assert!(
+            subscriptions::subscribe_thread(&con, subscriber.id.into(), thread.id.into()).is_ok()
+        );
+        assert!(subscriptions::subscribe_thread(&con, author.id.into(), thread.id.into()).is_ok());
+
+        // Comment
+        let insert_data = InsertComment {
+            thread_id: thread.id,
+            user_id: author.id,
+            parent_id: None,
+            content: "TestContent".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let comment = returned_data.unwrap();
+
+        // Fan out
+        let returned_data = notify_thread_subscribers(&con, &comment);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), 1);
+
+        // Count
+        let returned_data = count_unread_notifications(&con, subscriber.id.into());
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), 1);
+
+        // Get
+        let returned_data = get_notifications(&con, subscriber.id.into(), true, false, 0, 10);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data[0].comment_id, Some(comment.id));
+
+        // Mark read
+        assert!(mark_notifications_read(&con, subscriber.id.into(), None).is_ok());
+        let returned_data = count_unread_notifications(&con, subscriber.id.into());
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), 0);
+    }
+
+    #[test]
+    fn queued() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // Users
+        let insert_data = InsertUser {
+            id: 53,
+            username: "TestUser53".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let author = returned_data.unwrap();
+
+        let insert_data = InsertUser {
+            id: 54,
+            username: "TestUser54".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let subscriber = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+        assert!(
+            subscriptions::subscribe_category(&con, subscriber.id.into(), category.id.into())
+                .is_ok()
+        );
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: author.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Queued, but not delivered yet
+        assert!(queue_notifications(&con, thread.id, None).is_ok());
+        let returned_data = count_unread_notifications(&con, subscriber.id.into());
+        assert_eq!(returned_data.unwrap(), 0);
+
+        // Delivered, along with whatever other tests queued
+        loop {
+            let returned_data = deliver_queued_notifications(&con, 100);
+            assert!(returned_data.is_ok());
+            if returned_data.unwrap() < 100 {
+                break;
+            }
+        }
+        let returned_data = get_notifications(&con, subscriber.id.into(), true, false, 0, 10);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.len(), 1);
+        assert_eq!(returned_data[0].thread_id, thread.id);
+        assert_eq!(returned_data[0].comment_id, None);
+    }
+
+    #[test]
+    fn park_failing() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        con.test_transaction::<_, IntError, _>(|| {
+            use crate::db::schema::notification_queue::dsl;
+            use diesel::connection::SimpleConnection;
+
+            // An entry of a thread which can not be loaded, one attempt short
+            // of being parked
+            con.batch_execute("SET FOREIGN_KEY_CHECKS = 0")?;
+            diesel::insert_into(dsl::notification_queue)
+                .values((
+                    dsl::thread_id.eq(999_999_999),
+                    dsl::attempts.eq(MAX_DELIVERY_ATTEMPTS - 1),
+                )).execute(&con)?;
+            con.batch_execute("SET FOREIGN_KEY_CHECKS = 1")?;
+            let id = dsl::notification_queue
+                .filter(dsl::thread_id.eq(999_999_999))
+                .select(dsl::id)
+                .first::<u32>(&con)?;
+
+            // The failure does not hold up the rest of the queue, and the
+            // entry is parked
+            for _ in 0..2 {
+                while deliver_queued_notifications(&con, 100)? == 100 {}
+                let attempts = dsl::notification_queue
+                    .filter(dsl::id.eq(id))
+                    .select(dsl::attempts)
+                    .first::<u32>(&con)?;
+                assert_eq!(attempts, MAX_DELIVERY_ATTEMPTS);
+            }
+
+            // An entry which is already gone is skipped
+            diesel::delete(dsl::notification_queue.filter(dsl::id.eq(id))).execute(&con)?;
+            assert!(deliver_queued_notification(&con, id).is_ok());
+            Ok(())
+        });
+    }
+}
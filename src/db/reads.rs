@@ -0,0 +1,244 @@
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Integer, Unsigned};
+use failure::ResultExt;
+
+use super::{max_rows, DbConn, MAX_THREAD_LIMIT};
+use crate::types::{Thread, UnreadCount};
+use crate::{IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// Marks a thread as read by a user up to (and including) a comment
+///
+/// The read marker never moves backwards, so marking an older comment as read
+/// does nothing.
+pub fn mark_thread_read(
+    con: &DbConn,
+    user_id: UserId,
+    thread_id: ThreadId,
+    comment_id: CommentId,
+) -> IntResult<usize> {
+    use super::schema::comments::dsl;
+
+    trace!(
+        "Marking thread ({}) read by user ({}) up to comment ({})",
+        thread_id,
+        user_id,
+        comment_id
+    );
+
+    let in_thread = dsl::comments
+        .filter(dsl::id.eq(*comment_id))
+        .filter(dsl::thread_id.eq(*thread_id))
+        .count()
+        .get_result::<i64>(con)
+        .context(IntErrorKind::QueryError)?;
+
+    if in_thread == 0 {
+        return Err(IntErrorKind::ContentNotFound.into());
+    }
+
+    sql_query(
+        "INSERT INTO thread_reads (user_id, thread_id, comment_id) VALUES (?, ?, ?) \
+         ON DUPLICATE KEY UPDATE comment_id = GREATEST(comment_id, VALUES(comment_id))",
+    ).bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<Unsigned<Integer>, _>(*thread_id)
+    .bind::<Unsigned<Integer>, _>(*comment_id)
+    .execute(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!(
+            "Unable to mark thread ({}) read by user ({}): {}",
+            thread_id, user_id, e
+        );
+        e.into()
+    })
+}
+
+/// Counts the visible comments by other users which a user has not read in
+/// each of the given threads
+///
+/// Threads without any unread comments are left out of the result.
+pub fn get_unread_counts(
+    con: &DbConn,
+    user_id: UserId,
+    thread_ids: &[u32],
+) -> IntResult<Vec<UnreadCount>> {
+    trace!(
+        "Counting unread comments of user ({}) in threads {:?}",
+        user_id,
+        thread_ids
+    );
+
+    if thread_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The ids are integers, so they can safely be a part of the query
+    let thread_ids = thread_ids
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    sql_query(format!(
+        "SELECT c.thread_id, COUNT(*) AS unread FROM comments c \
+         LEFT JOIN thread_reads r ON r.thread_id = c.thread_id AND r.user_id = ? \
+         WHERE c.thread_id IN ({}) AND c.hidden = 0 AND c.user_id <> ? \
+         AND c.id > COALESCE(r.comment_id, 0) \
+         GROUP BY c.thread_id",
+        thread_ids
+    )).bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<Unsigned<Integer>, _>(*user_id)
+    .load(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!("Unable to count unread comments of user ({}): {}", user_id, e);
+        e.into()
+    })
+}
+
+/// Gets a page of the visible threads with unread comments for a user, the
+/// threads with the most recent comments first
+///
+/// Only threads which the user has read before, or is subscribed to, are
+/// considered.
+pub fn get_unread_threads(
+    con: &DbConn,
+    user_id: UserId,
+    offset: u32,
+    limit: u32,
+) -> IntResult<Vec<(Thread, i64)>> {
+    use super::schema::threads::dsl;
+
+    trace!("Getting unread threads of user ({})", user_id);
+
+    let counts: Vec<UnreadCount> = sql_query(
+        "SELECT t.id AS thread_id, COUNT(c.id) AS unread FROM threads t \
+         JOIN (SELECT thread_id FROM thread_reads WHERE user_id = ? \
+         UNION SELECT thread_id FROM thread_subscriptions WHERE user_id = ?) f \
+         ON f.thread_id = t.id \
+         LEFT JOIN thread_reads r ON r.thread_id = t.id AND r.user_id = ? \
+         JOIN comments c ON c.thread_id = t.id AND c.hidden = 0 AND c.user_id <> ? \
+         AND c.id > COALESCE(r.comment_id, 0) \
+         WHERE t.hidden = 0 \
+         GROUP BY t.id ORDER BY MAX(c.id) DESC LIMIT ? OFFSET ?",
+    ).bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<Unsigned<Integer>, _>(*user_id)
+    .bind::<BigInt, _>(i64::from(limit).min(max_rows(&MAX_THREAD_LIMIT)))
+    .bind::<BigInt, _>(i64::from(offset))
+    .load(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!("Unable to get unread threads of user ({}): {}", user_id, e);
+        e
+    })?;
+
+    let ids = counts.iter().map(|c| c.thread_id).collect::<Vec<_>>();
+    let mut threads = dsl::threads
+        .filter(dsl::id.eq_any(&ids))
+        .get_results::<Thread>(con)
+        .context(IntErrorKind::QueryError)?;
+
+    // Keep the order of the counts, which is by the most recent activity
+    Ok(counts
+        .into_iter()
+        .filter_map(|count| {
+            threads
+                .iter()
+                .position(|t| t.id == count.thread_id)
+                .map(|i| (threads.swap_remove(i), count.unread))
+        }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{categories, comments, establish_connection, threads, users};
+    use crate::types::{InsertCategory, InsertComment, InsertThread, InsertUser};
+
+    #[test]
+    fn unread() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // Users
+        let insert_data = InsertUser {
+            id: 44,
+            username: "ReadAuthor".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let author = returned_data.unwrap();
+
+        let insert_data = InsertUser {
+            id: 45,
+            username: "ReadReader".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let reader = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: author.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Comments
+        let mut inserted = Vec::new();
+        for _ in 0..3 {
+            let insert_data = InsertComment {
+                thread_id: thread.id,
+                user_id: author.id,
+                parent_id: None,
+                content: "TestContent".to_string(),
+            };
+            let returned_data = comments::insert_comment(&con, insert_data);
+            assert!(returned_data.is_ok());
+            inserted.push(returned_data.unwrap());
+        }
+
+        // Read the first comment
+        assert!(
+            mark_thread_read(
+                &con,
+                reader.id.into(),
+                thread.id.into(),
+                inserted[0].id.into()
+            ).is_ok()
+        );
+
+        // Count
+        let returned_data = get_unread_counts(&con, reader.id.into(), &[thread.id]);
+        assert!(returned_data.is_ok());
+        assert_eq!(
+            returned_data.unwrap(),
+            vec![UnreadCount {
+                thread_id: thread.id,
+                unread: 2,
+            }]
+        );
+
+        // Unread threads
+        let returned_data = get_unread_threads(&con, reader.id.into(), 0, 10);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), vec![(thread, 2)]);
+    }
+}
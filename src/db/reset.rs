@@ -0,0 +1,236 @@
+//! Deletion of all the content, or the content of a single category or
+//! thread, along with everything referring to it
+use diesel::prelude::*;
+use failure::ResultExt;
+
+use super::DbConn;
+use crate::{IntError, IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// Deletes all users, categories, threads and comments, and everything
+/// referring to them, in a single transaction
+///
+/// Returns the number of deleted users, categories, threads and comments.
+pub fn reset_all(con: &DbConn) -> IntResult<usize> {
+    use super::schema::{bookmarks, comments};
+
+    trace!("Resetting all content");
+
+    con.transaction::<_, IntError, _>(|| {
+        // Bookmarks are the only rows which are not deleted along with the
+        // users they belong to
+        diesel::delete(bookmarks::table).execute(con)?;
+
+        // Replies have to go before the comments they reply to
+        diesel::update(comments::table)
+            .set(comments::parent_id.eq(None::<u32>))
+            .execute(con)?;
+
+        Ok(super::comments::delete_all_comments(con)?
+            + super::threads::delete_all_threads(con)?
+            + super::categories::delete_all_categories(con)?
+            + super::users::delete_all_users(con)?)
+    }).map_err(|e| {
+        error!("Unable to reset all content: {}", e);
+        e
+    })
+}
+
+/// Deletes a category and its subcategories, along with their threads and
+/// comments, in a single transaction
+///
+/// Returns the number of deleted categories, threads and comments.
+pub fn reset_category(con: &DbConn, id: CategoryId) -> IntResult<usize> {
+    use super::schema::{categories, threads};
+
+    trace!("Resetting category ({})", id);
+
+    con.transaction::<_, IntError, _>(|| {
+        // The categories by depth, which are deleted deepest first so that
+        // none of them is removed by the cascade of its parent
+        let mut levels = vec![vec![*id]];
+        let mut category_ids = vec![*id];
+        loop {
+            let mut children = categories::table
+                .filter(categories::parent_id.eq_any(&levels[levels.len() - 1]))
+                .select(categories::id)
+                .load::<u32>(con)?;
+            children.retain(|child| !category_ids.contains(child));
+            if children.is_empty() {
+                break;
+            }
+            category_ids.extend(&children);
+            levels.push(children);
+        }
+
+        let thread_ids = threads::table
+            .filter(threads::category_id.eq_any(&category_ids))
+            .select(threads::id)
+            .load::<u32>(con)?;
+
+        let mut deleted = delete_threads(con, &thread_ids)?;
+        let mut num_deleted = 0;
+        for level in levels.iter().rev() {
+            num_deleted += diesel::delete(categories::table)
+                .filter(categories::id.eq_any(level))
+                .execute(con)?;
+        }
+        deleted += num_deleted;
+
+        if num_deleted == 0 {
+            Err(IntErrorKind::ContentNotFound)?
+        } else {
+            Ok(deleted)
+        }
+    }).map_err(|e| {
+        error!("Unable to reset category ({}): {}", id, e);
+        e
+    })
+}
+
+/// Deletes a thread along with its comments in a single transaction
+///
+/// Returns the number of deleted threads and comments.
+pub fn reset_thread(con: &DbConn, id: ThreadId) -> IntResult<usize> {
+    trace!("Resetting thread ({})", id);
+
+    con.transaction::<_, IntError, _>(|| match delete_threads(con, &[*id])? {
+        0 => Err(IntErrorKind::ContentNotFound)?,
+        deleted => Ok(deleted),
+    }).map_err(|e| {
+        error!("Unable to reset thread ({}): {}", id, e);
+        e
+    })
+}
+
+/// Deletes threads along with their comments
+fn delete_threads(con: &DbConn, ids: &[u32]) -> IntResult<usize> {
+    use super::schema::{comments, threads};
+
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let comment_ids = comments::table
+        .filter(comments::thread_id.eq_any(ids))
+        .select(comments::id)
+        .load::<u32>(con)
+        .context(IntErrorKind::QueryError)?;
+
+    // Detach every reply to the comments, including replies from other
+    // threads which should not exist but would block the deletion
+    diesel::update(comments::table)
+        .filter(comments::parent_id.eq_any(&comment_ids))
+        .set(comments::parent_id.eq(None::<u32>))
+        .execute(con)
+        .context(IntErrorKind::QueryError)?;
+
+    let num_comments = diesel::delete(comments::table)
+        .filter(comments::thread_id.eq_any(ids))
+        .execute(con)
+        .context(IntErrorKind::QueryError)?;
+    let num_threads = diesel::delete(threads::table)
+        .filter(threads::id.eq_any(ids))
+        .execute(con)
+        .context(IntErrorKind::QueryError)?;
+
+    Ok(num_comments + num_threads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{categories, comments, establish_connection, schema, threads, users};
+    use crate::types::{InsertCategory, InsertComment, InsertThread, InsertUser};
+
+    #[test]
+    fn category() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // User
+        let insert_data = InsertUser {
+            id: 49,
+            username: "ResetUser".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let user = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Comment and reply
+        let insert_data = InsertComment {
+            thread_id: thread.id,
+            user_id: user.id,
+            parent_id: None,
+            content: "TestContent".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let comment = returned_data.unwrap();
+        let insert_data = InsertComment {
+            thread_id: thread.id,
+            user_id: user.id,
+            parent_id: Some(comment.id),
+            content: "TestContent".to_string(),
+        };
+        assert!(comments::insert_comment(&con, insert_data).is_ok());
+
+        // Subcategory with a thread
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let subcategory = returned_data.unwrap();
+        assert!(
+            diesel::update(schema::categories::table)
+                .filter(schema::categories::id.eq(subcategory.id))
+                .set(schema::categories::parent_id.eq(Some(category.id)))
+                .execute(&con)
+                .is_ok()
+        );
+        let insert_data = InsertThread {
+            category_id: subcategory.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let subthread = returned_data.unwrap();
+
+        // Reset
+        let returned_data = reset_category(&con, category.id.into());
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), 6);
+
+        // Gone, except for the user
+        assert!(categories::get_category(&con, category.id.into(), true).is_err());
+        assert!(categories::get_category(&con, subcategory.id.into(), true).is_err());
+        assert!(threads::get_thread(&con, thread.id.into(), true).is_err());
+        assert!(threads::get_thread(&con, subthread.id.into(), true).is_err());
+        assert!(comments::get_comment(&con, comment.id.into(), true).is_err());
+        assert!(users::get_user(&con, user.id.into()).is_ok());
+    }
+}
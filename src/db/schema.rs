@@ -1,9 +1,40 @@
+table! {
+    audit_log (id) {
+        id -> Unsigned<Integer>,
+        action -> Varchar,
+        user_id -> Unsigned<Integer>,
+        actor_id -> Nullable<Unsigned<Integer>>,
+        details -> Text,
+        timestamp -> Datetime,
+    }
+}
+
+table! {
+    bookmarks (id) {
+        id -> Unsigned<Integer>,
+        user_id -> Unsigned<Integer>,
+        thread_id -> Nullable<Unsigned<Integer>>,
+        comment_id -> Nullable<Unsigned<Integer>>,
+        note -> Nullable<Varchar>,
+        timestamp -> Datetime,
+    }
+}
+
 table! {
     categories (id) {
         id -> Unsigned<Integer>,
         title -> Varchar,
         description -> Text,
         hidden -> Bool,
+        qa -> Bool,
+        parent_id -> Nullable<Unsigned<Integer>>,
+    }
+}
+
+table! {
+    category_subscriptions (user_id, category_id) {
+        user_id -> Unsigned<Integer>,
+        category_id -> Unsigned<Integer>,
     }
 }
 
@@ -19,6 +50,50 @@ table! {
     }
 }
 
+table! {
+    mentions (comment_id, user_id) {
+        comment_id -> Unsigned<Integer>,
+        user_id -> Unsigned<Integer>,
+        timestamp -> Datetime,
+    }
+}
+
+table! {
+    notification_queue (id) {
+        id -> Unsigned<Integer>,
+        thread_id -> Unsigned<Integer>,
+        comment_id -> Nullable<Unsigned<Integer>>,
+        attempts -> Unsigned<Integer>,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Unsigned<Integer>,
+        user_id -> Unsigned<Integer>,
+        thread_id -> Unsigned<Integer>,
+        comment_id -> Nullable<Unsigned<Integer>>,
+        timestamp -> Datetime,
+        seen -> Bool,
+        mention -> Bool,
+    }
+}
+
+table! {
+    thread_reads (user_id, thread_id) {
+        user_id -> Unsigned<Integer>,
+        thread_id -> Unsigned<Integer>,
+        comment_id -> Unsigned<Integer>,
+    }
+}
+
+table! {
+    thread_subscriptions (user_id, thread_id) {
+        user_id -> Unsigned<Integer>,
+        thread_id -> Unsigned<Integer>,
+    }
+}
+
 table! {
     threads (id) {
         id -> Unsigned<Integer>,
@@ -28,6 +103,16 @@ table! {
         description -> Text,
         timestamp -> Datetime,
         hidden -> Bool,
+        answer_id -> Nullable<Unsigned<Integer>>,
+    }
+}
+
+table! {
+    username_history (id) {
+        id -> Unsigned<Integer>,
+        user_id -> Unsigned<Integer>,
+        username -> Varchar,
+        changed -> Datetime,
     }
 }
 
@@ -37,12 +122,44 @@ table! {
         username -> Varchar,
         description -> Nullable<Varchar>,
         avatar -> Nullable<Varchar>,
+        joined -> Datetime,
     }
 }
 
+joinable!(bookmarks -> comments (comment_id));
+joinable!(bookmarks -> threads (thread_id));
+joinable!(bookmarks -> users (user_id));
+joinable!(category_subscriptions -> categories (category_id));
+joinable!(category_subscriptions -> users (user_id));
 joinable!(comments -> threads (thread_id));
 joinable!(comments -> users (user_id));
+joinable!(mentions -> comments (comment_id));
+joinable!(mentions -> users (user_id));
+joinable!(notification_queue -> comments (comment_id));
+joinable!(notification_queue -> threads (thread_id));
+joinable!(notifications -> comments (comment_id));
+joinable!(notifications -> threads (thread_id));
+joinable!(notifications -> users (user_id));
+joinable!(thread_reads -> threads (thread_id));
+joinable!(thread_reads -> users (user_id));
+joinable!(thread_subscriptions -> threads (thread_id));
+joinable!(thread_subscriptions -> users (user_id));
 joinable!(threads -> categories (category_id));
 joinable!(threads -> users (user_id));
+joinable!(username_history -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(categories, comments, threads, users,);
+allow_tables_to_appear_in_same_query!(
+    audit_log,
+    bookmarks,
+    categories,
+    category_subscriptions,
+    comments,
+    mentions,
+    notification_queue,
+    notifications,
+    thread_reads,
+    thread_subscriptions,
+    threads,
+    username_history,
+    users,
+);
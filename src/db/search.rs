@@ -1,7 +1,7 @@
 use diesel::prelude::*;
 use failure::ResultExt;
 
-use super::{DbConn, MAX_SEARCH_LIMIT};
+use super::{max_rows, DbConn, MAX_SEARCH_LIMIT};
 use crate::types::{Category, Comment, SearchResults, Thread, User};
 use crate::{IntErrorKind, IntResult};
 
@@ -10,7 +10,7 @@ use datatypes::content::requests::*;
 pub fn search_user(con: &DbConn, query: &str) -> IntResult<Vec<User>> {
     use super::schema::users::dsl;
     dsl::users
-        .limit(MAX_SEARCH_LIMIT)
+        .limit(max_rows(&MAX_SEARCH_LIMIT))
         .filter(dsl::username.like(query))
         .get_results(con)
         .context(IntErrorKind::QueryError)
@@ -28,12 +28,12 @@ pub fn search_category(
     use super::schema::categories::dsl;
     if include_hidden {
         dsl::categories
-            .limit(MAX_SEARCH_LIMIT)
+            .limit(max_rows(&MAX_SEARCH_LIMIT))
             .filter(dsl::title.like(query))
             .get_results(con)
     } else {
         dsl::categories
-            .limit(MAX_SEARCH_LIMIT)
+            .limit(max_rows(&MAX_SEARCH_LIMIT))
             .filter(dsl::hidden.eq(false))
             .filter(dsl::title.like(query))
             .get_results(con)
@@ -48,13 +48,13 @@ pub fn search_thread(con: &DbConn, query: &str, include_hidden: bool) -> IntResu
     use super::schema::threads::dsl;
     if include_hidden {
         dsl::threads
-            .limit(MAX_SEARCH_LIMIT)
+            .limit(max_rows(&MAX_SEARCH_LIMIT))
             .filter(dsl::title.like(query))
             .or_filter(dsl::description.like(query))
             .get_results(con)
     } else {
         dsl::threads
-            .limit(MAX_SEARCH_LIMIT)
+            .limit(max_rows(&MAX_SEARCH_LIMIT))
             .filter(dsl::hidden.eq(false))
             .filter(dsl::title.like(query).or(dsl::description.like(query)))
             .get_results(con)
@@ -69,12 +69,12 @@ pub fn search_comment(con: &DbConn, query: &str, include_hidden: bool) -> IntRes
     use super::schema::comments::dsl;
     if include_hidden {
         dsl::comments
-            .limit(MAX_SEARCH_LIMIT)
+            .limit(max_rows(&MAX_SEARCH_LIMIT))
             .filter(dsl::content.like(query))
             .get_results(con)
     } else {
         dsl::comments
-            .limit(MAX_SEARCH_LIMIT)
+            .limit(max_rows(&MAX_SEARCH_LIMIT))
             .filter(dsl::hidden.eq(false))
             .filter(dsl::content.like(query))
             .get_results(con)
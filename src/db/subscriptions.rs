@@ -0,0 +1,108 @@
+use diesel::prelude::*;
+use failure::ResultExt;
+
+use super::DbConn;
+use crate::types::{CategorySubscription, ThreadSubscription};
+use crate::{IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// Subscribes a user to a thread, does nothing if already subscribed
+pub fn subscribe_thread(con: &DbConn, user_id: UserId, thread_id: ThreadId) -> IntResult<usize> {
+    use super::schema::thread_subscriptions::dsl;
+
+    trace!("Subscribing user ({}) to thread ({})", user_id, thread_id);
+
+    let subscription = ThreadSubscription {
+        user_id: *user_id,
+        thread_id: *thread_id,
+    };
+
+    diesel::insert_or_ignore_into(dsl::thread_subscriptions)
+        .values(&subscription)
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!(
+                "Unable to subscribe user ({}) to thread ({}): {}",
+                user_id, thread_id, e
+            );
+            e.into()
+        })
+}
+
+/// Unsubscribes a user from a thread
+pub fn unsubscribe_thread(con: &DbConn, user_id: UserId, thread_id: ThreadId) -> IntResult<usize> {
+    use super::schema::thread_subscriptions::dsl;
+
+    trace!("Unsubscribing user ({}) from thread ({})", user_id, thread_id);
+
+    diesel::delete(dsl::thread_subscriptions)
+        .filter(dsl::user_id.eq(*user_id))
+        .filter(dsl::thread_id.eq(*thread_id))
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!(
+                "Unable to unsubscribe user ({}) from thread ({}): {}",
+                user_id, thread_id, e
+            );
+            e.into()
+        })
+}
+
+/// Subscribes a user to a category, does nothing if already subscribed
+pub fn subscribe_category(
+    con: &DbConn,
+    user_id: UserId,
+    category_id: CategoryId,
+) -> IntResult<usize> {
+    use super::schema::category_subscriptions::dsl;
+
+    trace!("Subscribing user ({}) to category ({})", user_id, category_id);
+
+    let subscription = CategorySubscription {
+        user_id: *user_id,
+        category_id: *category_id,
+    };
+
+    diesel::insert_or_ignore_into(dsl::category_subscriptions)
+        .values(&subscription)
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!(
+                "Unable to subscribe user ({}) to category ({}): {}",
+                user_id, category_id, e
+            );
+            e.into()
+        })
+}
+
+/// Unsubscribes a user from a category
+pub fn unsubscribe_category(
+    con: &DbConn,
+    user_id: UserId,
+    category_id: CategoryId,
+) -> IntResult<usize> {
+    use super::schema::category_subscriptions::dsl;
+
+    trace!(
+        "Unsubscribing user ({}) from category ({})",
+        user_id,
+        category_id
+    );
+
+    diesel::delete(dsl::category_subscriptions)
+        .filter(dsl::user_id.eq(*user_id))
+        .filter(dsl::category_id.eq(*category_id))
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!(
+                "Unable to unsubscribe user ({}) from category ({}): {}",
+                user_id, category_id, e
+            );
+            e.into()
+        })
+}
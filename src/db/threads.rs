@@ -1,7 +1,7 @@
 use diesel::prelude::*;
 use failure::ResultExt;
 
-use super::{DbConn, MAX_THREAD_LIMIT};
+use super::{max_rows, DbConn, MAX_THREAD_LIMIT};
 use crate::types::{InsertThread, Thread, UpdateThread};
 use crate::{IntErrorKind, IntResult};
 
@@ -66,10 +66,10 @@ pub fn get_all_threads(con: &DbConn, include_hidden: bool) -> IntResult<Vec<Thre
     trace!("Getting all threads [{}]", fmt_hidden!(include_hidden));
 
     if include_hidden {
-        dsl::threads.limit(MAX_THREAD_LIMIT).get_results(con)
+        dsl::threads.limit(max_rows(&MAX_THREAD_LIMIT)).get_results(con)
     } else {
         dsl::threads
-            .limit(MAX_THREAD_LIMIT)
+            .limit(max_rows(&MAX_THREAD_LIMIT))
             .filter(dsl::hidden.eq(false))
             .get_results(con)
     }.context(IntErrorKind::QueryError)
@@ -79,32 +79,47 @@ pub fn get_all_threads(con: &DbConn, include_hidden: bool) -> IntResult<Vec<Thre
     })
 }
 
-/// Gets all the comments in a thread from the comment table
+/// Gets all the threads in a category from the thread table
+///
+/// If `answered` is given, only threads which have (or have not) got an
+/// accepted answer are returned.
 pub fn get_threads_in_category(
     con: &DbConn,
     category_id: CategoryId,
     include_hidden: bool,
+    answered: Option<bool>,
 ) -> IntResult<Vec<Thread>> {
     use super::schema::threads::dsl;
 
-    trace!("Getting threads in category ({})", category_id);
+    trace!(
+        "Getting threads in category ({}), answered: {:?} [{}]",
+        category_id,
+        answered,
+        fmt_hidden!(include_hidden)
+    );
 
-    if include_hidden {
-        dsl::threads
-            .limit(MAX_THREAD_LIMIT)
-            .filter(dsl::category_id.eq(*category_id))
-            .get_results(con)
-    } else {
-        dsl::threads
-            .limit(MAX_THREAD_LIMIT)
-            .filter(dsl::category_id.eq(*category_id))
-            .filter(dsl::hidden.eq(false))
-            .get_results(con)
-    }.context(IntErrorKind::QueryError)
-    .map_err(|e| {
-        error!("Unable to get threads in category ({}): {}", category_id, e);
-        e.into()
-    })
+    let mut query = dsl::threads
+        .limit(max_rows(&MAX_THREAD_LIMIT))
+        .filter(dsl::category_id.eq(*category_id))
+        .into_boxed();
+
+    if !include_hidden {
+        query = query.filter(dsl::hidden.eq(false));
+    }
+
+    match answered {
+        Some(true) => query = query.filter(dsl::answer_id.is_not_null()),
+        Some(false) => query = query.filter(dsl::answer_id.is_null()),
+        None => {}
+    }
+
+    query
+        .get_results(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get threads in category ({}): {}", category_id, e);
+            e.into()
+        })
 }
 
 /// Clears the thread table
@@ -149,12 +164,74 @@ pub fn update_thread(
     get_thread(con, id.into(), true)
 }
 
+/// Sets (or removes) the accepted answer of a thread
+///
+/// The thread has to be in a Q&A category and the answer has to be a comment
+/// in the thread. When `user_id` is given, the thread has to be authored by
+/// that user, while `None` skips the check for moderators.
+pub fn set_answer(
+    con: &DbConn,
+    id: ThreadId,
+    user_id: Option<UserId>,
+    answer_id: Option<CommentId>,
+) -> IntResult<Thread> {
+    use super::schema::threads::dsl;
+    use super::schema::{categories, comments};
+
+    trace!("Setting answer of thread ({}) to {:?}", id, answer_id);
+
+    let thread = get_thread(con, id, true)?;
+
+    if let Some(user_id) = user_id {
+        if thread.user_id != *user_id {
+            return Err(IntErrorKind::ContentNotFound.into());
+        }
+    }
+
+    let qa = categories::table
+        .filter(categories::id.eq(thread.category_id))
+        .select(categories::qa)
+        .first::<bool>(con)
+        .context(IntErrorKind::QueryError)?;
+
+    if !qa {
+        trace!("Category ({}) is not a Q&A category", thread.category_id);
+        return Err(IntErrorKind::InvalidAnswer.into());
+    }
+
+    if let Some(answer_id) = answer_id {
+        let in_thread = comments::table
+            .filter(comments::id.eq(*answer_id))
+            .filter(comments::thread_id.eq(thread.id))
+            .count()
+            .get_result::<i64>(con)
+            .context(IntErrorKind::QueryError)?;
+
+        if in_thread == 0 {
+            trace!("Comment ({}) is not in thread ({})", answer_id, id);
+            return Err(IntErrorKind::InvalidAnswer.into());
+        }
+    }
+
+    diesel::update(dsl::threads)
+        .filter(dsl::id.eq(thread.id))
+        .set(dsl::answer_id.eq(answer_id.map(|a| *a)))
+        .execute(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to set answer of thread ({}): {}", id, e);
+            e
+        })?;
+
+    get_thread(con, id, true)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use chrono::NaiveDateTime;
-    use crate::db::{categories, establish_connection, users};
-    use crate::types::{InsertCategory, InsertUser};
+    use crate::db::{categories, comments, establish_connection, users};
+    use crate::types::{InsertCategory, InsertComment, InsertUser};
 
     #[test]
     fn insert_and_get() {
@@ -163,7 +240,7 @@ mod tests {
         // User
         let insert_data = InsertUser {
             id: 10,
-            username: "TestUser".to_string(),
+            username: "TestUser10".to_string(),
         };
         let returned_data = users::insert_user(&con, insert_data);
         assert!(returned_data.is_ok());
@@ -194,6 +271,7 @@ mod tests {
             description: "TestDescription".to_string(),
             timestamp: NaiveDateTime::from_timestamp(0, 0),
             hidden: false,
+            answer_id: None,
         };
 
         // Insert
@@ -222,7 +300,7 @@ mod tests {
         // User
         let insert_data = InsertUser {
             id: 11,
-            username: "TestUser".to_string(),
+            username: "TestUser11".to_string(),
         };
         let returned_data = users::insert_user(&con, insert_data);
         assert!(returned_data.is_ok());
@@ -274,6 +352,7 @@ mod tests {
             description: "OtherDescription".to_string(),
             timestamp: NaiveDateTime::from_timestamp(0, 0),
             hidden: true,
+            answer_id: None,
         };
 
         // Missing foreign keys
@@ -304,7 +383,7 @@ mod tests {
         // User
         let insert_data = InsertUser {
             id: 12,
-            username: "TestUser".to_string(),
+            username: "TestUser12".to_string(),
         };
         let returned_data = users::insert_user(&con, insert_data);
         assert!(returned_data.is_ok());
@@ -349,4 +428,69 @@ mod tests {
         // Fail to get
         assert!(get_thread(&con, returned_data.id.into(), false).is_err());
     }
+
+    #[test]
+    fn answer() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // User
+        let insert_data = InsertUser {
+            id: 13,
+            username: "TestUser13".to_string(),
+        };
+        let returned_data = users::insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let user = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Comment
+        let insert_data = InsertComment {
+            thread_id: thread.id,
+            user_id: user.id,
+            parent_id: None,
+            content: "TestContent".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let comment = returned_data.unwrap();
+
+        // Not a Q&A category
+        assert!(set_answer(&con, thread.id.into(), None, Some(comment.id.into())).is_err());
+
+        // Accept
+        assert!(categories::set_category_qa(&con, category.id.into(), true).is_ok());
+        let returned_data =
+            set_answer(&con, thread.id.into(), Some(user.id.into()), Some(comment.id.into()));
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().answer_id, Some(comment.id));
+
+        // Answered filter
+        let returned_data = get_threads_in_category(&con, category.id.into(), true, Some(true));
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().len(), 1);
+
+        // Pinned at the top
+        let returned_data = comments::get_comments_in_thread(&con, thread.id.into(), true);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap()[0].id, comment.id);
+    }
 }
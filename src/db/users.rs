@@ -1,25 +1,58 @@
 use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
 use failure::ResultExt;
 
-use super::DbConn;
-use crate::types::{InsertUser, UpdateUser, User};
-use crate::{IntErrorKind, IntResult};
+use super::{DbConn, RENAME_COOLDOWN_DAYS, USERNAME_RESERVATION_DAYS};
+use crate::types::{
+    InsertAuditEntry, InsertUser, InsertUsernameChange, UpdateUser, User, UsernameChange,
+};
+use crate::{IntError, IntErrorKind, IntResult};
 
 use datatypes::valid::ids::*;
 
+use chrono::naive::NaiveDateTime;
+use chrono::{Duration, Local};
+
+/// The id of the placeholder user which the content of deleted users belongs to
+const DELETED_USER_ID: u32 = 0;
+/// The usernames of the placeholder user, of which it gets the first one no
+/// user registered before they were reserved. No user can take them.
+const DELETED_USERNAMES: &[&str] = &["deleted", "[deleted]"];
+/// The text which replaces the content of deleted users when it is removed
+const DELETED_CONTENT: &str = "[deleted]";
+
 /// Inserts new user into the user table
+///
+/// Usernames are unique regardless of case, so inserting a user with a taken
+/// username fails with `UsernameTaken`, and with a recently changed username
+/// with `UsernameReserved`.
 pub fn insert_user(con: &DbConn, user: impl Into<InsertUser>) -> IntResult<User> {
     use super::schema::users::dsl;
     let user = user.into();
     let id = user.id;
     trace!("Inserting user");
 
+    if username_taken(con, &user.username)? {
+        return Err(IntErrorKind::UsernameTaken.into());
+    }
+
+    if username_reserved(con, &user.username, None)? {
+        return Err(IntErrorKind::UsernameReserved.into());
+    }
+
     user.insert_into(dsl::users)
         .execute(con)
-        .context(IntErrorKind::QueryError)
-        .map_err(|e| {
-            error!("Unable to insert user: {:?}", e);
-            e.into()
+        .map_err(|e| match e {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, ref info)
+                if info.message().contains("users_username_unique") =>
+            {
+                IntErrorKind::UsernameTaken.into()
+            }
+            e => {
+                error!("Unable to insert user: {:?}", e);
+                e.into()
+            }
         }).and_then(|_| {
             dsl::users
                 .filter(dsl::id.eq(id))
@@ -34,6 +67,29 @@ pub fn insert_user(con: &DbConn, user: impl Into<InsertUser>) -> IntResult<User>
         })
 }
 
+/// Checks whether a username is used by any user, ignoring case
+pub fn username_taken(con: &DbConn, username: &str) -> IntResult<bool> {
+    use super::schema::users::dsl;
+
+    trace!("Checking whether username ({}) is taken", username);
+
+    if DELETED_USERNAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(username))
+    {
+        return Ok(true);
+    }
+
+    diesel::select(diesel::dsl::exists(
+        dsl::users.filter(dsl::username.eq(username)),
+    )).get_result(con)
+    .context(IntErrorKind::QueryError)
+    .map_err(|e| {
+        error!("Unable to check username ({}): {}", username, e);
+        e.into()
+    })
+}
+
 /// Gets an exisiting user from the user table
 pub fn get_user(con: &DbConn, id: UserId) -> IntResult<User> {
     use super::schema::users::dsl;
@@ -52,26 +108,287 @@ pub fn get_user(con: &DbConn, id: UserId) -> IntResult<User> {
         })
 }
 
-/// Deletes an existing user from the user table
-pub fn delete_user(con: &DbConn, id: UserId) -> IntResult<usize> {
+/// Checks whether a username was recently given up by a user other than
+/// `user_id`, and is still reserved for its previous owner
+pub fn username_reserved(
+    con: &DbConn,
+    username: &str,
+    user_id: Option<UserId>,
+) -> IntResult<bool> {
+    use super::schema::username_history::dsl;
+
+    trace!("Checking whether username ({}) is reserved", username);
+
+    let cutoff = Local::now().naive_local() - Duration::days(USERNAME_RESERVATION_DAYS);
+
+    let mut query = dsl::username_history
+        .filter(dsl::username.eq(username))
+        .filter(dsl::changed.gt(cutoff))
+        .into_boxed();
+
+    if let Some(user_id) = user_id {
+        query = query.filter(dsl::user_id.ne(*user_id));
+    }
+
+    diesel::select(diesel::dsl::exists(query))
+        .get_result(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to check reservation of username ({}): {}", username, e);
+            e.into()
+        })
+}
+
+/// Changes the username of an existing user, and records the old username in
+/// the username history
+///
+/// Fails with `RenameCooldown` if the user was renamed within the cooldown
+/// period, and with `UsernameTaken` or `UsernameReserved` if the username
+/// belongs to another user. Changing only the case of a username is allowed.
+pub fn rename_user(con: &DbConn, id: UserId, username: &str) -> IntResult<User> {
+    use super::schema::username_history::dsl as history_dsl;
     use super::schema::users::dsl;
 
-    trace!("Deleting user ({})", id);
+    trace!("Renaming user ({}) to {}", id, username);
 
-    let num_deleted = diesel::delete(dsl::users)
-        .filter(dsl::id.eq(*id))
+    con.transaction::<_, IntError, _>(|| {
+        let user = get_user(con, id)?;
+        if user.username == username {
+            return Ok(user);
+        }
+
+        let now = Local::now().naive_local();
+
+        let last_change = history_dsl::username_history
+            .filter(history_dsl::user_id.eq(*id))
+            .select(diesel::dsl::max(history_dsl::changed))
+            .first::<Option<NaiveDateTime>>(con)
+            .context(IntErrorKind::QueryError)?;
+        if let Some(last_change) = last_change {
+            if last_change > now - Duration::days(RENAME_COOLDOWN_DAYS) {
+                return Err(IntErrorKind::RenameCooldown.into());
+            }
+        }
+
+        let taken = diesel::select(diesel::dsl::exists(
+            dsl::users
+                .filter(dsl::username.eq(username))
+                .filter(dsl::id.ne(*id)),
+        )).get_result(con)
+        .context(IntErrorKind::QueryError)?;
+        let placeholder = DELETED_USERNAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(username));
+        if taken || placeholder {
+            return Err(IntErrorKind::UsernameTaken.into());
+        }
+
+        if username_reserved(con, username, Some(id))? {
+            return Err(IntErrorKind::UsernameReserved.into());
+        }
+
+        InsertUsernameChange {
+            user_id: *id,
+            username: user.username,
+            changed: now,
+        }.insert_into(history_dsl::username_history)
         .execute(con)
+        .context(IntErrorKind::QueryError)?;
+
+        diesel::update(dsl::users)
+            .filter(dsl::id.eq(*id))
+            .set(dsl::username.eq(username))
+            .execute(con)
+            .map_err(|e| match e {
+                // Another user took the username since it was checked
+                DatabaseError(DatabaseErrorKind::UniqueViolation, ref info)
+                    if info.message().contains("users_username_unique") =>
+                {
+                    IntError::from(IntErrorKind::UsernameTaken)
+                }
+                e => {
+                    error!("Unable to rename user ({}): {:?}", id, e);
+                    e.into()
+                }
+            })?;
+
+        get_user(con, id)
+    }).map_err(|e| {
+        trace!("Unable to rename user ({}): {}", id, e);
+        e
+    })
+}
+
+/// Gets the previous usernames of a user, the most recent change first
+pub fn get_username_history(con: &DbConn, id: UserId) -> IntResult<Vec<UsernameChange>> {
+    use super::schema::username_history::dsl;
+
+    trace!("Getting username history of user ({})", id);
+
+    dsl::username_history
+        .filter(dsl::user_id.eq(*id))
+        .order(dsl::changed.desc())
+        .load(con)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get username history of user ({}): {}", id, e);
+            e.into()
+        })
+}
+
+/// Gets an existing user by username, ignoring case
+pub fn get_user_by_username(con: &DbConn, username: &str) -> IntResult<User> {
+    use super::schema::users::dsl;
+
+    trace!("Getting user by username ({})", username);
+
+    dsl::users
+        .filter(dsl::username.eq(username))
+        .first::<User>(con)
+        .optional()
+        .context(IntErrorKind::QueryError)?
+        .ok_or(IntErrorKind::ContentNotFound)
+        .map_err(|e| {
+            trace!("Unable to get user by username ({}): {}", username, e);
+            e.into()
+        })
+}
+
+/// Gets existing users by id, skipping ids without a user
+///
+/// At most `MAX_USER_LIMIT` ids are looked up, and the users are returned in
+/// the order of the ids.
+pub fn get_users(con: &DbConn, ids: &[u32]) -> IntResult<Vec<User>> {
+    use super::schema::users::dsl;
+
+    let ids = &ids[..ids.len().min(super::max_rows(&super::MAX_USER_LIMIT) as usize)];
+
+    trace!("Getting users ({:?})", ids);
+
+    let mut users = dsl::users
+        .filter(dsl::id.eq_any(ids))
+        .load::<User>(con)
         .context(IntErrorKind::QueryError)
         .map_err(|e| {
-            error!("Unable to delete user ({}): {}", id, e);
-            e
+            error!("Unable to get users ({:?}): {}", ids, e);
+            IntError::from(e)
         })?;
 
-    if num_deleted == 0 {
-        Err(IntErrorKind::ContentNotFound)?
-    } else {
+    users.sort_by_key(|user| ids.iter().position(|id| *id == user.id));
+    Ok(users)
+}
+
+/// Deletes an existing user from the user table
+///
+/// The threads and comments of the user are reassigned to the placeholder
+/// user `DELETED_USER_ID`. With `remove_content` set they are also hidden and
+/// their text is replaced, but they are kept so that replies and answers stay
+/// valid. The bookmarks of the user are deleted, the rest of the data of the
+/// user goes with the cascading foreign keys, and the deletion is recorded in
+/// the audit log.
+pub fn delete_user(
+    con: &DbConn,
+    id: UserId,
+    actor_id: Option<UserId>,
+    remove_content: bool,
+) -> IntResult<usize> {
+    use super::schema::comments::dsl as comments_dsl;
+    use super::schema::threads::dsl as threads_dsl;
+    use super::schema::users::dsl;
+
+    trace!("Deleting user ({}), remove content: {}", id, remove_content);
+
+    if *id == DELETED_USER_ID {
+        return Err(IntErrorKind::InvalidId.into());
+    }
+
+    con.transaction::<_, IntError, _>(|| {
+        get_user(con, id)?;
+
+        insert_placeholder(con)?;
+
+        if remove_content {
+            diesel::update(threads_dsl::threads.filter(threads_dsl::user_id.eq(*id)))
+                .set((
+                    threads_dsl::title.eq(DELETED_CONTENT),
+                    threads_dsl::description.eq(DELETED_CONTENT),
+                    threads_dsl::hidden.eq(true),
+                )).execute(con)?;
+            diesel::update(comments_dsl::comments.filter(comments_dsl::user_id.eq(*id)))
+                .set((
+                    comments_dsl::content.eq(DELETED_CONTENT),
+                    comments_dsl::hidden.eq(true),
+                )).execute(con)?;
+        }
+
+        let num_threads =
+            diesel::update(threads_dsl::threads.filter(threads_dsl::user_id.eq(*id)))
+                .set(threads_dsl::user_id.eq(DELETED_USER_ID))
+                .execute(con)?;
+        let num_comments =
+            diesel::update(comments_dsl::comments.filter(comments_dsl::user_id.eq(*id)))
+                .set(comments_dsl::user_id.eq(DELETED_USER_ID))
+                .execute(con)?;
+
+        super::bookmarks::delete_user_bookmarks(con, id)?;
+
+        let num_deleted = diesel::delete(dsl::users)
+            .filter(dsl::id.eq(*id))
+            .execute(con)?;
+
+        super::audit::insert_audit_entry(
+            con,
+            &InsertAuditEntry {
+                action: "delete_user".to_string(),
+                user_id: *id,
+                actor_id: actor_id.map(|id| *id),
+                details: format!(
+                    "threads: {}, comments: {}, content removed: {}",
+                    num_threads, num_comments, remove_content
+                ),
+            },
+        )?;
+
         Ok(num_deleted)
+    }).map_err(|e| {
+        error!("Unable to delete user ({}): {}", id, e);
+        e
+    })
+}
+
+/// Inserts the placeholder user `DELETED_USER_ID` unless it exists
+fn insert_placeholder(con: &DbConn) -> IntResult<()> {
+    use super::schema::users::dsl;
+
+    let exists = diesel::select(diesel::dsl::exists(
+        dsl::users.filter(dsl::id.eq(DELETED_USER_ID)),
+    )).get_result(con)
+    .context(IntErrorKind::QueryError)?;
+    if exists {
+        return Ok(());
+    }
+
+    for username in DELETED_USERNAMES {
+        let taken = diesel::select(diesel::dsl::exists(
+            dsl::users.filter(dsl::username.eq(username)),
+        )).get_result(con)
+        .context(IntErrorKind::QueryError)?;
+        if taken {
+            warn!("The placeholder username ({}) belongs to another user", username);
+            continue;
+        }
+
+        InsertUser {
+            id: DELETED_USER_ID,
+            username: username.to_string(),
+        }.insert_into(dsl::users)
+        .execute(con)
+        .context(IntErrorKind::QueryError)?;
+        return Ok(());
     }
+
+    error!("Unable to insert the placeholder user, all its usernames are taken");
+    Err(IntErrorKind::ServerError.into())
 }
 
 /// Clears the user table
@@ -114,7 +431,8 @@ pub fn update_user(con: &DbConn, user_id: UserId, user: impl Into<UpdateUser>) -
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::establish_connection;
+    use crate::db::{categories, comments, establish_connection, threads};
+    use crate::types::{InsertCategory, InsertComment, InsertThread};
 
     #[test]
     fn insert_and_get() {
@@ -122,14 +440,15 @@ mod tests {
 
         let insert_data = InsertUser {
             id: 1,
-            username: "TestUser".to_string(),
+            username: "TestUser1".to_string(),
         };
 
-        let expected_data = User {
+        let mut expected_data = User {
             id: 1,
-            username: "TestUser".to_string(),
+            username: "TestUser1".to_string(),
             description: None,
             avatar: None,
+            joined: NaiveDateTime::from_timestamp(0, 0),
         };
 
         // Insert
@@ -138,6 +457,7 @@ mod tests {
         let returned_data = returned_data.unwrap();
 
         // Compare
+        expected_data.joined = returned_data.joined;
         assert_eq!(returned_data, expected_data);
 
         // Get
@@ -155,7 +475,7 @@ mod tests {
 
         let insert_data = InsertUser {
             id: 2,
-            username: "TestUser".to_string(),
+            username: "TestUser2".to_string(),
         };
 
         let update_data = UpdateUser {
@@ -164,11 +484,12 @@ mod tests {
             avatar: Some("TestAvatar".to_string()),
         };
 
-        let expected_data = User {
+        let mut expected_data = User {
             id: 2,
-            username: "TestUser".to_string(),
+            username: "TestUser2".to_string(),
             description: Some("TestDescription".to_string()),
             avatar: Some("TestAvatar".to_string()),
+            joined: NaiveDateTime::from_timestamp(0, 0),
         };
 
         // Insert
@@ -180,6 +501,7 @@ mod tests {
         let returned_data = returned_data.unwrap();
 
         // Compare
+        expected_data.joined = returned_data.joined;
         assert_eq!(returned_data, expected_data);
     }
 
@@ -190,7 +512,7 @@ mod tests {
         // insert
         let insert_data = InsertUser {
             id: 3,
-            username: "TestUser".to_string(),
+            username: "TestUser3".to_string(),
         };
         assert!(insert_user(&con, insert_data).is_ok());
 
@@ -198,9 +520,209 @@ mod tests {
         assert!(get_user(&con, 3.into()).is_ok());
 
         // Delete
-        assert!(delete_user(&con, 3.into()).is_ok());
+        assert!(delete_user(&con, 3.into(), None, false).is_ok());
 
         // Fail to get
         assert!(get_user(&con, 3.into()).is_err());
     }
+
+    #[test]
+    fn unique_username() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // Insert
+        let insert_data = InsertUser {
+            id: 4,
+            username: "TestUser4".to_string(),
+        };
+        assert!(insert_user(&con, insert_data).is_ok());
+
+        // Fail to insert with the same username in another case
+        let insert_data = InsertUser {
+            id: 5,
+            username: "testuser4".to_string(),
+        };
+        let returned_data = insert_user(&con, insert_data);
+        assert_eq!(returned_data.unwrap_err().kind(), IntErrorKind::UsernameTaken);
+
+        // Get by username
+        let returned_data = get_user_by_username(&con, "TESTUSER4");
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().id, 4);
+
+        // Get several, skipping missing ids
+        let returned_data = get_users(&con, &[5, 4]);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.len(), 1);
+        assert_eq!(returned_data[0].id, 4);
+    }
+
+    #[test]
+    fn rename() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // Insert
+        let insert_data = InsertUser {
+            id: 6,
+            username: "TestUser6".to_string(),
+        };
+        assert!(insert_user(&con, insert_data).is_ok());
+        let insert_data = InsertUser {
+            id: 7,
+            username: "TestUser7".to_string(),
+        };
+        assert!(insert_user(&con, insert_data).is_ok());
+
+        // Rename
+        let returned_data = rename_user(&con, 6.into(), "Renamed6");
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap().username, "Renamed6");
+
+        // Fail to rename again within the cooldown
+        let returned_data = rename_user(&con, 6.into(), "Renamed6b");
+        assert_eq!(returned_data.unwrap_err().kind(), IntErrorKind::RenameCooldown);
+
+        // Fail to take the new or the reserved old username
+        let returned_data = rename_user(&con, 7.into(), "renamed6");
+        assert_eq!(returned_data.unwrap_err().kind(), IntErrorKind::UsernameTaken);
+        let returned_data = rename_user(&con, 7.into(), "TestUser6");
+        assert_eq!(returned_data.unwrap_err().kind(), IntErrorKind::UsernameReserved);
+
+        // History
+        let returned_data = get_username_history(&con, 6.into());
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.len(), 1);
+        assert_eq!(returned_data[0].username, "TestUser6");
+    }
+
+    #[test]
+    fn delete_with_content() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // User
+        let insert_data = InsertUser {
+            id: 8,
+            username: "TestUser8".to_string(),
+        };
+        let returned_data = insert_user(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let user = returned_data.unwrap();
+
+        // Category
+        let insert_data = InsertCategory {
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = categories::insert_category(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let category = returned_data.unwrap();
+
+        // Thread
+        let insert_data = InsertThread {
+            category_id: category.id,
+            user_id: user.id,
+            title: "TestTitle".to_string(),
+            description: "TestDescription".to_string(),
+        };
+        let returned_data = threads::insert_thread(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let thread = returned_data.unwrap();
+
+        // Comment
+        let insert_data = InsertComment {
+            thread_id: thread.id,
+            user_id: user.id,
+            parent_id: None,
+            content: "TestContent".to_string(),
+        };
+        let returned_data = comments::insert_comment(&con, insert_data);
+        assert!(returned_data.is_ok());
+        let comment = returned_data.unwrap();
+
+        // Delete
+        assert!(delete_user(&con, user.id.into(), None, true).is_ok());
+        assert!(get_user(&con, user.id.into()).is_err());
+
+        // The content is kept, but anonymized and hidden
+        let returned_data = threads::get_thread(&con, thread.id.into(), true);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.user_id, DELETED_USER_ID);
+        assert!(returned_data.hidden);
+
+        let returned_data = comments::get_comment(&con, comment.id.into(), true);
+        assert!(returned_data.is_ok());
+        let returned_data = returned_data.unwrap();
+        assert_eq!(returned_data.user_id, DELETED_USER_ID);
+        assert_eq!(returned_data.content, DELETED_CONTENT);
+
+        // The placeholder can not be deleted
+        assert!(delete_user(&con, DELETED_USER_ID.into(), None, false).is_err());
+    }
+
+    #[test]
+    fn placeholder_username_taken() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        // The placeholder usernames can not be registered
+        let insert_data = InsertUser {
+            id: 51,
+            username: "Deleted".to_string(),
+        };
+        let returned_data = insert_user(&con, insert_data);
+        assert_eq!(returned_data.unwrap_err().kind(), IntErrorKind::UsernameTaken);
+
+        con.test_transaction::<_, IntError, _>(|| {
+            use crate::db::schema::users::dsl;
+            use diesel::connection::SimpleConnection;
+
+            // A user which registered "Deleted" before it was reserved, and no
+            // placeholder yet
+            con.batch_execute("SET FOREIGN_KEY_CHECKS = 0")?;
+            diesel::delete(dsl::users.filter(dsl::id.eq(DELETED_USER_ID))).execute(&con)?;
+            con.batch_execute("SET FOREIGN_KEY_CHECKS = 1")?;
+            InsertUser {
+                id: 51,
+                username: "Deleted".to_string(),
+            }.insert_into(dsl::users)
+            .execute(&con)?;
+            insert_user(
+                &con,
+                InsertUser {
+                    id: 52,
+                    username: "TestUser52".to_string(),
+                },
+            )?;
+
+            // The content of the deleted user goes to a placeholder with the
+            // next free username
+            let category = categories::insert_category(
+                &con,
+                InsertCategory {
+                    title: "TestTitle".to_string(),
+                    description: "TestDescription".to_string(),
+                },
+            )?;
+            let thread = threads::insert_thread(
+                &con,
+                InsertThread {
+                    category_id: category.id,
+                    user_id: 52,
+                    title: "TestTitle".to_string(),
+                    description: "TestDescription".to_string(),
+                },
+            )?;
+            assert!(delete_user(&con, 52.into(), None, false).is_ok());
+
+            assert_eq!(get_user(&con, DELETED_USER_ID.into())?.username, "[deleted]");
+            assert_eq!(get_user(&con, 51.into())?.username, "Deleted");
+            assert_eq!(
+                threads::get_thread(&con, thread.id.into(), true)?.user_id,
+                DELETED_USER_ID
+            );
+            Ok(())
+        });
+    }
 }
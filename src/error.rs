@@ -1,8 +1,8 @@
-use failure::{Backtrace, Context, Fail};
+use failure::{Backtrace, Context, Fail, ResultExt};
 use std::convert::From;
 use std::fmt::{self, Display};
 
-use datatypes::content::responses::ContentError;
+use crate::payloads::ServiceError;
 
 /// The type of an internal error ([struct.Error.html])
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail)]
@@ -17,6 +17,30 @@ pub enum ErrorKind {
     ServerError,
     #[fail(display = "invalid id")]
     InvalidId,
+    #[fail(display = "the comment can not be accepted as an answer")]
+    InvalidAnswer,
+    #[fail(display = "the username is already taken")]
+    UsernameTaken,
+    #[fail(display = "the username is reserved for its previous owner")]
+    UsernameReserved,
+    #[fail(display = "the user was renamed too recently")]
+    RenameCooldown,
+    #[fail(display = "failed to read or write data")]
+    IoError,
+    #[fail(display = "the data is malformed")]
+    InvalidData,
+    #[fail(display = "the operation was refused")]
+    Refused,
+    #[fail(display = "the configuration is invalid")]
+    InvalidConfig,
+    #[fail(display = "no database connection is available, the service is busy")]
+    ServiceBusy,
+    #[fail(display = "invalid log level")]
+    InvalidLogLevel,
+    #[fail(display = "the request queue is full, the service is overloaded")]
+    Overloaded,
+    #[fail(display = "the request was queued past its deadline")]
+    DeadlineExceeded,
 }
 
 /// An internal error which can be used for debugging or error tracing
@@ -95,14 +119,32 @@ impl From<Context<ErrorKind>> for Error {
     }
 }
 
-impl Into<ContentError> for Error {
-    fn into(self) -> ContentError {
+impl From<diesel::result::Error> for Error {
+    fn from(e: diesel::result::Error) -> Error {
+        e.context(ErrorKind::QueryError).into()
+    }
+}
+
+impl Into<ServiceError> for Error {
+    fn into(self) -> ServiceError {
         match self.kind() {
-            ErrorKind::ConnectionError => ContentError::InternalServerError,
-            ErrorKind::QueryError => ContentError::InternalServerError,
-            ErrorKind::ContentNotFound => ContentError::MissingContent,
-            ErrorKind::ServerError => ContentError::InternalServerError,
-            ErrorKind::InvalidId => ContentError::InvalidId,
+            ErrorKind::ConnectionError => ServiceError::InternalServerError,
+            ErrorKind::QueryError => ServiceError::InternalServerError,
+            ErrorKind::ContentNotFound => ServiceError::MissingContent,
+            ErrorKind::ServerError => ServiceError::InternalServerError,
+            ErrorKind::InvalidId => ServiceError::InvalidId,
+            ErrorKind::InvalidAnswer => ServiceError::InvalidId,
+            ErrorKind::UsernameTaken => ServiceError::UsernameTaken,
+            ErrorKind::UsernameReserved => ServiceError::UsernameReserved,
+            ErrorKind::RenameCooldown => ServiceError::RenameCooldown,
+            ErrorKind::IoError => ServiceError::InternalServerError,
+            ErrorKind::InvalidData => ServiceError::InternalServerError,
+            ErrorKind::Refused => ServiceError::InternalServerError,
+            ErrorKind::InvalidConfig => ServiceError::InternalServerError,
+            ErrorKind::ServiceBusy => ServiceError::ServiceBusy,
+            ErrorKind::InvalidLogLevel => ServiceError::InvalidId,
+            ErrorKind::Overloaded => ServiceError::Overloaded,
+            ErrorKind::DeadlineExceeded => ServiceError::DeadlineExceeded,
         }
     }
 }
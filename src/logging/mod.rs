@@ -0,0 +1,334 @@
+mod rotate;
+
+use log::LevelFilter;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use self::rotate::RotatingFile;
+use crate::config::{LogConfig, LogFormat};
+
+/// The noisy dependencies which are kept at info when debugging
+const QUIET_MODULES: &[&str] = &[
+    "tokio_core",
+    "tokio_reactor",
+    "tokio_proto",
+    "tokio_io",
+    "mio",
+    "tarpc",
+];
+
+/// The time logging was set up, which makes request ids unique across runs
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+/// The number of the next request id
+static NEXT_REQUEST: AtomicUsize = AtomicUsize::new(0);
+/// The longest trace id accepted from a caller
+const MAX_TRACE_ID_LEN: usize = 64;
+
+thread_local! {
+    /// The id of the request handled by the current thread, if any
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Generates an id for a new request
+fn new_request_id() -> String {
+    format!(
+        "{:x}-{:x}",
+        STARTED.load(Ordering::Relaxed),
+        NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The id of a request, which is the trace id given by the caller if there is
+/// one, or else a new id
+///
+/// Trace ids which are too long or contain anything but alphanumerics, `-`,
+/// `_`, `.` and `:` are replaced, so that they can not garble the logs.
+pub fn request_id(trace_id: Option<String>) -> String {
+    match trace_id {
+        Some(trace_id) => {
+            let valid = !trace_id.is_empty()
+                && trace_id.len() <= MAX_TRACE_ID_LEN
+                && trace_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+            if valid {
+                trace_id
+            } else {
+                let request_id = new_request_id();
+                warn!("Replacing invalid trace id with request id {}", request_id);
+                request_id
+            }
+        }
+        None => new_request_id(),
+    }
+}
+
+/// Attaches a request id to all log records of the current thread, until the
+/// returned guard is dropped
+pub fn enter_request(request_id: String) -> RequestGuard {
+    let previous = REQUEST_ID.with(|id| id.replace(Some(request_id)));
+    RequestGuard(previous)
+}
+
+/// Restores the previous request id of the thread when dropped
+#[derive(Debug)]
+pub struct RequestGuard(Option<String>);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        REQUEST_ID.with(|id| *id.borrow_mut() = previous);
+    }
+}
+
+/// Formats the request id of the current thread as `[id]`, if any
+fn request_tag() -> String {
+    REQUEST_ID.with(|id| {
+        id.borrow()
+            .as_ref()
+            .map(|id| format!("[{}]", id))
+            .unwrap_or_default()
+    })
+}
+
+/// The log levels, which can be changed while running
+#[derive(Debug)]
+struct Levels {
+    default: LevelFilter,
+    /// The levels of modules and their submodules, overriding the default
+    modules: BTreeMap<String, LevelFilter>,
+}
+
+impl Levels {
+    /// The level of a target, which is the level of its closest module
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target == module.as_str()
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            }).max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |(_, &level)| level)
+    }
+
+    /// The most verbose of the levels, which is the level records have to
+    /// be at to be considered at all
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .values()
+            .cloned()
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+/// A handle to the log levels, which can be changed while running
+#[derive(Debug, Clone)]
+pub struct LogLevels(Arc<RwLock<Levels>>);
+
+impl LogLevels {
+    /// The levels given by the number of `-v` arguments
+    fn from_verbosity(verbosity: u64) -> Self {
+        let (default, quiet) = match verbosity {
+            0 => (LevelFilter::Info, false),
+            1 => (LevelFilter::Debug, true),
+            2 => (LevelFilter::Trace, true),
+            _3_or_more => (LevelFilter::Trace, false),
+        };
+        let modules = if quiet {
+            QUIET_MODULES
+                .iter()
+                .map(|module| (module.to_string(), LevelFilter::Info))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+
+        LogLevels(Arc::new(RwLock::new(Levels { default, modules })))
+    }
+
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let levels = self.0.read().unwrap_or_else(|e| e.into_inner());
+        metadata.level() <= levels.level(metadata.target())
+    }
+
+    /// Sets the level of a module and its submodules, or the default level if
+    /// `module` is `None`
+    ///
+    /// A module level of `None` removes the level of the module, so that it
+    /// falls back to the default.
+    pub fn set(&self, module: Option<&str>, level: Option<LevelFilter>) {
+        {
+            let mut levels = self.0.write().unwrap_or_else(|e| e.into_inner());
+            match (module, level) {
+                (Some(module), Some(level)) => {
+                    levels.modules.insert(module.to_string(), level);
+                }
+                (Some(module), None) => {
+                    levels.modules.remove(module);
+                }
+                (None, Some(level)) => levels.default = level,
+                (None, None) => {}
+            }
+            log::set_max_level(levels.max());
+        }
+
+        // Logged after releasing the lock, as logging reads the levels
+        info!(
+            "Set log level of {} to {}",
+            module.unwrap_or("all modules"),
+            level.map_or("the default".to_string(), |level| level.to_string())
+        );
+    }
+
+    /// The default level and the levels of modules
+    pub fn get(&self) -> (LevelFilter, Vec<(String, LevelFilter)>) {
+        let levels = self.0.read().unwrap_or_else(|e| e.into_inner());
+        let modules = levels
+            .modules
+            .iter()
+            .map(|(module, &level)| (module.clone(), level))
+            .collect();
+        (levels.default, modules)
+    }
+}
+
+/// A log record in the JSON format
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: String,
+    level: String,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// Formats a log record as a single line of JSON
+fn format_json(out: fern::FormatCallback, message: &std::fmt::Arguments, record: &log::Record) {
+    let record = JsonRecord {
+        timestamp: chrono::Local::now().to_rfc3339(),
+        level: record.level().to_string(),
+        target: record.target(),
+        message: message.to_string(),
+        request_id: REQUEST_ID.with(|id| id.borrow().clone()),
+    };
+    match serde_json::to_string(&record) {
+        Ok(json) => out.finish(format_args!("{}", json)),
+        Err(_) => out.finish(*message),
+    }
+}
+
+/// Sets up logging to the configured files and the console
+///
+/// With `stderr` set, console logging goes to stderr so that stdout is free
+/// for the output of subcommands. The returned handle changes the log levels.
+pub fn setup_logging(
+    verbosity: u64,
+    stderr: bool,
+    config: &LogConfig,
+) -> Result<LogLevels, fern::InitError> {
+    STARTED.store(chrono::Local::now().timestamp() as usize, Ordering::Relaxed);
+    let levels = LogLevels::from_verbosity(verbosity);
+    let filter_levels = levels.clone();
+    let mut base_config = fern::Dispatch::new()
+        .level(LevelFilter::Trace)
+        .filter(move |metadata| filter_levels.enabled(metadata));
+
+    // Separate file config so we can include year, month and day in file logs
+    let mut file_config = match config.format {
+        LogFormat::Text => fern::Dispatch::new().format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}]{} {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                request_tag(),
+                message
+            ))
+        }),
+        LogFormat::Json => fern::Dispatch::new().format(format_json),
+    };
+    let max_age = match config.rotate_hours {
+        0 => None,
+        hours => Some(chrono::Duration::hours(hours as i64)),
+    };
+    for path in &config.files {
+        let file = RotatingFile::open(
+            path.clone(),
+            config.max_size_mb * 1024 * 1024,
+            max_age,
+            config.keep,
+        )?;
+        file_config = file_config.chain(Box::new(file) as Box<dyn Write + Send>);
+    }
+
+    let console: fern::Output = if stderr {
+        io::stderr().into()
+    } else {
+        io::stdout().into()
+    };
+
+    let stdout_config = match config.format {
+        LogFormat::Text => fern::Dispatch::new().format(|out, message, record| {
+            // special format for debug messages coming from our own crate.
+            if record.level() > LevelFilter::Info && record.target() == "controller" {
+                out.finish(format_args!(
+                    "---\nDEBUG: {}{}: {}\n---",
+                    chrono::Local::now().format("%H:%M:%S"),
+                    request_tag(),
+                    message
+                ))
+            } else {
+                out.finish(format_args!(
+                    "[{}][{}][{}]{} {}",
+                    chrono::Local::now().format("%H:%M"),
+                    record.target(),
+                    record.level(),
+                    request_tag(),
+                    message
+                ))
+            }
+        }),
+        LogFormat::Json => fern::Dispatch::new().format(format_json),
+    }.chain(console);
+
+    base_config = base_config.chain(file_config);
+    if config.console {
+        base_config = base_config.chain(stdout_config);
+    }
+    base_config.apply()?;
+    let max_level = levels.0.read().unwrap_or_else(|e| e.into_inner()).max();
+    log::set_max_level(max_level);
+
+    Ok(levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_levels() {
+        let levels = LogLevels::from_verbosity(1);
+        levels.set(Some("controller::db"), Some(LevelFilter::Trace));
+
+        let levels = levels.0.read().unwrap();
+        assert_eq!(levels.level("controller"), LevelFilter::Debug);
+        assert_eq!(levels.level("controller::db::users"), LevelFilter::Trace);
+        assert_eq!(levels.level("controller::dbx"), LevelFilter::Debug);
+        assert_eq!(levels.level("tarpc::server"), LevelFilter::Info);
+        assert_eq!(levels.max(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn trace_ids() {
+        assert_eq!(request_id(Some("gateway-1f3a:42".to_string())), "gateway-1f3a:42");
+        assert_ne!(request_id(Some("two\nlines".to_string())), "two\nlines");
+        assert_ne!(request_id(Some("a".repeat(MAX_TRACE_ID_LEN + 1))).len(), MAX_TRACE_ID_LEN + 1);
+        assert!(!request_id(None).is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+//! A log file which is rotated when it gets too large or too old
+use chrono::{DateTime, Duration, Local};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// A log file which is renamed to `<path>.1` when it exceeds its size or age,
+/// shifting older files up to `<path>.<keep>` and removing the oldest
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: DateTime<Local>,
+    /// The size in bytes to rotate at, or 0 for any size
+    max_size: u64,
+    /// The age to rotate at, if any
+    max_age: Option<Duration>,
+    /// The number of rotated files to keep
+    keep: usize,
+    /// Whether the last write ended a line, as files are only rotated
+    /// between lines
+    line_start: bool,
+}
+
+impl RotatingFile {
+    /// Opens a log file for appending
+    pub fn open(
+        path: PathBuf,
+        max_size: u64,
+        max_age: Option<Duration>,
+        keep: usize,
+    ) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            opened: Local::now(),
+            max_size,
+            max_age,
+            keep,
+            line_start: true,
+        })
+    }
+
+    /// The path of the `n`th rotated file
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn needs_rotation(&self) -> bool {
+        let too_large = self.max_size > 0 && self.size >= self.max_size;
+        let too_old = self
+            .max_age
+            .map_or(false, |max_age| Local::now() - self.opened >= max_age);
+        self.line_start && (too_large || too_old)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.keep > 0 {
+            let oldest = self.rotated_path(self.keep);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for n in (1..self.keep).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = Local::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation() {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("controller-rotate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.log");
+
+        let mut file = RotatingFile::open(path.clone(), 10, None, 2).unwrap();
+        for line in 0..5 {
+            // A line is never split between files
+            write!(file, "line {}", line).unwrap();
+            writeln!(file, " of 5").unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 4 of 5\n");
+        assert_eq!(fs::read_to_string(dir.join("test.log.1")).unwrap(), "line 3 of 5\n");
+        assert_eq!(fs::read_to_string(dir.join("test.log.2")).unwrap(), "line 2 of 5\n");
+        assert!(!dir.join("test.log.3").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,16 +3,21 @@
 #![feature(try_from)]
 #![plugin(tarpc_plugins)]
 
+extern crate backtrace;
 extern crate datatypes;
 #[macro_use]
 extern crate diesel;
 extern crate dotenv;
 extern crate fern;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 
 extern crate chrono;
 extern crate clap;
@@ -22,27 +27,29 @@ extern crate failure;
 extern crate failure_derive;
 extern crate futures;
 extern crate futures_cpupool;
+extern crate num_cpus;
 #[macro_use]
 extern crate tarpc;
 extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_signal;
 
 #[macro_use]
 pub mod macros;
+pub mod config;
 pub mod db;
 pub mod error;
 pub mod logging;
+pub mod mentions;
 pub mod migration;
+pub mod payloads;
 pub mod server;
+pub mod tools;
 pub mod types;
 
 use dotenv::dotenv;
-use std::net::{SocketAddr, ToSocketAddrs};
+use failure::ResultExt;
 
-use self::db::categories::delete_all_categories;
-use self::db::comments::delete_all_comments;
-use self::db::establish_connection;
-use self::db::threads::delete_all_threads;
-use self::db::users::delete_all_users;
 use self::server::Server;
 
 // Include internal error type as Int[ernal]Error
@@ -62,65 +69,186 @@ fn run() -> IntResult<()> {
                 .long("verbose")
                 .multiple(true)
                 .help("Increases logging verbosity each use for up to 3 times"),
-        ).arg(
-            clap::Arg::with_name("clear")
-                .short("c")
-                .long("clear")
-                .multiple(true)
-                .help("Clears the database on startup"),
         ).arg(
             clap::Arg::with_name("migrate")
                 .short("m")
                 .long("migrate")
                 .multiple(true)
                 .help("Runs db migration"),
+        ).arg(
+            clap::Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help("The config file to read instead of 'controller.toml'"),
+        ).arg(
+            clap::Arg::with_name("database-url")
+                .long("database-url")
+                .takes_value(true)
+                .help("Overrides the url of the database"),
+        ).arg(
+            clap::Arg::with_name("pool-size")
+                .long("pool-size")
+                .takes_value(true)
+                .help("Overrides the maximum number of database connections"),
+        ).arg(
+            clap::Arg::with_name("address")
+                .long("address")
+                .takes_value(true)
+                .help("Overrides the address to listen on"),
+        ).arg(
+            clap::Arg::with_name("workers")
+                .long("workers")
+                .takes_value(true)
+                .help("Overrides the number of threads running reads"),
+        ).arg(
+            clap::Arg::with_name("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .help("Overrides the file to log to"),
+        ).arg(
+            clap::Arg::with_name("log-format")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .help("Overrides the format of log records"),
+        ).subcommand(
+            clap::SubCommand::with_name("export-user")
+                .about("Exports everything stored about a user as a JSON document")
+                .arg(
+                    clap::Arg::with_name("ID")
+                        .required(true)
+                        .help("The id of the user"),
+                ).arg(
+                    clap::Arg::with_name("FILE")
+                        .help("The file to write to, or '-' for stdout (the default)"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("export")
+                .about("Exports the whole forum as a JSON Lines snapshot")
+                .arg(
+                    clap::Arg::with_name("FILE")
+                        .help("The file to write to, or '-' for stdout (the default)"),
+                ).arg(
+                    clap::Arg::with_name("anonymize")
+                        .long("anonymize")
+                        .help("Replaces names and text with generated text of the same length"),
+                ).arg(
+                    clap::Arg::with_name("salt")
+                        .long("salt")
+                        .takes_value(true)
+                        .requires("anonymize")
+                        .help("Changes the generated text, which is the same for the same salt"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("import")
+                .about("Imports a JSON Lines snapshot in a single transaction")
+                .arg(
+                    clap::Arg::with_name("FILE")
+                        .help("The file to read from, or '-' for stdin (the default)"),
+                ).arg(
+                    clap::Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Checks the snapshot against the database without importing it"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("seed")
+                .about("Generates a synthetic forum after the existing content")
+                .arg(
+                    clap::Arg::with_name("scale")
+                        .long("scale")
+                        .takes_value(true)
+                        .help("The size of the forum, 1 being 100 users and 250 threads"),
+                ).arg(
+                    clap::Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("The seed of the generator, the same seed gives the same forum"),
+                ),
+        ).subcommand(
+            clap::SubCommand::with_name("db")
+                .about("Maintains the database")
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    clap::SubCommand::with_name("reset")
+                        .about("Deletes all content, or a single category or thread")
+                        .arg(
+                            clap::Arg::with_name("yes")
+                                .short("y")
+                                .long("yes")
+                                .help("Resets without asking for confirmation"),
+                        ).arg(
+                            clap::Arg::with_name("force")
+                                .long("force")
+                                .help("Resets non-local and production databases"),
+                        ).arg(
+                            clap::Arg::with_name("category")
+                                .long("category")
+                                .takes_value(true)
+                                .conflicts_with("thread")
+                                .help("Only deletes the category with this id and its content"),
+                        ).arg(
+                            clap::Arg::with_name("thread")
+                                .long("thread")
+                                .takes_value(true)
+                                .help("Only deletes the thread with this id and its comments"),
+                        ),
+                ).subcommand(
+                    clap::SubCommand::with_name("check")
+                        .about("Reports inconsistencies which the schema does not prevent")
+                        .arg(
+                            clap::Arg::with_name("fix")
+                                .long("fix")
+                                .help("Repairs the inconsistencies which can be safely repaired"),
+                        ).arg(
+                            clap::Arg::with_name("json")
+                                .long("json")
+                                .help("Writes the report as JSON"),
+                        ),
+                ),
         ).get_matches();
 
+    // Configuration
+    let config = config::Config::load(&cmd_arguments).map_err(|e| {
+        eprintln!("Invalid configuration: {}", e);
+        IntErrorKind::InvalidConfig
+    })?;
+    db::set_limits(&config.limits);
+    db::set_slow_query_log(&config.database);
+    let database_url = config.database.url.as_str();
+
     // Logging
     let verbosity: u64 = cmd_arguments.occurrences_of("verbose");
-    logging::setup_logging(verbosity).expect("failed to initialize logging");
+    let subcommand = cmd_arguments.subcommand_name().is_some();
+    let log_levels = logging::setup_logging(verbosity, subcommand, &config.log)
+        .expect("failed to initialize logging");
 
-    // Get database url and other environment variables
-    let database_url = std::env::var("CONTROLLER_DATABASE_URL").expect(
-        "CONTROLLER_DATABASE_URL must be set as an environment variable or in a '.env' file",
-    );
+    // Subcommands
+    match cmd_arguments.subcommand() {
+        ("export-user", Some(args)) => return tools::user_data::run(database_url, args),
+        ("export", Some(args)) => return tools::snapshot::run_export(database_url, args),
+        ("import", Some(args)) => return tools::snapshot::run_import(database_url, args),
+        ("seed", Some(args)) => return tools::seed::run(database_url, args),
+        ("db", Some(args)) => match args.subcommand() {
+            ("reset", Some(args)) => return tools::reset::run(database_url, args),
+            ("check", Some(args)) => return tools::check::run(database_url, args),
+            _ => {}
+        },
+        _ => {}
+    }
 
     // Server
-    let address = match std::env::var("CONTROLLER_ADDRESS") {
-        Ok(value) => value
-            .to_socket_addrs()
-            .expect("Unable to perform CONTROLLER_ADDRESS resolving")
-            .next()
-            .expect(&format!("Unable to resolve '{}'", value)),
-        Err(_) => {
-            warn!("CONTROLLER_ADDRESS is not set, using '127.0.0.1:10000'");
-            SocketAddr::from(([127, 0, 0, 1], 10000))
-        }
-    };
+    let address = config.address().context(IntErrorKind::InvalidConfig)?;
 
     info!("Setting up server");
-    let server = Server::try_new(&database_url)?;
+    let server = Server::try_new(&config, log_levels)?;
 
     //Migrate
     let migrate: u64 = cmd_arguments.occurrences_of("migrate");
     if migrate > 0 {
         info!("Running db migration");
-        let _ = migration::run(&database_url);
-    }
-
-    // Clear db
-    let clear: u64 = cmd_arguments.occurrences_of("clear");
-    if clear > 0 {
-        info!("Clearing database");
-
-        match establish_connection(&database_url) {
-            Err(e) => warn!("Failed to connect to db! {}", e),
-            Ok(conn) => {
-                delete_all_comments(&conn)?;
-                delete_all_threads(&conn)?;
-                delete_all_categories(&conn)?;
-                delete_all_users(&conn)?;
-            }
+        if let Err(e) = migration::run(database_url) {
+            error!("Unable to migrate the database: {}", e);
         }
     }
 
@@ -0,0 +1,83 @@
+//! Parsing of `@username` mentions in the content of comments
+
+/// The longest username which can be mentioned (the width of the column)
+const MAX_USERNAME_LEN: usize = 20;
+
+/// The most users which can be mentioned in a single comment
+const MAX_MENTIONS: usize = 20;
+
+fn is_username_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Finds all the usernames mentioned as `@username` in a text
+///
+/// A mention has to start the text or follow a character which can not be a
+/// part of a username, so that e.g. email addresses are not treated as
+/// mentions. Usernames are deduplicated case-insensitively in the order they
+/// first appear.
+pub fn parse_mentions(content: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    let mut prev = None;
+    let mut chars = content.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '@' && !prev.map_or(false, is_username_char) {
+            let start = i + 1;
+            let mut end = start;
+            while let Some(&(j, c)) = chars.peek() {
+                if !is_username_char(c) {
+                    break;
+                }
+                end = j + c.len_utf8();
+                chars.next();
+            }
+
+            let username = &content[start..end];
+            if !username.is_empty()
+                && username.len() <= MAX_USERNAME_LEN
+                && !mentions.iter().any(|m| m.eq_ignore_ascii_case(username))
+            {
+                mentions.push(username.to_owned());
+                if mentions.len() == MAX_MENTIONS {
+                    break;
+                }
+            }
+            prev = content[..end].chars().next_back();
+        } else {
+            prev = Some(c);
+        }
+    }
+
+    mentions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_mentions("@alice and @bob, meet @carol_1!"),
+            vec!["alice", "bob", "carol_1"]
+        );
+        assert!(parse_mentions("no mentions here").is_empty());
+    }
+
+    #[test]
+    fn ignore_emails_and_lone_at() {
+        assert!(parse_mentions("mail me at alice@example.com").is_empty());
+        assert!(parse_mentions("meet @ noon").is_empty());
+    }
+
+    #[test]
+    fn deduplicate() {
+        assert_eq!(parse_mentions("@Alice @alice @ALICE"), vec!["Alice"]);
+    }
+
+    #[test]
+    fn too_long() {
+        assert!(parse_mentions("@abcdefghijklmnopqrstuvwxyz").is_empty());
+    }
+}
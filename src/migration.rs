@@ -1,14 +1,37 @@
 use diesel::prelude::*;
 use diesel::sql_query;
+use diesel::sql_types::Text;
+use failure::ResultExt;
 
-use crate::db::establish_connection;
+use crate::db::{establish_connection, DbConn};
+use crate::types::{SchemaObjects, SchemaVersion};
 use crate::{IntErrorKind, IntResult};
 
-pub fn run(database_url: &str) -> IntResult<()> {
-    let con = establish_connection(database_url)?;
+/// A migration in `migrations/`
+struct Migration {
+    version: &'static str,
+    /// A change made by the migration, by which it is detected in databases
+    /// which were migrated before the versions were recorded
+    change: Change,
+    statements: &'static [&'static str],
+}
 
-    sql_query(
-        r#"CREATE TABLE users (
+/// A table, a column of a table, an index of a table or a column which was
+/// dropped from a table
+enum Change {
+    Table(&'static str),
+    Column(&'static str, &'static str),
+    Index(&'static str, &'static str),
+    DroppedColumn(&'static str, &'static str),
+}
+
+/// The migrations in `migrations/`, in the order they are applied
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: "20180921200026",
+        change: Change::Table("users"),
+        statements: &[
+            r#"CREATE TABLE users (
 
   id INT UNSIGNED NOT NULL,
   username VARCHAR(20) NOT NULL,
@@ -17,11 +40,13 @@ pub fn run(database_url: &str) -> IntResult<()> {
 
   PRIMARY KEY (id)
 );"#,
-    ).execute(&con)
-    .map_err(|_| IntErrorKind::QueryError)?;
-
-    sql_query(
-        r#"CREATE TABLE categories (
+        ],
+    },
+    Migration {
+        version: "20180921200038",
+        change: Change::Table("categories"),
+        statements: &[
+            r#"CREATE TABLE categories (
 
   id INT UNSIGNED NOT NULL AUTO_INCREMENT,
   title VARCHAR(45) NOT NULL,
@@ -30,11 +55,13 @@ pub fn run(database_url: &str) -> IntResult<()> {
 
   PRIMARY KEY (id)
 );"#,
-    ).execute(&con)
-    .map_err(|_| IntErrorKind::QueryError)?;
-
-    sql_query(
-        r#"CREATE TABLE threads (
+        ],
+    },
+    Migration {
+        version: "20180921200050",
+        change: Change::Table("threads"),
+        statements: &[
+            r#"CREATE TABLE threads (
 
   id INT UNSIGNED NOT NULL AUTO_INCREMENT,
   category_id INT UNSIGNED NOT NULL,
@@ -52,11 +79,13 @@ pub fn run(database_url: &str) -> IntResult<()> {
   FOREIGN KEY (user_id)
     REFERENCES users(id)
 );"#,
-    ).execute(&con)
-    .map_err(|_| IntErrorKind::QueryError)?;
-
-    sql_query(
-        r#"CREATE TABLE comments (
+        ],
+    },
+    Migration {
+        version: "20180921200057",
+        change: Change::Table("comments"),
+        statements: &[
+            r#"CREATE TABLE comments (
 
   id INT UNSIGNED NOT NULL AUTO_INCREMENT,
   thread_id INT UNSIGNED NOT NULL,
@@ -76,9 +105,484 @@ pub fn run(database_url: &str) -> IntResult<()> {
 
   FOREIGN KEY (user_id)
     REFERENCES users(id)
+);"#,
+        ],
+    },
+    Migration {
+        version: "20181015120000",
+        change: Change::Column("threads", "answer_id"),
+        statements: &[
+            r#"ALTER TABLE categories
+  ADD COLUMN qa BOOLEAN NOT NULL DEFAULT 0;"#,
+            r#"ALTER TABLE threads
+  ADD COLUMN answer_id INT UNSIGNED NULL,
+
+  ADD CONSTRAINT threads_answer_fk
+    FOREIGN KEY (answer_id)
+    REFERENCES comments(id)
+    ON DELETE SET NULL;"#,
+        ],
+    },
+    Migration {
+        version: "20181016120000",
+        change: Change::Table("notifications"),
+        statements: &[
+            r#"CREATE TABLE thread_subscriptions (
+
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NOT NULL,
+
+  PRIMARY KEY (user_id, thread_id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE
+);"#,
+            r#"CREATE TABLE category_subscriptions (
+
+  user_id INT UNSIGNED NOT NULL,
+  category_id INT UNSIGNED NOT NULL,
+
+  PRIMARY KEY (user_id, category_id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (category_id)
+    REFERENCES categories(id)
+    ON DELETE CASCADE
+);"#,
+            r#"CREATE TABLE notifications (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NOT NULL,
+  comment_id INT UNSIGNED NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+  seen BOOLEAN NOT NULL DEFAULT 0,
+
+  PRIMARY KEY (id),
+  INDEX (user_id, seen),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE
+);"#,
+        ],
+    },
+    Migration {
+        version: "20181017120000",
+        change: Change::Column("notifications", "mention"),
+        statements: &[
+            r#"CREATE TABLE mentions (
+
+  comment_id INT UNSIGNED NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (comment_id, user_id),
+  INDEX (user_id),
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE
+);"#,
+            r#"ALTER TABLE notifications
+  ADD COLUMN mention BOOLEAN NOT NULL DEFAULT 0;"#,
+        ],
+    },
+    Migration {
+        version: "20181018120000",
+        change: Change::Table("thread_reads"),
+        statements: &[
+            r#"CREATE TABLE thread_reads (
+
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NOT NULL,
+  comment_id INT UNSIGNED NOT NULL,
+
+  PRIMARY KEY (user_id, thread_id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE
+);"#,
+        ],
+    },
+    Migration {
+        version: "20181019120000",
+        change: Change::Table("bookmarks"),
+        statements: &[
+            r#"CREATE TABLE bookmarks (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  user_id INT UNSIGNED NOT NULL,
+  thread_id INT UNSIGNED NULL,
+  comment_id INT UNSIGNED NULL,
+  note VARCHAR(255) NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id),
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE
+);"#,
+        ],
+    },
+    // Renames the users whose usernames differ only in case from an older
+    // one, recording the renames in the audit log, which is created early
+    Migration {
+        version: "20181020120000",
+        change: Change::Index("users", "users_username_unique"),
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS audit_log (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  action VARCHAR(32) NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  actor_id INT UNSIGNED NULL,
+  details TEXT NOT NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+  INDEX (user_id)
+);"#,
+            r#"ALTER TABLE users
+  MODIFY username VARCHAR(20) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL;"#,
+            r#"INSERT INTO audit_log (action, user_id, details)
+  SELECT DISTINCT 'rename_user', u.id,
+    CONCAT('duplicate username ', u.username, ' renamed to ',
+      LEFT(u.username, 19 - LENGTH(u.id)), '_', u.id)
+  FROM users u
+  JOIN users o
+    ON o.username = u.username
+    AND o.id < u.id;"#,
+            r#"UPDATE users u
+  JOIN (
+    SELECT DISTINCT d.id FROM users d
+    JOIN users o
+      ON o.username = d.username
+      AND o.id < d.id
+  ) duplicates
+    ON duplicates.id = u.id
+  SET u.username = CONCAT(LEFT(u.username, 19 - LENGTH(u.id)), '_', u.id);"#,
+            r#"ALTER TABLE users
+  ADD CONSTRAINT users_username_unique UNIQUE (username);"#,
+        ],
+    },
+    Migration {
+        version: "20181021120000",
+        change: Change::Table("username_history"),
+        statements: &[
+            r#"CREATE TABLE username_history (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  user_id INT UNSIGNED NOT NULL,
+  username VARCHAR(20) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL,
+  changed DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+  INDEX (user_id, changed),
+  INDEX (username, changed),
+
+  FOREIGN KEY (user_id)
+    REFERENCES users(id)
+    ON DELETE CASCADE
+);"#,
+        ],
+    },
+    Migration {
+        version: "20181022120000",
+        change: Change::Table("audit_log"),
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS audit_log (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  action VARCHAR(32) NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  actor_id INT UNSIGNED NULL,
+  details TEXT NOT NULL,
+  timestamp DATETIME NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (id),
+  INDEX (user_id)
+);"#,
+        ],
+    },
+    Migration {
+        version: "20181023120000",
+        change: Change::Column("users", "last_active"),
+        statements: &[
+            r#"ALTER TABLE users
+  ADD COLUMN joined DATETIME NOT NULL DEFAULT NOW(),
+  ADD COLUMN thread_count INT UNSIGNED NOT NULL DEFAULT 0,
+  ADD COLUMN comment_count INT UNSIGNED NOT NULL DEFAULT 0,
+  ADD COLUMN last_active DATETIME NULL;"#,
+            r#"UPDATE users SET
+  thread_count = (SELECT COUNT(*) FROM threads WHERE threads.user_id = users.id),
+  comment_count = (SELECT COUNT(*) FROM comments WHERE comments.user_id = users.id),
+  last_active = GREATEST(
+    COALESCE((SELECT MAX(timestamp) FROM threads WHERE threads.user_id = users.id), '1000-01-01'),
+    COALESCE((SELECT MAX(timestamp) FROM comments WHERE comments.user_id = users.id), '1000-01-01')
+  );"#,
+            r#"UPDATE users SET
+  last_active = NULL
+  WHERE last_active = '1000-01-01';"#,
+            r#"UPDATE users SET
+  joined = LEAST(
+    joined,
+    COALESCE((SELECT MIN(timestamp) FROM threads WHERE threads.user_id = users.id), joined),
+    COALESCE((SELECT MIN(timestamp) FROM comments WHERE comments.user_id = users.id), joined)
+  );"#,
+        ],
+    },
+    Migration {
+        version: "20181024120000",
+        change: Change::Column("categories", "parent_id"),
+        statements: &[
+            r#"ALTER TABLE categories
+  ADD COLUMN parent_id INT UNSIGNED NULL,
+
+  ADD CONSTRAINT categories_parent_fk
+    FOREIGN KEY (parent_id)
+    REFERENCES categories(id)
+    ON DELETE CASCADE;"#,
+        ],
+    },
+    Migration {
+        version: "20181025120000",
+        change: Change::Table("notification_queue"),
+        statements: &[
+            r#"CREATE TABLE notification_queue (
+
+  id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  thread_id INT UNSIGNED NOT NULL,
+  comment_id INT UNSIGNED NULL,
+
+  PRIMARY KEY (id),
+
+  FOREIGN KEY (thread_id)
+    REFERENCES threads(id)
+    ON DELETE CASCADE,
+
+  FOREIGN KEY (comment_id)
+    REFERENCES comments(id)
+    ON DELETE CASCADE
+);"#,
+        ],
+    },
+    Migration {
+        version: "20181026120000",
+        change: Change::Index("bookmarks", "bookmarks_target_unique"),
+        statements: &[
+            r#"DELETE b FROM bookmarks b
+  JOIN bookmarks o
+    ON o.user_id = b.user_id
+    AND o.comment_id <=> b.comment_id
+    AND (b.comment_id IS NOT NULL OR o.thread_id <=> b.thread_id)
+    AND o.id > b.id;"#,
+            r#"ALTER TABLE bookmarks
+  ADD COLUMN target_thread_id INT UNSIGNED
+    AS (IF(comment_id IS NULL, thread_id, 0)) STORED,
+  ADD COLUMN target_comment_id INT UNSIGNED AS (IFNULL(comment_id, 0)) STORED,
+  ADD CONSTRAINT bookmarks_target_unique
+    UNIQUE (user_id, target_thread_id, target_comment_id);"#,
+        ],
+    },
+    Migration {
+        version: "20181027120000",
+        change: Change::DroppedColumn("users", "thread_count"),
+        statements: &[
+            r#"ALTER TABLE users
+  DROP COLUMN thread_count,
+  DROP COLUMN comment_count,
+  DROP COLUMN last_active;"#,
+        ],
+    },
+    Migration {
+        version: "20181028120000",
+        change: Change::Column("notification_queue", "attempts"),
+        statements: &[
+            r#"ALTER TABLE notification_queue
+  ADD COLUMN attempts INT UNSIGNED NOT NULL DEFAULT 0;"#,
+        ],
+    },
+];
+
+/// The version of the latest migration, which the database has to be at
+pub const SCHEMA_VERSION: &str = "20181028120000";
+
+/// Gets the version of the latest migration applied to the database
+///
+/// The versions are recorded in the same table as the diesel CLI uses, so it
+/// does not matter which of them migrated the database.
+pub fn get_schema_version(con: &DbConn) -> IntResult<Option<String>> {
+    trace!("Getting schema version");
+
+    sql_query("SELECT MAX(version) AS version FROM __diesel_schema_migrations")
+        .get_result::<SchemaVersion>(con)
+        .map(|row| row.version)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to get schema version: {}", e);
+            e.into()
+        })
+}
+
+/// Checks whether a change of a migration is in the database
+fn change_exists(con: &DbConn, change: &Change) -> IntResult<bool> {
+    let objects = match change {
+        Change::Table(table) => sql_query(
+            "SELECT COUNT(*) AS count FROM information_schema.tables \
+             WHERE table_schema = DATABASE() AND table_name = ?",
+        ).bind::<Text, _>(*table)
+        .get_result::<SchemaObjects>(con),
+        Change::Column(table, column) => sql_query(
+            "SELECT COUNT(*) AS count FROM information_schema.columns \
+             WHERE table_schema = DATABASE() AND table_name = ? AND column_name = ?",
+        ).bind::<Text, _>(*table)
+        .bind::<Text, _>(*column)
+        .get_result::<SchemaObjects>(con),
+        Change::Index(table, index) => sql_query(
+            "SELECT COUNT(*) AS count FROM information_schema.statistics \
+             WHERE table_schema = DATABASE() AND table_name = ? AND index_name = ?",
+        ).bind::<Text, _>(*table)
+        .bind::<Text, _>(*index)
+        .get_result::<SchemaObjects>(con),
+        Change::DroppedColumn(table, column) => {
+            return change_exists(con, &Change::Column(table, column)).map(|exists| !exists)
+        }
+    };
+
+    objects
+        .map(|objects| objects.count > 0)
+        .context(IntErrorKind::QueryError)
+        .map_err(|e| {
+            error!("Unable to check the schema for a migration: {}", e);
+            e.into()
+        })
+}
+
+/// Applies the migrations which are missing from the database
+///
+/// Migrations which are not recorded, but whose changes are in the database,
+/// were applied before the versions were recorded and are only recorded, so
+/// that migrating a database more than once does nothing.
+pub fn run(database_url: &str) -> IntResult<()> {
+    let con = establish_connection(database_url)?;
+
+    sql_query(
+        r#"CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (
+  version VARCHAR(50) PRIMARY KEY NOT NULL,
+  run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );"#,
     ).execute(&con)
-    .map_err(|_| IntErrorKind::QueryError)?;
+    .context(IntErrorKind::QueryError)?;
+
+    let applied = sql_query("SELECT version FROM __diesel_schema_migrations")
+        .load::<SchemaVersion>(&con)
+        .context(IntErrorKind::QueryError)?
+        .into_iter()
+        .filter_map(|row| row.version)
+        .collect::<Vec<_>>();
+
+    for migration in MIGRATIONS {
+        if applied.iter().any(|version| version == migration.version) {
+            continue;
+        }
+
+        if change_exists(&con, &migration.change)? {
+            info!("Recording migration {}, which is already applied", migration.version);
+        } else {
+            info!("Applying migration {}", migration.version);
+            for statement in migration.statements {
+                sql_query(*statement)
+                    .execute(&con)
+                    .context(IntErrorKind::QueryError)
+                    .map_err(|e| {
+                        error!("Unable to apply migration {}: {}", migration.version, e);
+                        e
+                    })?;
+            }
+        }
+
+        sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES (?)")
+            .bind::<Text, _>(migration.version)
+            .execute(&con)
+            .context(IntErrorKind::QueryError)?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        let mut migrations = std::fs::read_dir("migrations")
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().unwrap().is_dir())
+            .map(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .split('_')
+                    .next()
+                    .unwrap()
+                    .replace('-', "")
+            }).collect::<Vec<_>>();
+        migrations.sort();
+
+        let versions = MIGRATIONS
+            .iter()
+            .map(|migration| migration.version)
+            .collect::<Vec<_>>();
+        assert_eq!(migrations, versions);
+        assert_eq!(versions.last(), Some(&SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn run_twice() {
+        let url = std::env::var("CONTROLLER_DATABASE_URL").unwrap();
+
+        assert!(run(&url).is_ok());
+        assert!(run(&url).is_ok());
+
+        let con = establish_connection(&url).unwrap();
+        let returned_data = get_schema_version(&con);
+        assert!(returned_data.is_ok());
+        assert_eq!(returned_data.unwrap(), Some(SCHEMA_VERSION.to_string()));
+    }
+}
@@ -0,0 +1,394 @@
+//! Request and response payloads for the RPCs which are specific to the
+//! controller and are not a part of `datatypes`
+use datatypes::content::responses::*;
+use datatypes::valid::ids::*;
+
+use chrono::naive::NaiveDateTime;
+use std::fmt;
+
+/// The error of the RPCs added by the controller, and of the `_traced`
+/// variants of the RPCs of `datatypes`
+///
+/// The first variants match `ContentError`, and the others tell clients
+/// about failures which `ContentError` can not express. `From` converts it
+/// to a `ContentError` for the plain RPCs of `datatypes`, whose clients only
+/// know those.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceError {
+    InvalidId,
+    MissingContent,
+    InternalServerError,
+    /// The username is used by another user, ignoring case
+    UsernameTaken,
+    /// The username was recently given up and is reserved for its previous
+    /// owner
+    UsernameReserved,
+    /// The user was renamed too recently to be renamed again
+    RenameCooldown,
+    /// No database connection became available in time, so the request may
+    /// be retried later
+    ServiceBusy,
+    /// The worker pool of the RPC was full, so the request was rejected
+    /// without being run
+    Overloaded,
+    /// The request was not answered before its deadline
+    DeadlineExceeded,
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ServiceError::InvalidId => "invalid id",
+            ServiceError::MissingContent => "the content does not exist",
+            ServiceError::InternalServerError => "internal server error",
+            ServiceError::UsernameTaken => "the username is already taken",
+            ServiceError::UsernameReserved => "the username is reserved for its previous owner",
+            ServiceError::RenameCooldown => "the user was renamed too recently",
+            ServiceError::ServiceBusy => "the service is busy",
+            ServiceError::Overloaded => "the service is overloaded",
+            ServiceError::DeadlineExceeded => "the deadline of the request was exceeded",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<ServiceError> for ContentError {
+    fn from(error: ServiceError) -> ContentError {
+        match error {
+            ServiceError::MissingContent => ContentError::MissingContent,
+            ServiceError::InternalServerError
+            | ServiceError::ServiceBusy
+            | ServiceError::Overloaded
+            | ServiceError::DeadlineExceeded => ContentError::InternalServerError,
+            ServiceError::InvalidId
+            | ServiceError::UsernameTaken
+            | ServiceError::UsernameReserved
+            | ServiceError::RenameCooldown => ContentError::InvalidId,
+        }
+    }
+}
+
+/// The payload of a request, along with the id of the trace the request
+/// belongs to in the services calling the controller
+///
+/// The trace id tags the log records of the request, and a new id is used if
+/// it is `None` or invalid. The RPCs of `datatypes` keep their plain payloads,
+/// and their `_traced` variants take this envelope instead.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Traced<T> {
+    pub trace_id: Option<String>,
+    pub payload: T,
+}
+
+impl<T> From<T> for Traced<T> {
+    fn from(payload: T) -> Self {
+        Traced {
+            trace_id: None,
+            payload,
+        }
+    }
+}
+
+/// Sets whether a category is a Q&A category or not
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct SetQaPayload {
+    pub id: CategoryId,
+    pub qa: bool,
+}
+
+/// Marks a comment as the accepted answer of a thread
+///
+/// A `comment_id` of `None` removes the currently accepted answer, and
+/// `user_id` has to be the author of the thread.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct AcceptAnswerPayload {
+    pub thread_id: ThreadId,
+    pub comment_id: Option<CommentId>,
+    pub user_id: UserId,
+}
+
+/// Marks a comment as the accepted answer of any thread, which only
+/// moderators may do
+///
+/// The controller does not know who is a moderator, so the gateway has to
+/// authorize the callers of this RPC.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ModerateAnswerPayload {
+    pub thread_id: ThreadId,
+    pub comment_id: Option<CommentId>,
+}
+
+/// The accepted answer of a thread
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct AnswerPayload {
+    pub thread_id: ThreadId,
+    pub comment_id: Option<CommentId>,
+}
+
+/// Gets the threads in a category which are either answered or unanswered
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GetAnsweredThreadsPayload {
+    pub id: CategoryId,
+    pub include_hidden: bool,
+    pub answered: bool,
+}
+
+/// Subscribes (or unsubscribes) a user to new comments in a thread
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct SubscribeThreadPayload {
+    pub thread_id: ThreadId,
+    pub user_id: Option<UserId>,
+    pub subscribe: bool,
+}
+
+/// Subscribes (or unsubscribes) a user to new threads in a category
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct SubscribeCategoryPayload {
+    pub category_id: CategoryId,
+    pub user_id: Option<UserId>,
+    pub subscribe: bool,
+}
+
+/// Gets a page of the notifications of a user, newest first
+///
+/// With `mentions_only` set, only the mentions inbox of the user is returned.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GetNotificationsPayload {
+    pub user_id: Option<UserId>,
+    pub unread_only: bool,
+    pub mentions_only: bool,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+/// Marks notifications of a user as read
+///
+/// When `ids` is `None` all the notifications of the user are marked as read.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReadNotificationsPayload {
+    pub user_id: Option<UserId>,
+    pub ids: Option<Vec<u32>>,
+}
+
+/// A notification about a new thread (`comment_id` is `None`), a new
+/// comment or a mention in a comment
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct NotificationPayload {
+    pub id: u32,
+    pub user_id: UserId,
+    pub thread_id: ThreadId,
+    pub comment_id: Option<CommentId>,
+    pub timestamp: NaiveDateTime,
+    pub seen: bool,
+    pub mention: bool,
+}
+
+/// Gets a page of the comments where a user was mentioned, newest first
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GetMentionsPayload {
+    pub user_id: UserId,
+    pub include_hidden: bool,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+/// Marks a thread as read by a user up to (and including) a comment
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct MarkThreadReadPayload {
+    pub thread_id: ThreadId,
+    pub comment_id: CommentId,
+    pub user_id: Option<UserId>,
+}
+
+/// Gets the threads in a category along with the unread counts of a user
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GetUserThreadsPayload {
+    pub id: CategoryId,
+    pub include_hidden: bool,
+    pub user_id: UserId,
+}
+
+/// Gets a page of the threads with unread comments for a user, the threads
+/// with the most recent activity first
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GetUnreadThreadsPayload {
+    pub user_id: UserId,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+/// A thread along with the number of comments a user has not read yet
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UnreadThreadPayload {
+    pub thread: ThreadPayload,
+    pub unread: u32,
+}
+
+/// The thread or comment a bookmark points to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum BookmarkTarget {
+    Thread(ThreadId),
+    Comment(CommentId),
+}
+
+/// Bookmarks a thread or comment for a user, or updates the note of an
+/// existing bookmark of the same target
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AddBookmarkPayload {
+    pub user_id: Option<UserId>,
+    pub target: BookmarkTarget,
+    pub note: Option<String>,
+}
+
+/// Removes a bookmark of a user
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RemoveBookmarkPayload {
+    pub id: u32,
+    pub user_id: Option<UserId>,
+}
+
+/// Gets a page of the bookmarks of a user, newest first
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GetBookmarksPayload {
+    pub user_id: UserId,
+    pub include_hidden: bool,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookmarkPayload {
+    pub id: u32,
+    pub user_id: UserId,
+    pub target: BookmarkTarget,
+    pub note: Option<String>,
+    pub timestamp: NaiveDateTime,
+}
+
+/// Gets a user by username, ignoring case
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetUserByUsernamePayload {
+    pub username: String,
+}
+
+/// Gets several users at once
+///
+/// Ids without a user are skipped, and the users are returned in the order of
+/// the ids.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetUsersPayload {
+    pub ids: Vec<UserId>,
+}
+
+/// Changes the username of a user
+///
+/// The new username has to follow the same rules as the username of a new
+/// user, can not be taken or reserved by someone else, and users can only
+/// be renamed once per cooldown period.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RenameUserPayload {
+    pub id: Option<UserId>,
+    pub username: String,
+}
+
+/// A previous username of a user, and when it was changed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsernameChangePayload {
+    pub user_id: UserId,
+    pub username: String,
+    pub changed: NaiveDateTime,
+}
+
+/// Deletes a user account
+///
+/// The threads and comments of the user are kept under a placeholder user,
+/// and with `remove_content` set they are also hidden and their text removed.
+/// `actor_id` is the user requesting the deletion, which is recorded in the
+/// audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct DeleteUserPayload {
+    pub id: Option<UserId>,
+    pub actor_id: Option<UserId>,
+    pub remove_content: bool,
+}
+
+/// The profile of a user along with statistics about the activity of the user
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserProfilePayload {
+    pub user: UserPayload,
+    pub joined: NaiveDateTime,
+    pub thread_count: u32,
+    pub comment_count: u32,
+    pub last_active: Option<NaiveDateTime>,
+}
+
+/// Gets a page of the threads and comments of a user, newest first
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct GetUserActivityPayload {
+    pub user_id: UserId,
+    pub include_hidden: bool,
+    pub offset: u32,
+    pub limit: u32,
+}
+
+/// A thread or comment in the activity of a user
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ActivityPayload {
+    Thread(ThreadPayload),
+    Comment(CommentPayload),
+}
+
+/// The liveness of the controller, which is answered without using the
+/// database or the worker pool
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct HealthPayload {
+    /// The number of seconds since the server started
+    pub uptime: u64,
+}
+
+/// Whether the controller is able to serve requests
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReadinessPayload {
+    pub ready: bool,
+    /// Whether a database connection could be taken from the pool
+    pub pool_available: bool,
+    pub connections: u32,
+    pub idle_connections: u32,
+    /// The latest migration applied to the database, if it could be read
+    pub schema_version: Option<String>,
+    /// The latest migration known to this build
+    pub expected_schema_version: String,
+    /// The number of requests queued or running on the worker pool
+    pub queue_depth: u32,
+    pub max_queue_depth: u32,
+}
+
+/// The build of the controller
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VersionPayload {
+    pub version: String,
+    pub git_hash: String,
+    pub schema_version: String,
+    /// The enabled cargo features
+    pub features: Vec<String>,
+}
+
+/// Changes the log level of a module and its submodules, or the default level
+/// if `module` is `None`
+///
+/// The level is one of `off`, `error`, `warn`, `info`, `debug` and `trace`.
+/// A `level` of `None` removes the level of the module.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetLogLevelPayload {
+    pub module: Option<String>,
+    pub level: Option<String>,
+}
+
+/// The current log levels
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogLevelsPayload {
+    pub level: String,
+    pub modules: Vec<(String, String)>,
+}
@@ -0,0 +1,251 @@
+//! A read-through cache of the entities which are read far more often than
+//! they are written
+//!
+//! The services read categories, users and threads through the cache, and
+//! invalidate the entries of the entities they write.
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::config::{CacheConfig, EntityCacheConfig};
+use crate::types::{Category, Thread, User};
+use crate::IntResult;
+
+/// The entries of a cache, along with the settings they are kept by
+#[derive(Debug)]
+struct Entries<K, V> {
+    map: HashMap<K, (Instant, V)>,
+    size: usize,
+    ttl: Duration,
+    /// Incremented by every invalidation, so that a value which was read
+    /// before an invalidation is not stored after it
+    generation: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Entries<K, V> {
+    /// Stores a value, making room by removing the expired entries or else
+    /// the oldest one
+    fn insert(&mut self, key: K, value: V) {
+        if self.map.len() >= self.size && !self.map.contains_key(&key) {
+            let ttl = self.ttl;
+            self.map.retain(|_, (stored, _)| stored.elapsed() < ttl);
+        }
+        if self.map.len() >= self.size && !self.map.contains_key(&key) {
+            let oldest = self
+                .map
+                .iter()
+                .min_by_key(|(_, (stored, _))| *stored)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.map.remove(&oldest);
+            }
+        }
+        self.map.insert(key, (Instant::now(), value));
+    }
+}
+
+/// A cache of one kind of entity
+#[derive(Debug)]
+pub struct EntityCache<K, V> {
+    name: &'static str,
+    entries: Mutex<Entries<K, V>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> EntityCache<K, V> {
+    fn new(name: &'static str, config: EntityCacheConfig) -> Self {
+        EntityCache {
+            name,
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                size: config.size,
+                ttl: Duration::from_secs(config.ttl),
+                generation: 0,
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Entries<K, V>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Changes the size and lifetime of the entries, removing all of them
+    fn configure(&self, config: EntityCacheConfig) {
+        let mut entries = self.lock();
+        entries.size = config.size;
+        entries.ttl = Duration::from_secs(config.ttl);
+        entries.generation += 1;
+        entries.map.clear();
+    }
+
+    /// Gets a value from the cache, or loads and stores it if it is missing
+    /// or expired
+    pub fn get_or_load(&self, key: K, load: impl FnOnce() -> IntResult<V>) -> IntResult<V> {
+        let generation = {
+            let entries = self.lock();
+            if let Some((stored, value)) = entries.map.get(&key) {
+                if stored.elapsed() < entries.ttl {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(value.clone());
+                }
+            }
+            if entries.size == 0 {
+                None
+            } else {
+                Some(entries.generation)
+            }
+        };
+        // The lock is not held while loading, so that loads run concurrently
+        let generation = match generation {
+            Some(generation) => generation,
+            None => return load(),
+        };
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = load()?;
+
+        let mut entries = self.lock();
+        if entries.generation == generation {
+            entries.insert(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Removes the entries with matching keys
+    pub fn invalidate(&self, matches: impl Fn(&K) -> bool) {
+        let mut entries = self.lock();
+        entries.generation += 1;
+        entries.map.retain(|key, _| !matches(key));
+    }
+
+    /// Removes all the entries
+    pub fn clear(&self) {
+        self.invalidate(|_| true)
+    }
+
+    /// The number of hits, misses and entries
+    fn stats(&self) -> (usize, usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.lock().map.len(),
+        )
+    }
+}
+
+/// The caches of all the entities
+#[derive(Debug)]
+pub struct Cache {
+    /// Categories by id and whether hidden ones are included
+    pub categories: EntityCache<(u32, bool), Category>,
+    /// All the categories by whether hidden ones are included
+    pub all_categories: EntityCache<bool, Vec<Category>>,
+    pub users: EntityCache<u32, User>,
+    /// Threads by id and whether hidden ones are included
+    pub threads: EntityCache<(u32, bool), Thread>,
+}
+
+lazy_static! {
+    /// The cache shared by all the services, which is configured by
+    /// `configure` on startup
+    pub static ref CACHE: Cache = {
+        let config = CacheConfig::default();
+        Cache {
+            categories: EntityCache::new("categories", config.categories),
+            all_categories: EntityCache::new("all_categories", config.categories),
+            users: EntityCache::new("users", config.users),
+            threads: EntityCache::new("threads", config.threads),
+        }
+    };
+}
+
+/// Sets the size and lifetime of the entries of each cache
+pub fn configure(config: &CacheConfig) {
+    CACHE.categories.configure(config.categories);
+    CACHE.all_categories.configure(config.categories);
+    CACHE.users.configure(config.users);
+    CACHE.threads.configure(config.threads);
+}
+
+/// Removes all the entries of all the caches
+pub fn flush() {
+    CACHE.categories.clear();
+    CACHE.all_categories.clear();
+    CACHE.users.clear();
+    CACHE.threads.clear();
+    info!("Flushed the cache");
+}
+
+/// Writes the hits, misses and entries of each cache in the Prometheus text
+/// format
+pub fn write_metrics(out: &mut String) -> fmt::Result {
+    let stats = [
+        (CACHE.categories.name, CACHE.categories.stats()),
+        (CACHE.all_categories.name, CACHE.all_categories.stats()),
+        (CACHE.users.name, CACHE.users.stats()),
+        (CACHE.threads.name, CACHE.threads.stats()),
+    ];
+
+    writeln!(out, "# HELP controller_cache_hits_total Reads answered by the cache.")?;
+    writeln!(out, "# TYPE controller_cache_hits_total counter")?;
+    for (name, (hits, _, _)) in &stats {
+        writeln!(out, "controller_cache_hits_total{{cache=\"{}\"}} {}", name, hits)?;
+    }
+    writeln!(out, "# HELP controller_cache_misses_total Reads which went to the database.")?;
+    writeln!(out, "# TYPE controller_cache_misses_total counter")?;
+    for (name, (_, misses, _)) in &stats {
+        writeln!(out, "controller_cache_misses_total{{cache=\"{}\"}} {}", name, misses)?;
+    }
+    writeln!(out, "# HELP controller_cache_entries Entries in the cache.")?;
+    writeln!(out, "# TYPE controller_cache_entries gauge")?;
+    for (name, (_, _, entries)) in &stats {
+        writeln!(out, "controller_cache_entries{{cache=\"{}\"}} {}", name, entries)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntErrorKind;
+
+    fn cache(size: usize) -> EntityCache<u32, String> {
+        EntityCache::new("test", EntityCacheConfig { size, ttl: 60 })
+    }
+
+    #[test]
+    fn read_through() {
+        let cache = cache(2);
+        assert_eq!(cache.get_or_load(1, || Ok("one".to_string())).unwrap(), "one");
+        assert_eq!(cache.get_or_load(1, || panic!("not cached")).unwrap(), "one");
+        assert!(cache.get_or_load(2, || Err(IntErrorKind::ContentNotFound.into())).is_err());
+        assert_eq!(cache.stats(), (1, 2, 1));
+
+        cache.invalidate(|&id| id == 1);
+        assert_eq!(cache.get_or_load(1, || Ok("new".to_string())).unwrap(), "new");
+    }
+
+    #[test]
+    fn evict_oldest() {
+        let cache = cache(2);
+        for id in 0..3 {
+            cache.get_or_load(id, || Ok(id.to_string())).unwrap();
+        }
+        assert_eq!(cache.stats().2, 2);
+        assert_eq!(cache.get_or_load(2, || panic!("not cached")).unwrap(), "2");
+    }
+
+    #[test]
+    fn disabled() {
+        let cache = cache(0);
+        cache.get_or_load(1, || Ok("one".to_string())).unwrap();
+        assert_eq!(cache.get_or_load(1, || Ok("two".to_string())).unwrap(), "two");
+        assert_eq!(cache.stats(), (0, 0, 0));
+    }
+}
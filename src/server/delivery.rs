@@ -0,0 +1,67 @@
+//! Delivery of the notifications which new threads and comments queue
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::db::{self, DbPool};
+
+/// The milliseconds between checks of the queue, while it is empty
+const INTERVAL_MS: u64 = 1000;
+/// The number of queued threads and comments delivered at once
+const BATCH_SIZE: i64 = 100;
+
+/// A thread which delivers the queued notifications until it is stopped
+pub struct Delivery {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Delivery {
+    /// Starts delivering the queued notifications
+    pub fn start(db_pool: DbPool) -> std::io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let thread = thread::Builder::new()
+            .name("notifications".to_string())
+            .spawn(move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    if !deliver(&db_pool) {
+                        thread::sleep(Duration::from_millis(INTERVAL_MS));
+                    }
+                }
+            })?;
+
+        Ok(Delivery { stop, thread })
+    }
+
+    /// Stops the delivery after the current batch
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if self.thread.join().is_err() {
+            error!("The notification delivery thread panicked");
+        }
+    }
+}
+
+/// Delivers a batch of queued notifications, and returns whether there may
+/// be more of them
+fn deliver(db_pool: &DbPool) -> bool {
+    let con = match db_pool.get() {
+        Ok(con) => con,
+        Err(e) => {
+            warn!("No database connection to deliver notifications: {}", e);
+            return false;
+        }
+    };
+    // The connection may come with the statement time limit of a request
+    if db::limit_statement_time(&con, None).is_err() {
+        return false;
+    }
+
+    match db::notifications::deliver_queued_notifications(&con, BATCH_SIZE) {
+        Ok(delivered) => delivered as i64 == BATCH_SIZE,
+        Err(_) => false,
+    }
+}
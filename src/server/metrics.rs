@@ -0,0 +1,257 @@
+//! Metrics about the requests to the server, in the Prometheus text format
+use futures::{Future, Stream};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Write};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+
+use super::shutdown::Jobs;
+use crate::db::{self, DbPool};
+
+/// The upper bounds of the buckets of the latency histograms, in seconds
+const BUCKETS: [f64; 11] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// The metrics of a single RPC
+#[derive(Debug, Default)]
+struct RpcMetrics {
+    requests: u64,
+    /// The number of requests in each bucket, which are not cumulative
+    buckets: [u64; 11],
+    latency_sum: f64,
+    /// The number of errors by the name of the `ServiceError` variant
+    errors: BTreeMap<String, u64>,
+}
+
+/// The metrics of all the RPCs
+#[derive(Debug, Default)]
+pub struct Metrics {
+    rpcs: Mutex<BTreeMap<&'static str, RpcMetrics>>,
+    busy: AtomicUsize,
+    overloaded: AtomicUsize,
+    timed_out: AtomicUsize,
+}
+
+/// The name of an enum variant, such as `MissingContent`
+fn variant_name(value: &impl Debug) -> String {
+    let name = format!("{:?}", value);
+    name.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or("")
+        .to_string()
+}
+
+impl Metrics {
+    /// Records a finished request, which took `elapsed` since it was queued
+    pub fn record(&self, rpc: &'static str, elapsed: Duration, error: Option<&impl Debug>) {
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let mut rpcs = self.rpcs.lock().unwrap_or_else(|e| e.into_inner());
+        let metrics = rpcs.entry(rpc).or_insert_with(RpcMetrics::default);
+
+        metrics.requests += 1;
+        metrics.latency_sum += seconds;
+        if let Some(bucket) = BUCKETS.iter().position(|&le| seconds <= le) {
+            metrics.buckets[bucket] += 1;
+        }
+        if let Some(error) = error {
+            *metrics.errors.entry(variant_name(error)).or_insert(0) += 1;
+        }
+    }
+
+    /// Records a request which failed because no database connection was
+    /// available
+    pub fn record_busy(&self) {
+        self.busy.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a request which was rejected because its worker pool was full
+    pub fn record_overloaded(&self) {
+        self.overloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a request which was queued past its deadline
+    pub fn record_timeout(&self) {
+        self.timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the metrics in the Prometheus text format, along with the
+    /// current state of the database pool and the worker queue
+    pub fn render(&self, db_pool: &DbPool, jobs: &Jobs) -> String {
+        let mut out = String::new();
+        self.write(&mut out, db_pool, jobs)
+            .expect("writing to a string can not fail");
+        out
+    }
+
+    fn write(&self, out: &mut String, db_pool: &DbPool, jobs: &Jobs) -> std::fmt::Result {
+        let rpcs = self.rpcs.lock().unwrap_or_else(|e| e.into_inner());
+
+        writeln!(out, "# HELP controller_requests_total Finished requests by RPC.")?;
+        writeln!(out, "# TYPE controller_requests_total counter")?;
+        for (rpc, metrics) in rpcs.iter() {
+            writeln!(out, "controller_requests_total{{rpc=\"{}\"}} {}", rpc, metrics.requests)?;
+        }
+
+        writeln!(out, "# HELP controller_request_duration_seconds Latency of requests, including the time queued.")?;
+        writeln!(out, "# TYPE controller_request_duration_seconds histogram")?;
+        for (rpc, metrics) in rpcs.iter() {
+            let mut cumulative = 0;
+            for (le, count) in BUCKETS.iter().zip(metrics.buckets.iter()) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "controller_request_duration_seconds_bucket{{rpc=\"{}\",le=\"{}\"}} {}",
+                    rpc, le, cumulative
+                )?;
+            }
+            writeln!(
+                out,
+                "controller_request_duration_seconds_bucket{{rpc=\"{}\",le=\"+Inf\"}} {}",
+                rpc, metrics.requests
+            )?;
+            writeln!(
+                out,
+                "controller_request_duration_seconds_sum{{rpc=\"{}\"}} {}",
+                rpc, metrics.latency_sum
+            )?;
+            writeln!(
+                out,
+                "controller_request_duration_seconds_count{{rpc=\"{}\"}} {}",
+                rpc, metrics.requests
+            )?;
+        }
+
+        writeln!(out, "# HELP controller_errors_total Failed requests by RPC and error.")?;
+        writeln!(out, "# TYPE controller_errors_total counter")?;
+        for (rpc, metrics) in rpcs.iter() {
+            for (error, count) in &metrics.errors {
+                writeln!(
+                    out,
+                    "controller_errors_total{{rpc=\"{}\",error=\"{}\"}} {}",
+                    rpc, error, count
+                )?;
+            }
+        }
+
+        writeln!(out, "# HELP controller_busy_total Requests failed because no database connection was available.")?;
+        writeln!(out, "# TYPE controller_busy_total counter")?;
+        writeln!(out, "controller_busy_total {}", self.busy.load(Ordering::Relaxed))?;
+        writeln!(out, "# HELP controller_overloaded_total Requests rejected because their worker pool was full.")?;
+        writeln!(out, "# TYPE controller_overloaded_total counter")?;
+        writeln!(out, "controller_overloaded_total {}", self.overloaded.load(Ordering::Relaxed))?;
+        writeln!(out, "# HELP controller_timeouts_total Requests failed because they were queued past their deadline.")?;
+        writeln!(out, "# TYPE controller_timeouts_total counter")?;
+        writeln!(out, "controller_timeouts_total {}", self.timed_out.load(Ordering::Relaxed))?;
+
+        let state = db_pool.state();
+        writeln!(out, "# HELP controller_db_connections Open database connections by state.")?;
+        writeln!(out, "# TYPE controller_db_connections gauge")?;
+        writeln!(
+            out,
+            "controller_db_connections{{state=\"active\"}} {}",
+            state.connections - state.idle_connections
+        )?;
+        writeln!(
+            out,
+            "controller_db_connections{{state=\"idle\"}} {}",
+            state.idle_connections
+        )?;
+        writeln!(out, "# HELP controller_db_connections_max The size of the database pool.")?;
+        writeln!(out, "# TYPE controller_db_connections_max gauge")?;
+        writeln!(out, "controller_db_connections_max {}", db_pool.max_size())?;
+
+        let (statements, slow_statements, statement_time) = db::instrument::statistics();
+        let statement_seconds =
+            statement_time.as_secs() as f64 + f64::from(statement_time.subsec_nanos()) / 1e9;
+        writeln!(out, "# HELP controller_db_statements_total Statements run on the database.")?;
+        writeln!(out, "# TYPE controller_db_statements_total counter")?;
+        writeln!(out, "controller_db_statements_total {}", statements)?;
+        writeln!(out, "# HELP controller_db_slow_statements_total Statements slower than the slow query threshold.")?;
+        writeln!(out, "# TYPE controller_db_slow_statements_total counter")?;
+        writeln!(out, "controller_db_slow_statements_total {}", slow_statements)?;
+        writeln!(out, "# HELP controller_db_statement_seconds_total Time spent running statements.")?;
+        writeln!(out, "# TYPE controller_db_statement_seconds_total counter")?;
+        writeln!(out, "controller_db_statement_seconds_total {}", statement_seconds)?;
+
+        writeln!(out, "# HELP controller_queue_depth Requests queued or running on the worker pool.")?;
+        writeln!(out, "# TYPE controller_queue_depth gauge")?;
+        writeln!(out, "controller_queue_depth {}", jobs.running())?;
+
+        super::cache::write_metrics(out)
+    }
+}
+
+/// Serves the metrics over HTTP on `addr`, answering any request with the
+/// rendered metrics
+pub fn serve(
+    addr: SocketAddr,
+    handle: &Handle,
+    metrics: Arc<Metrics>,
+    db_pool: DbPool,
+    jobs: Arc<Jobs>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(&addr, handle)?;
+    let spawn_handle = handle.clone();
+
+    let server = listener
+        .incoming()
+        .for_each(move |(stream, _)| {
+            let body = metrics.render(&db_pool, &jobs);
+            let response = format!(
+                "HTTP/1.0 200 OK\r\n\
+                 Content-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+
+            // Read the request before answering, so that closing the
+            // connection does not reset it while the request is unread
+            let reply = tokio_io::io::read(stream, vec![0; 1024])
+                .and_then(move |(stream, _, _)| tokio_io::io::write_all(stream, response))
+                .map(|_| ())
+                .map_err(|e| debug!("Unable to serve metrics: {}", e));
+            spawn_handle.spawn(reply);
+            Ok(())
+        }).map_err(|e| error!("Unable to accept metrics connection: {}", e));
+    handle.spawn(server);
+
+    info!("Serving metrics on http://{}/metrics", addr);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    enum TestError {
+        MissingContent,
+        Other(u32),
+    }
+
+    #[test]
+    fn record() {
+        let metrics = Metrics::default();
+        metrics.record("get_user", Duration::from_millis(3), None::<&TestError>);
+        metrics.record(
+            "get_user",
+            Duration::from_millis(30),
+            Some(&TestError::MissingContent),
+        );
+        metrics.record("get_user", Duration::from_secs(10), Some(&TestError::Other(1)));
+
+        let rpcs = metrics.rpcs.lock().unwrap();
+        let get_user = &rpcs["get_user"];
+        assert_eq!(get_user.requests, 3);
+        assert_eq!(get_user.buckets[1], 1);
+        assert_eq!(get_user.buckets[4], 1);
+        assert_eq!(get_user.buckets.iter().sum::<u64>(), 2);
+        assert_eq!(get_user.errors["MissingContent"], 1);
+        assert_eq!(get_user.errors["Other"], 1);
+    }
+}
@@ -1,14 +1,25 @@
+mod cache;
+mod delivery;
+mod metrics;
 mod services;
+mod shutdown;
+mod status;
+mod workers;
 use self::services::*;
+use self::delivery::Delivery;
+use self::metrics::Metrics;
+use self::shutdown::Jobs;
+use self::workers::{Deadlines, WorkerPool};
 
 use failure::ResultExt;
-use futures_cpupool::CpuPool;
-use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tarpc::future::server;
 use tokio_core::reactor;
 
 use super::db::{setup_connection_pool, DbPool};
+use crate::config::Config;
+use crate::logging::LogLevels;
 use std::net::SocketAddr;
 
 use crate::{IntErrorKind, IntResult};
@@ -17,34 +28,112 @@ use crate::{IntErrorKind, IntResult};
 /// to the MySQL database
 #[derive(Clone)]
 pub struct Server {
-    pool: CpuPool,
-    request_count: Arc<AtomicUsize>,
+    read_pool: WorkerPool,
+    write_pool: WorkerPool,
+    deadlines: Arc<Deadlines>,
+    metrics: Arc<Metrics>,
+    metrics_address: Option<SocketAddr>,
     db_pool: DbPool,
+    jobs: Arc<Jobs>,
+    shutdown_timeout: Duration,
+    max_queue_depth: usize,
+    started: Instant,
+    log_levels: LogLevels,
 }
 
 impl Server {
     /// Try to make a new server by creating a connection pool to the database
-    pub fn try_new(database_url: &str) -> IntResult<Self> {
-        let db_pool = setup_connection_pool(database_url)?;
+    ///
+    /// The log levels can be changed through the server while it runs.
+    pub fn try_new(config: &Config, log_levels: LogLevels) -> IntResult<Self> {
+        let db_pool = setup_connection_pool(&config.database)?;
+        cache::configure(&config.cache);
+        let metrics = Arc::new(Metrics::default());
+        let queue_size = config.server.queue_size;
 
         Ok(Server {
-            pool: CpuPool::new_num_cpus(),
-            request_count: Arc::new(AtomicUsize::new(1)),
+            read_pool: WorkerPool::new("read", config.server.workers, queue_size, metrics.clone()),
+            write_pool: WorkerPool::new(
+                "write",
+                config.server.write_workers,
+                queue_size,
+                metrics.clone(),
+            ),
+            deadlines: Arc::new(Deadlines::new(&config.server)),
+            metrics,
+            metrics_address: config.metrics_address().context(IntErrorKind::InvalidConfig)?,
             db_pool,
+            jobs: Arc::new(Jobs::default()),
+            shutdown_timeout: Duration::from_secs(config.server.shutdown_timeout),
+            max_queue_depth: config.server.max_queue_depth,
+            started: Instant::now(),
+            log_levels,
         })
     }
 
-    /// Run the current server on the given socket address
+    /// Run the current server on the given socket address until SIGTERM or
+    /// SIGINT is received
+    ///
+    /// On shutdown, new connections are refused and the requests which are
+    /// already queued or running get up to `shutdown_timeout` to finish.
     pub fn run(self, addr: SocketAddr) -> IntResult<()> {
         let mut reactor = reactor::Core::new().context(IntErrorKind::ServerError)?;
+        let jobs = self.jobs.clone();
+        let db_pool = self.db_pool.clone();
+        let shutdown_timeout = self.shutdown_timeout;
 
-        let (_handle, server) = self
+        if let Some(metrics_address) = self.metrics_address {
+            metrics::serve(
+                metrics_address,
+                &reactor.handle(),
+                self.metrics.clone(),
+                self.db_pool.clone(),
+                self.jobs.clone(),
+            ).context(IntErrorKind::ServerError)?;
+        }
+
+        let delivery = Delivery::start(self.db_pool.clone()).context(IntErrorKind::ServerError)?;
+
+        let (handle, server) = self
             .listen(addr, &reactor.handle(), server::Options::default())
             .context(IntErrorKind::ServerError)?;
+        reactor.handle().spawn(server);
 
         info!("Starting server on {}", addr);
-        reactor.run(server).map_err(|_| IntErrorKind::ServerError)?;
-        info!("Shuting down server");
+        let signal = shutdown::signal(&reactor.handle());
+        let signal = reactor.run(signal).context(IntErrorKind::ServerError)?;
+
+        info!("Received {}, no longer accepting connections", signal);
+        reactor.handle().spawn(handle.shutdown().shutdown());
+
+        // Keep the reactor running so the responses of the last requests are
+        // sent
+        let deadline = Instant::now() + shutdown_timeout;
+        while jobs.running() > 0 && Instant::now() < deadline {
+            reactor.turn(Some(Duration::from_millis(100)));
+        }
+        reactor.turn(Some(Duration::from_millis(0)));
+        let abandoned = jobs.running();
+        delivery.stop();
+
+        // Dropping the reactor drops the connections and the clones of the
+        // server, so that the database pool is closed with this last clone
+        drop(reactor);
+        let connections = db_pool.state().connections;
+        drop(db_pool);
+
+        if abandoned > 0 {
+            warn!(
+                "Shutting down server with {} unfinished requests after {} seconds",
+                abandoned,
+                shutdown_timeout.as_secs()
+            );
+        }
+        info!(
+            "Shut down server after {} requests, closed {} database connections",
+            jobs.finished(),
+            connections
+        );
         Ok(())
     }
 }
@@ -0,0 +1,36 @@
+use crate::db::{self, DbConn};
+use crate::payloads::*;
+use crate::{IntErrorKind, IntResult};
+
+pub fn add_bookmark(con: &DbConn, payload: AddBookmarkPayload) -> IntResult<BookmarkPayload> {
+    trace!("add_bookmark: {:?}", payload);
+
+    let _user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+
+    db::bookmarks::insert_bookmark(&con, payload).map(|b| b.into())
+}
+
+pub fn remove_bookmark(con: &DbConn, payload: RemoveBookmarkPayload) -> IntResult<()> {
+    let RemoveBookmarkPayload { id, .. } = payload;
+    trace!("remove_bookmark: {:?}", payload);
+
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+
+    db::bookmarks::delete_bookmark(&con, user_id, id).map(|_| ())
+}
+
+pub fn get_bookmarks(
+    con: &DbConn,
+    payload: GetBookmarksPayload,
+) -> IntResult<Vec<BookmarkPayload>> {
+    let GetBookmarksPayload {
+        user_id,
+        include_hidden,
+        offset,
+        limit,
+    } = payload;
+    trace!("get_bookmarks: {:?}", payload);
+
+    db::bookmarks::get_bookmarks(&con, user_id, include_hidden, offset, limit)
+        .map(|bookmarks| bookmarks.into_iter().map(|b| b.into()).collect())
+}
@@ -5,21 +5,33 @@ use datatypes::content::requests::*;
 use datatypes::content::responses::*;
 
 use crate::db::{self, DbConn};
+use crate::payloads::*;
+use crate::server::cache::CACHE;
 use crate::types::Category;
 use crate::{IntErrorKind, IntResult};
 
+/// Removes a changed category from the cache
+fn invalidate(id: u32) {
+    CACHE.categories.invalidate(|&(cached, _)| cached == id);
+    CACHE.all_categories.clear();
+}
+
 pub fn get_category(con: &DbConn, payload: GetCategoryPayload) -> IntResult<CategoryPayload> {
     let GetCategoryPayload { id, include_hidden } = payload;
     trace!("get_category: {:?}", payload);
 
-    db::categories::get_category(&con, id, include_hidden).and_then(|p| {
-        <Category as TryInto<CategoryPayload>>::try_into(p)
-            .context(IntErrorKind::ServerError)
-            .map_err(|e| {
-                error!("Unable to convert category to payload: {}", e);
-                e.into()
-            })
-    })
+    CACHE
+        .categories
+        .get_or_load((*id, include_hidden), || {
+            db::categories::get_category(&con, id, include_hidden)
+        }).and_then(|p| {
+            <Category as TryInto<CategoryPayload>>::try_into(p)
+                .context(IntErrorKind::ServerError)
+                .map_err(|e| {
+                    error!("Unable to convert category to payload: {}", e);
+                    e.into()
+                })
+        })
 }
 
 pub fn get_all_categories(
@@ -29,23 +41,28 @@ pub fn get_all_categories(
     let GetHiddenPayload { include_hidden } = payload;
     trace!("get_all_categories: {:?}", payload);
 
-    db::categories::get_all_categories(&con, include_hidden).and_then(|categories| {
-        categories
-            .into_iter()
-            .map(|category| category.try_into())
-            .collect::<Result<Vec<CategoryPayload>, _>>()
-            .context(IntErrorKind::ServerError)
-            .map_err(|e| {
-                error!("Unable to convert category to payload: {}", e);
-                e.into()
-            })
-    })
+    CACHE
+        .all_categories
+        .get_or_load(include_hidden, || {
+            db::categories::get_all_categories(&con, include_hidden)
+        }).and_then(|categories| {
+            categories
+                .into_iter()
+                .map(|category| category.try_into())
+                .collect::<Result<Vec<CategoryPayload>, _>>()
+                .context(IntErrorKind::ServerError)
+                .map_err(|e| {
+                    error!("Unable to convert category to payload: {}", e);
+                    e.into()
+                })
+        })
 }
 
 pub fn add_category(con: &DbConn, payload: AddCategoryPayload) -> IntResult<CategoryPayload> {
     trace!("add_category: {:?}", payload);
 
     db::categories::insert_category(&con, payload).and_then(|p| {
+        CACHE.all_categories.clear();
         <Category as TryInto<CategoryPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -60,6 +77,7 @@ pub fn edit_category(con: &DbConn, payload: EditCategoryPayload) -> IntResult<Ca
     trace!("edit_category: {:?}", payload);
 
     db::categories::update_category(con, payload).and_then(|p| {
+        invalidate(p.id);
         <Category as TryInto<CategoryPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -74,6 +92,22 @@ pub fn hide_category(con: &DbConn, payload: HideCategoryPayload) -> IntResult<Ca
     trace!("hide_category: {:?}", payload);
 
     db::categories::update_category(&con, payload).and_then(|p| {
+        invalidate(p.id);
+        <Category as TryInto<CategoryPayload>>::try_into(p)
+            .context(IntErrorKind::ServerError)
+            .map_err(|e| {
+                error!("Unable to convert category ({}) to payload: {}", id, e);
+                e.into()
+            })
+    })
+}
+
+pub fn set_category_qa(con: &DbConn, payload: SetQaPayload) -> IntResult<CategoryPayload> {
+    let SetQaPayload { id, qa } = payload;
+    trace!("set_category_qa: {:?}", payload);
+
+    db::categories::set_category_qa(&con, id, qa).and_then(|p| {
+        invalidate(p.id);
         <Category as TryInto<CategoryPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -1,3 +1,4 @@
+use diesel::Connection;
 use failure::ResultExt;
 use std::convert::TryInto;
 
@@ -5,8 +6,10 @@ use datatypes::content::requests::*;
 use datatypes::content::responses::*;
 
 use crate::db::{self, DbConn};
+use crate::mentions::parse_mentions;
+use crate::payloads::*;
 use crate::types::Comment;
-use crate::{IntErrorKind, IntResult};
+use crate::{IntError, IntErrorKind, IntResult};
 
 pub fn get_comment(con: &DbConn, payload: GetCommentPayload) -> IntResult<CommentPayload> {
     let GetCommentPayload { id, include_hidden } = payload;
@@ -62,9 +65,15 @@ pub fn get_all_comments(con: &DbConn, payload: GetHiddenPayload) -> IntResult<Ve
 pub fn add_comment(con: &DbConn, payload: AddCommentPayload) -> IntResult<CommentPayload> {
     trace!("add_comment: {:?}", payload);
 
-    let _user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
 
-    db::comments::insert_comment(&con, payload).and_then(|p| {
+    con.transaction::<_, IntError, _>(|| {
+        let comment = db::comments::insert_comment(&con, payload)?;
+        db::subscriptions::subscribe_thread(&con, user_id, comment.thread_id.into())?;
+        db::notifications::queue_notifications(&con, comment.thread_id, Some(comment.id))?;
+        db::mentions::add_mentions(&con, &comment, &parse_mentions(&comment.content))?;
+        Ok(comment)
+    }).and_then(|p| {
         <Comment as TryInto<CommentPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -79,7 +88,11 @@ pub fn edit_comment(con: &DbConn, payload: EditCommentPayload) -> IntResult<Comm
 
     let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
 
-    db::comments::update_comment(&con, user_id, payload).and_then(|p| {
+    con.transaction::<_, IntError, _>(|| {
+        let comment = db::comments::update_comment(&con, user_id, payload)?;
+        db::mentions::add_mentions(&con, &comment, &parse_mentions(&comment.content))?;
+        Ok(comment)
+    }).and_then(|p| {
         <Comment as TryInto<CommentPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -103,3 +116,27 @@ pub fn hide_comment(con: &DbConn, payload: HideCommentPayload) -> IntResult<Comm
             })
     })
 }
+
+pub fn get_mentions(con: &DbConn, payload: GetMentionsPayload) -> IntResult<Vec<CommentPayload>> {
+    let GetMentionsPayload {
+        user_id,
+        include_hidden,
+        offset,
+        limit,
+    } = payload;
+    trace!("get_mentions: {:?}", payload);
+
+    db::mentions::get_mentioned_comments(&con, user_id, include_hidden, offset, limit).and_then(
+        |comments| {
+            comments
+                .into_iter()
+                .map(|comment| comment.try_into())
+                .collect::<Result<Vec<CommentPayload>, _>>()
+                .context(IntErrorKind::ServerError)
+                .map_err(|e| {
+                    error!("Unable to convert comment to payload: {}", e);
+                    e.into()
+                })
+        },
+    )
+}
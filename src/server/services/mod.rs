@@ -1,12 +1,18 @@
+use super::workers::{ContentWork, Work};
 use super::Server;
 
-use futures_cpupool::CpuFuture;
+use futures::future::{self, FutureResult};
 
 use datatypes::content::requests::*;
 use datatypes::content::responses::*;
 
+use crate::payloads::*;
+
+mod bookmarks;
 mod categories;
 mod comments;
+mod notifications;
+mod reads;
 mod search;
 mod threads;
 mod users;
@@ -37,179 +43,606 @@ service! {
     rpc hide_comment(payload: HideCommentPayload) -> CommentPayload | ContentError;
 
     rpc search(payload: SearchPayload) -> SearchResultsPayload | ContentError;
+
+    rpc set_category_qa(payload: Traced<SetQaPayload>) -> CategoryPayload | ServiceError;
+    rpc get_threads_in_category_by_answer(payload: Traced<GetAnsweredThreadsPayload>) -> Vec<ThreadPayload> | ServiceError;
+    rpc accept_answer(payload: Traced<AcceptAnswerPayload>) -> AnswerPayload | ServiceError;
+    rpc get_answer(payload: Traced<GetThreadPayload>) -> AnswerPayload | ServiceError;
+
+    rpc subscribe_thread(payload: Traced<SubscribeThreadPayload>) -> () | ServiceError;
+    rpc subscribe_category(payload: Traced<SubscribeCategoryPayload>) -> () | ServiceError;
+    rpc get_notifications(payload: Traced<GetNotificationsPayload>) -> Vec<NotificationPayload> | ServiceError;
+    rpc read_notifications(payload: Traced<ReadNotificationsPayload>) -> () | ServiceError;
+    rpc count_unread_notifications(payload: Traced<GetUserPayload>) -> u32 | ServiceError;
+
+    rpc get_mentions(payload: Traced<GetMentionsPayload>) -> Vec<CommentPayload> | ServiceError;
+
+    rpc mark_thread_read(payload: Traced<MarkThreadReadPayload>) -> () | ServiceError;
+    rpc get_threads_in_category_for_user(payload: Traced<GetUserThreadsPayload>) -> Vec<UnreadThreadPayload> | ServiceError;
+    rpc get_unread_threads(payload: Traced<GetUnreadThreadsPayload>) -> Vec<UnreadThreadPayload> | ServiceError;
+
+    rpc add_bookmark(payload: Traced<AddBookmarkPayload>) -> BookmarkPayload | ServiceError;
+    rpc remove_bookmark(payload: Traced<RemoveBookmarkPayload>) -> () | ServiceError;
+    rpc get_bookmarks(payload: Traced<GetBookmarksPayload>) -> Vec<BookmarkPayload> | ServiceError;
+
+    rpc get_user_by_username(payload: Traced<GetUserByUsernamePayload>) -> UserPayload | ServiceError;
+    rpc get_users(payload: Traced<GetUsersPayload>) -> Vec<UserPayload> | ServiceError;
+
+    rpc rename_user(payload: Traced<RenameUserPayload>) -> UserPayload | ServiceError;
+    rpc get_username_history(payload: Traced<GetUserPayload>) -> Vec<UsernameChangePayload> | ServiceError;
+
+    rpc delete_user(payload: Traced<DeleteUserPayload>) -> () | ServiceError;
+    rpc export_user_data(payload: Traced<GetUserPayload>) -> String | ServiceError;
+
+    rpc get_user_profile(payload: Traced<GetUserPayload>) -> UserProfilePayload | ServiceError;
+    rpc get_user_activity(payload: Traced<GetUserActivityPayload>) -> Vec<ActivityPayload> | ServiceError;
+
+    rpc health(payload: ()) -> HealthPayload | ServiceError;
+    rpc readiness(payload: ()) -> ReadinessPayload | ServiceError;
+    rpc version(payload: ()) -> VersionPayload | ServiceError;
+    rpc metrics(payload: ()) -> String | ServiceError;
+    rpc set_log_level(payload: SetLogLevelPayload) -> LogLevelsPayload | ServiceError;
+    rpc flush_cache(payload: ()) -> () | ServiceError;
+
+    rpc moderate_answer(payload: Traced<ModerateAnswerPayload>) -> AnswerPayload | ServiceError;
+
+    // The RPCs of `datatypes`, taking the trace id of the caller and failing
+    // with a `ServiceError`
+    rpc get_user_traced(payload: Traced<GetUserPayload>) -> UserPayload | ServiceError;
+    rpc add_user_traced(payload: Traced<AddUserPayload>) -> UserPayload | ServiceError;
+    rpc edit_user_traced(payload: Traced<EditUserPayload>) -> UserPayload | ServiceError;
+
+    rpc get_category_traced(payload: Traced<GetCategoryPayload>) -> CategoryPayload | ServiceError;
+    rpc get_all_categories_traced(payload: Traced<GetHiddenPayload>) -> Vec<CategoryPayload> | ServiceError;
+    rpc add_category_traced(payload: Traced<AddCategoryPayload>) -> CategoryPayload | ServiceError;
+    rpc edit_category_traced(payload: Traced<EditCategoryPayload>) -> CategoryPayload | ServiceError;
+    rpc hide_category_traced(payload: Traced<HideCategoryPayload>) -> CategoryPayload | ServiceError;
+
+    rpc get_thread_traced(payload: Traced<GetThreadPayload>) -> ThreadPayload | ServiceError;
+    rpc get_threads_in_category_traced(payload: Traced<GetThreadsPayload>) -> Vec<ThreadPayload> | ServiceError;
+    rpc get_all_threads_traced(payload: Traced<GetHiddenPayload>) -> Vec<ThreadPayload> | ServiceError;
+    rpc add_thread_traced(payload: Traced<AddThreadPayload>) -> ThreadPayload | ServiceError;
+    rpc edit_thread_traced(payload: Traced<EditThreadPayload>) -> ThreadPayload | ServiceError;
+    rpc hide_thread_traced(payload: Traced<HideThreadPayload>) -> ThreadPayload | ServiceError;
+
+    rpc get_comment_traced(payload: Traced<GetCommentPayload>) -> CommentPayload | ServiceError;
+    rpc get_comments_in_thread_traced(payload: Traced<GetCommentsPayload>) -> Vec<CommentPayload> | ServiceError;
+    rpc get_all_comments_traced(payload: Traced<GetHiddenPayload>) -> Vec<CommentPayload> | ServiceError;
+    rpc add_comment_traced(payload: Traced<AddCommentPayload>) -> CommentPayload | ServiceError;
+    rpc edit_comment_traced(payload: Traced<EditCommentPayload>) -> CommentPayload | ServiceError;
+    rpc hide_comment_traced(payload: Traced<HideCommentPayload>) -> CommentPayload | ServiceError;
+
+    rpc search_traced(payload: Traced<SearchPayload>) -> SearchResultsPayload | ServiceError;
 }
 
-type UserRes = CpuFuture<UserPayload, ContentError>;
+type UserRes = Work<UserPayload>;
+type UsersRes = Work<Vec<UserPayload>>;
+type UsernameHistoryRes = Work<Vec<UsernameChangePayload>>;
+type ProfileRes = Work<UserProfilePayload>;
+type ActivityRes = Work<Vec<ActivityPayload>>;
+
+type CategoryRes = Work<CategoryPayload>;
+type CategoriesRes = Work<Vec<CategoryPayload>>;
+
+type ThreadRes = Work<ThreadPayload>;
+type ThreadsRes = Work<Vec<ThreadPayload>>;
+
+type CommentRes = Work<CommentPayload>;
+type CommentsRes = Work<Vec<CommentPayload>>;
 
-type CategoryRes = CpuFuture<CategoryPayload, ContentError>;
-type CategoriesRes = CpuFuture<Vec<CategoryPayload>, ContentError>;
+type SearchRes = Work<SearchResultsPayload>;
 
-type ThreadRes = CpuFuture<ThreadPayload, ContentError>;
-type ThreadsRes = CpuFuture<Vec<ThreadPayload>, ContentError>;
+type AnswerRes = Work<AnswerPayload>;
 
-type CommentRes = CpuFuture<CommentPayload, ContentError>;
-type CommentsRes = CpuFuture<Vec<CommentPayload>, ContentError>;
+type EmptyRes = Work<()>;
+type StringRes = Work<String>;
+type CountRes = Work<u32>;
+type NotificationsRes = Work<Vec<NotificationPayload>>;
+type UnreadThreadsRes = Work<Vec<UnreadThreadPayload>>;
 
-type SearchRes = CpuFuture<SearchResultsPayload, ContentError>;
+type BookmarkRes = Work<BookmarkPayload>;
+type BookmarksRes = Work<Vec<BookmarkPayload>>;
+
+type ReadinessRes = Work<ReadinessPayload>;
 
 #[macro_export]
 macro_rules! impl_service {
-    ($s_type:ident, $s_name:ident, $pay:ty, $fut:ident, $res:ty) => {
-        type $fut = $res;
+    // An RPC of `datatypes`, which takes a plain payload and fails with a
+    // `ContentError`, along with its `_traced` variant
+    (
+        $pool:ident,
+        $s_type:ident,
+        $s_name:ident,
+        $pay:ty,
+        $fut:ident,
+        $res:ty,
+        traced: $t_name:ident,
+        $t_fut:ident
+    ) => {
+        type $fut = ContentWork<$res>;
         fn $s_name(&self, payload: $pay) -> Self::$fut {
-            let cloned_pool = self.db_pool.clone();
-            let f = futures::lazy(move || {
-                cloned_pool
-                    .get()
-                    .map_err(|e| {
-                        error!("unable to get database connection from the pool: {}", e);
-                        ContentError::InternalServerError
-                    })
-                    .and_then(|con|
-                        $s_type::$s_name(&con, payload)
-                            .map_err(|e| {
-                                let ee = e.into();
-                                error!("sending error: {}", ee);
-                                ee
-                            })
-                    )
-            });
-            self.pool.spawn(f)
+            futures::Future::map_err(
+                impl_service!(@spawn self, $pool, $s_type, $s_name, payload, None),
+                ContentError::from as fn(ServiceError) -> ContentError,
+            )
         }
-    }
+        type $t_fut = $res;
+        fn $t_name(&self, payload: Traced<$pay>) -> Self::$t_fut {
+            let Traced { trace_id, payload } = payload;
+            impl_service!(@spawn self, $pool, $s_type, $s_name, payload, trace_id)
+        }
+    };
+    ($pool:ident, $s_type:ident, $s_name:ident, $pay:ty, $fut:ident, $res:ty) => {
+        type $fut = $res;
+        fn $s_name(&self, payload: Traced<$pay>) -> Self::$fut {
+            let Traced { trace_id, payload } = payload;
+            impl_service!(@spawn self, $pool, $s_type, $s_name, payload, trace_id)
+        }
+    };
+    (
+        @spawn $server:ident,
+        $pool:ident,
+        $s_type:ident,
+        $s_name:ident,
+        $payload:ident,
+        $trace_id:expr
+    ) => {{
+        let cloned_pool = $server.db_pool.clone();
+        let metrics = $server.metrics.clone();
+        let started = std::time::Instant::now();
+        let deadline = $server.deadlines.get(stringify!($s_name));
+        let job = super::shutdown::Jobs::start(&$server.jobs);
+        let request_id = crate::logging::request_id($trace_id);
+        let f = futures::lazy(move || {
+            let _job = job;
+            let _request = crate::logging::enter_request(request_id);
+            debug!("handling {}", stringify!($s_name));
+            let result = super::workers::connection(&cloned_pool, &metrics, started, deadline)
+                .and_then(|con|
+                    $s_type::$s_name(&con, $payload)
+                        .map_err(|e| {
+                            let ee = e.into();
+                            error!("sending error: {}", ee);
+                            ee
+                        })
+                );
+            if deadline.map_or(false, |deadline| started.elapsed() > deadline) {
+                warn!("{} finished after its deadline", stringify!($s_name));
+            }
+            metrics.record(stringify!($s_name), started.elapsed(), result.as_ref().err());
+            result
+        });
+        $server.$pool.spawn(stringify!($s_name), f)
+    }};
 }
 
 impl FutureService for Server {
     // Users
-    impl_service!(users, get_user, GetUserPayload, GetUserFut, UserRes);
-    impl_service!(users, add_user, AddUserPayload, AddUserFut, UserRes);
-    impl_service!(users, edit_user, EditUserPayload, EditUserFut, UserRes);
+    impl_service!(
+        read_pool,
+        users,
+        get_user,
+        GetUserPayload,
+        GetUserFut,
+        UserRes,
+        traced: get_user_traced,
+        GetUserTracedFut
+    );
+    impl_service!(
+        write_pool,
+        users,
+        add_user,
+        AddUserPayload,
+        AddUserFut,
+        UserRes,
+        traced: add_user_traced,
+        AddUserTracedFut
+    );
+    impl_service!(
+        write_pool,
+        users,
+        edit_user,
+        EditUserPayload,
+        EditUserFut,
+        UserRes,
+        traced: edit_user_traced,
+        EditUserTracedFut
+    );
+    impl_service!(
+        read_pool,
+        users,
+        get_user_by_username,
+        GetUserByUsernamePayload,
+        GetUserByUsernameFut,
+        UserRes
+    );
+    impl_service!(read_pool, users, get_users, GetUsersPayload, GetUsersFut, UsersRes);
+    impl_service!(write_pool, users, rename_user, RenameUserPayload, RenameUserFut, UserRes);
+    impl_service!(
+        read_pool,
+        users,
+        get_username_history,
+        GetUserPayload,
+        GetUsernameHistoryFut,
+        UsernameHistoryRes
+    );
+    impl_service!(write_pool, users, delete_user, DeleteUserPayload, DeleteUserFut, EmptyRes);
+    impl_service!(
+        write_pool,
+        users,
+        export_user_data,
+        GetUserPayload,
+        ExportUserDataFut,
+        StringRes
+    );
+    impl_service!(
+        read_pool,
+        users,
+        get_user_profile,
+        GetUserPayload,
+        GetUserProfileFut,
+        ProfileRes
+    );
+    impl_service!(
+        read_pool,
+        users,
+        get_user_activity,
+        GetUserActivityPayload,
+        GetUserActivityFut,
+        ActivityRes
+    );
 
     // Categories
     impl_service!(
+        read_pool,
         categories,
         get_category,
         GetCategoryPayload,
         GetCategoryFut,
-        CategoryRes
+        CategoryRes,
+        traced: get_category_traced,
+        GetCategoryTracedFut
     );
     impl_service!(
+        read_pool,
         categories,
         get_all_categories,
         GetHiddenPayload,
         GetAllCategoriesFut,
-        CategoriesRes
+        CategoriesRes,
+        traced: get_all_categories_traced,
+        GetAllCategoriesTracedFut
     );
     impl_service!(
+        write_pool,
         categories,
         add_category,
         AddCategoryPayload,
         AddCategoryFut,
-        CategoryRes
+        CategoryRes,
+        traced: add_category_traced,
+        AddCategoryTracedFut
     );
     impl_service!(
+        write_pool,
         categories,
         edit_category,
         EditCategoryPayload,
         EditCategoryFut,
-        CategoryRes
+        CategoryRes,
+        traced: edit_category_traced,
+        EditCategoryTracedFut
     );
     impl_service!(
+        write_pool,
         categories,
         hide_category,
         HideCategoryPayload,
         HideCategoryFut,
-        CategoryRes
+        CategoryRes,
+        traced: hide_category_traced,
+        HideCategoryTracedFut
     );
 
     // Threads
     impl_service!(
+        read_pool,
         threads,
         get_thread,
         GetThreadPayload,
         GetThreadFut,
-        ThreadRes
+        ThreadRes,
+        traced: get_thread_traced,
+        GetThreadTracedFut
     );
     impl_service!(
+        read_pool,
         threads,
         get_threads_in_category,
         GetThreadsPayload,
         GetThreadsInCategoryFut,
-        ThreadsRes
+        ThreadsRes,
+        traced: get_threads_in_category_traced,
+        GetThreadsInCategoryTracedFut
     );
     impl_service!(
+        read_pool,
         threads,
         get_all_threads,
         GetHiddenPayload,
         GetAllThreadsFut,
-        ThreadsRes
+        ThreadsRes,
+        traced: get_all_threads_traced,
+        GetAllThreadsTracedFut
     );
     impl_service!(
+        write_pool,
         threads,
         add_thread,
         AddThreadPayload,
         AddThreadFut,
-        ThreadRes
+        ThreadRes,
+        traced: add_thread_traced,
+        AddThreadTracedFut
     );
     impl_service!(
+        write_pool,
         threads,
         edit_thread,
         EditThreadPayload,
         EditThreadFut,
-        ThreadRes
+        ThreadRes,
+        traced: edit_thread_traced,
+        EditThreadTracedFut
     );
     impl_service!(
+        write_pool,
         threads,
         hide_thread,
         HideThreadPayload,
         HideThreadFut,
-        ThreadRes
+        ThreadRes,
+        traced: hide_thread_traced,
+        HideThreadTracedFut
     );
 
     // Comments
     impl_service!(
+        read_pool,
         comments,
         get_comment,
         GetCommentPayload,
         GetCommentFut,
-        CommentRes
+        CommentRes,
+        traced: get_comment_traced,
+        GetCommentTracedFut
     );
     impl_service!(
+        read_pool,
         comments,
         get_comments_in_thread,
         GetCommentsPayload,
         GetCommentsInThreadFut,
-        CommentsRes
+        CommentsRes,
+        traced: get_comments_in_thread_traced,
+        GetCommentsInThreadTracedFut
     );
     impl_service!(
+        read_pool,
         comments,
         get_all_comments,
         GetHiddenPayload,
         GetAllCommentsFut,
-        CommentsRes
+        CommentsRes,
+        traced: get_all_comments_traced,
+        GetAllCommentsTracedFut
     );
     impl_service!(
+        write_pool,
         comments,
         add_comment,
         AddCommentPayload,
         AddCommentFut,
-        CommentRes
+        CommentRes,
+        traced: add_comment_traced,
+        AddCommentTracedFut
     );
     impl_service!(
+        write_pool,
         comments,
         edit_comment,
         EditCommentPayload,
         EditCommentFut,
-        CommentRes
+        CommentRes,
+        traced: edit_comment_traced,
+        EditCommentTracedFut
     );
     impl_service!(
+        write_pool,
         comments,
         hide_comment,
         HideCommentPayload,
         HideCommentFut,
-        CommentRes
+        CommentRes,
+        traced: hide_comment_traced,
+        HideCommentTracedFut
     );
 
     // Search
-    impl_service!(search, search, SearchPayload, SearchFut, SearchRes);
+    impl_service!(
+        write_pool,
+        search,
+        search,
+        SearchPayload,
+        SearchFut,
+        SearchRes,
+        traced: search_traced,
+        SearchTracedFut
+    );
+
+    // Q&A
+    impl_service!(
+        write_pool,
+        categories,
+        set_category_qa,
+        SetQaPayload,
+        SetCategoryQaFut,
+        CategoryRes
+    );
+    impl_service!(
+        read_pool,
+        threads,
+        get_threads_in_category_by_answer,
+        GetAnsweredThreadsPayload,
+        GetThreadsInCategoryByAnswerFut,
+        ThreadsRes
+    );
+    impl_service!(
+        write_pool,
+        threads,
+        accept_answer,
+        AcceptAnswerPayload,
+        AcceptAnswerFut,
+        AnswerRes
+    );
+    impl_service!(
+        write_pool,
+        threads,
+        moderate_answer,
+        ModerateAnswerPayload,
+        ModerateAnswerFut,
+        AnswerRes
+    );
+    impl_service!(read_pool, threads, get_answer, GetThreadPayload, GetAnswerFut, AnswerRes);
+
+    // Notifications
+    impl_service!(
+        write_pool,
+        notifications,
+        subscribe_thread,
+        SubscribeThreadPayload,
+        SubscribeThreadFut,
+        EmptyRes
+    );
+    impl_service!(
+        write_pool,
+        notifications,
+        subscribe_category,
+        SubscribeCategoryPayload,
+        SubscribeCategoryFut,
+        EmptyRes
+    );
+    impl_service!(
+        read_pool,
+        notifications,
+        get_notifications,
+        GetNotificationsPayload,
+        GetNotificationsFut,
+        NotificationsRes
+    );
+    impl_service!(
+        write_pool,
+        notifications,
+        read_notifications,
+        ReadNotificationsPayload,
+        ReadNotificationsFut,
+        EmptyRes
+    );
+    impl_service!(
+        read_pool,
+        notifications,
+        count_unread_notifications,
+        GetUserPayload,
+        CountUnreadNotificationsFut,
+        CountRes
+    );
+
+    // Mentions
+    impl_service!(
+        read_pool,
+        comments,
+        get_mentions,
+        GetMentionsPayload,
+        GetMentionsFut,
+        CommentsRes
+    );
+
+    // Read tracking
+    impl_service!(
+        write_pool,
+        reads,
+        mark_thread_read,
+        MarkThreadReadPayload,
+        MarkThreadReadFut,
+        EmptyRes
+    );
+    impl_service!(
+        read_pool,
+        reads,
+        get_threads_in_category_for_user,
+        GetUserThreadsPayload,
+        GetThreadsInCategoryForUserFut,
+        UnreadThreadsRes
+    );
+    impl_service!(
+        read_pool,
+        reads,
+        get_unread_threads,
+        GetUnreadThreadsPayload,
+        GetUnreadThreadsFut,
+        UnreadThreadsRes
+    );
+
+    // Bookmarks
+    impl_service!(
+        write_pool,
+        bookmarks,
+        add_bookmark,
+        AddBookmarkPayload,
+        AddBookmarkFut,
+        BookmarkRes
+    );
+    impl_service!(
+        write_pool,
+        bookmarks,
+        remove_bookmark,
+        RemoveBookmarkPayload,
+        RemoveBookmarkFut,
+        EmptyRes
+    );
+    impl_service!(
+        read_pool,
+        bookmarks,
+        get_bookmarks,
+        GetBookmarksPayload,
+        GetBookmarksFut,
+        BookmarksRes
+    );
+
+    // Status
+    type HealthFut = FutureResult<HealthPayload, ServiceError>;
+    fn health(&self, _payload: ()) -> Self::HealthFut {
+        future::ok(self.health_status())
+    }
+    type ReadinessFut = ReadinessRes;
+    fn readiness(&self, _payload: ()) -> Self::ReadinessFut {
+        self.readiness_status()
+    }
+    type VersionFut = FutureResult<VersionPayload, ServiceError>;
+    fn version(&self, _payload: ()) -> Self::VersionFut {
+        future::ok(super::status::version())
+    }
+    type MetricsFut = FutureResult<String, ServiceError>;
+    fn metrics(&self, _payload: ()) -> Self::MetricsFut {
+        future::ok(self.metrics.render(&self.db_pool, &self.jobs))
+    }
+    type SetLogLevelFut = FutureResult<LogLevelsPayload, ServiceError>;
+    fn set_log_level(&self, payload: SetLogLevelPayload) -> Self::SetLogLevelFut {
+        future::result(self.change_log_level(payload))
+    }
+    type FlushCacheFut = FutureResult<(), ServiceError>;
+    fn flush_cache(&self, _payload: ()) -> Self::FlushCacheFut {
+        super::cache::flush();
+        future::ok(())
+    }
 }
@@ -0,0 +1,76 @@
+use datatypes::content::requests::*;
+
+use crate::db::{self, DbConn};
+use crate::payloads::*;
+use crate::{IntErrorKind, IntResult};
+
+pub fn subscribe_thread(con: &DbConn, payload: SubscribeThreadPayload) -> IntResult<()> {
+    let SubscribeThreadPayload {
+        thread_id,
+        subscribe,
+        ..
+    } = payload;
+    trace!("subscribe_thread: {:?}", payload);
+
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+
+    if subscribe {
+        db::subscriptions::subscribe_thread(&con, user_id, thread_id)?;
+    } else {
+        db::subscriptions::unsubscribe_thread(&con, user_id, thread_id)?;
+    }
+    Ok(())
+}
+
+pub fn subscribe_category(con: &DbConn, payload: SubscribeCategoryPayload) -> IntResult<()> {
+    let SubscribeCategoryPayload {
+        category_id,
+        subscribe,
+        ..
+    } = payload;
+    trace!("subscribe_category: {:?}", payload);
+
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+
+    if subscribe {
+        db::subscriptions::subscribe_category(&con, user_id, category_id)?;
+    } else {
+        db::subscriptions::unsubscribe_category(&con, user_id, category_id)?;
+    }
+    Ok(())
+}
+
+pub fn get_notifications(
+    con: &DbConn,
+    payload: GetNotificationsPayload,
+) -> IntResult<Vec<NotificationPayload>> {
+    let GetNotificationsPayload {
+        unread_only,
+        mentions_only,
+        offset,
+        limit,
+        ..
+    } = payload;
+    trace!("get_notifications: {:?}", payload);
+
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+
+    db::notifications::get_notifications(&con, user_id, unread_only, mentions_only, offset, limit)
+        .map(|notifications| notifications.into_iter().map(|n| n.into()).collect())
+}
+
+pub fn read_notifications(con: &DbConn, payload: ReadNotificationsPayload) -> IntResult<()> {
+    trace!("read_notifications: {:?}", payload);
+
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+
+    let ids = payload.ids.as_ref().map(|ids| &ids[..]);
+    db::notifications::mark_notifications_read(&con, user_id, ids).map(|_| ())
+}
+
+pub fn count_unread_notifications(con: &DbConn, payload: GetUserPayload) -> IntResult<u32> {
+    let GetUserPayload { id } = payload;
+    trace!("count_unread_notifications: {:?}", payload);
+
+    db::notifications::count_unread_notifications(&con, id).map(|count| count as u32)
+}
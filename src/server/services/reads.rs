@@ -0,0 +1,77 @@
+use failure::ResultExt;
+use std::convert::TryInto;
+
+use crate::db::{self, DbConn};
+use crate::payloads::*;
+use crate::types::Thread;
+use crate::{IntErrorKind, IntResult};
+
+fn to_unread_payload(thread: Thread, unread: i64) -> IntResult<UnreadThreadPayload> {
+    let id = thread.id;
+    thread
+        .try_into()
+        .map(|thread| UnreadThreadPayload {
+            thread,
+            unread: unread as u32,
+        }).context(IntErrorKind::ServerError)
+        .map_err(|e| {
+            error!("Unable to convert thread ({}) to payload: {}", id, e);
+            e.into()
+        })
+}
+
+pub fn mark_thread_read(con: &DbConn, payload: MarkThreadReadPayload) -> IntResult<()> {
+    let MarkThreadReadPayload {
+        thread_id,
+        comment_id,
+        ..
+    } = payload;
+    trace!("mark_thread_read: {:?}", payload);
+
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+
+    db::reads::mark_thread_read(&con, user_id, thread_id, comment_id).map(|_| ())
+}
+
+pub fn get_threads_in_category_for_user(
+    con: &DbConn,
+    payload: GetUserThreadsPayload,
+) -> IntResult<Vec<UnreadThreadPayload>> {
+    let GetUserThreadsPayload {
+        id,
+        include_hidden,
+        user_id,
+    } = payload;
+    trace!("get_threads_in_category_for_user: {:?}", payload);
+
+    let threads = db::threads::get_threads_in_category(&con, id, include_hidden, None)?;
+    let ids = threads.iter().map(|t| t.id).collect::<Vec<_>>();
+    let counts = db::reads::get_unread_counts(&con, user_id, &ids)?;
+
+    threads
+        .into_iter()
+        .map(|thread| {
+            let unread = counts
+                .iter()
+                .find(|c| c.thread_id == thread.id)
+                .map_or(0, |c| c.unread);
+            to_unread_payload(thread, unread)
+        }).collect()
+}
+
+pub fn get_unread_threads(
+    con: &DbConn,
+    payload: GetUnreadThreadsPayload,
+) -> IntResult<Vec<UnreadThreadPayload>> {
+    let GetUnreadThreadsPayload {
+        user_id,
+        offset,
+        limit,
+    } = payload;
+    trace!("get_unread_threads: {:?}", payload);
+
+    db::reads::get_unread_threads(&con, user_id, offset, limit)?
+        .into_iter()
+        .map(|(thread, unread)| to_unread_payload(thread, unread))
+        .collect()
+}
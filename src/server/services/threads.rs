@@ -1,3 +1,4 @@
+use diesel::Connection;
 use failure::ResultExt;
 use std::convert::TryInto;
 
@@ -5,21 +6,32 @@ use datatypes::content::requests::*;
 use datatypes::content::responses::*;
 
 use crate::db::{self, DbConn};
+use crate::payloads::*;
+use crate::server::cache::CACHE;
 use crate::types::Thread;
-use crate::{IntErrorKind, IntResult};
+use crate::{IntError, IntErrorKind, IntResult};
+
+/// Removes a changed thread from the cache
+fn invalidate(id: u32) {
+    CACHE.threads.invalidate(|&(cached, _)| cached == id);
+}
 
 pub fn get_thread(con: &DbConn, payload: GetThreadPayload) -> IntResult<ThreadPayload> {
     let GetThreadPayload { id, include_hidden } = payload;
     trace!("get_thread: {:?}", payload);
 
-    db::threads::get_thread(&con, id, include_hidden).and_then(|p| {
-        <Thread as TryInto<ThreadPayload>>::try_into(p)
-            .context(IntErrorKind::ServerError)
-            .map_err(|e| {
-                error!("Unable to convert thread ({}) to payload: {}", id, e);
-                e.into()
-            })
-    })
+    CACHE
+        .threads
+        .get_or_load((*id, include_hidden), || {
+            db::threads::get_thread(&con, id, include_hidden)
+        }).and_then(|p| {
+            <Thread as TryInto<ThreadPayload>>::try_into(p)
+                .context(IntErrorKind::ServerError)
+                .map_err(|e| {
+                    error!("Unable to convert thread ({}) to payload: {}", id, e);
+                    e.into()
+                })
+        })
 }
 
 pub fn get_threads_in_category(
@@ -29,7 +41,7 @@ pub fn get_threads_in_category(
     let GetThreadsPayload { id, include_hidden } = payload;
     trace!("get_threads_in_category: {:?}", payload);
 
-    db::threads::get_threads_in_category(&con, id, include_hidden).and_then(|threads| {
+    db::threads::get_threads_in_category(&con, id, include_hidden, None).and_then(|threads| {
         threads
             .into_iter()
             .map(|thread| thread.try_into())
@@ -42,6 +54,32 @@ pub fn get_threads_in_category(
     })
 }
 
+pub fn get_threads_in_category_by_answer(
+    con: &DbConn,
+    payload: GetAnsweredThreadsPayload,
+) -> IntResult<Vec<ThreadPayload>> {
+    let GetAnsweredThreadsPayload {
+        id,
+        include_hidden,
+        answered,
+    } = payload;
+    trace!("get_threads_in_category_by_answer: {:?}", payload);
+
+    db::threads::get_threads_in_category(&con, id, include_hidden, Some(answered)).and_then(
+        |threads| {
+            threads
+                .into_iter()
+                .map(|thread| thread.try_into())
+                .collect::<Result<Vec<ThreadPayload>, _>>()
+                .context(IntErrorKind::ServerError)
+                .map_err(|e| {
+                    error!("Unable to convert thread into payload: {}", e);
+                    e.into()
+                })
+        },
+    )
+}
+
 pub fn get_all_threads(con: &DbConn, payload: GetHiddenPayload) -> IntResult<Vec<ThreadPayload>> {
     let GetHiddenPayload { include_hidden } = payload;
     trace!("get_all_threads: {:?}", payload);
@@ -62,9 +100,14 @@ pub fn get_all_threads(con: &DbConn, payload: GetHiddenPayload) -> IntResult<Vec
 pub fn add_thread(con: &DbConn, payload: AddThreadPayload) -> IntResult<ThreadPayload> {
     trace!("add_thread: {:?}", payload);
 
-    let _user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
+    let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
 
-    db::threads::insert_thread(&con, payload).and_then(|p| {
+    con.transaction::<_, IntError, _>(|| {
+        let thread = db::threads::insert_thread(&con, payload)?;
+        db::subscriptions::subscribe_thread(&con, user_id, thread.id.into())?;
+        db::notifications::queue_notifications(&con, thread.id, None)?;
+        Ok(thread)
+    }).and_then(|p| {
         <Thread as TryInto<ThreadPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -82,6 +125,7 @@ pub fn edit_thread(con: &DbConn, payload: EditThreadPayload) -> IntResult<Thread
     let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
 
     db::threads::update_thread(&con, user_id, payload).and_then(|p| {
+        invalidate(p.id);
         <Thread as TryInto<ThreadPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -99,6 +143,7 @@ pub fn hide_thread(con: &DbConn, payload: HideThreadPayload) -> IntResult<Thread
     let user_id = payload.user_id.ok_or(IntErrorKind::InvalidId)?;
 
     db::threads::update_thread(&con, user_id, payload).and_then(|p| {
+        invalidate(p.id);
         <Thread as TryInto<ThreadPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
             .map_err(|e| {
@@ -107,3 +152,39 @@ pub fn hide_thread(con: &DbConn, payload: HideThreadPayload) -> IntResult<Thread
             })
     })
 }
+
+pub fn accept_answer(con: &DbConn, payload: AcceptAnswerPayload) -> IntResult<AnswerPayload> {
+    let AcceptAnswerPayload {
+        thread_id,
+        comment_id,
+        user_id,
+    } = payload;
+
+    trace!("accept_answer: {:?}", payload);
+
+    db::threads::set_answer(&con, thread_id, Some(user_id), comment_id).map(|t| {
+        invalidate(t.id);
+        t.into()
+    })
+}
+
+pub fn moderate_answer(con: &DbConn, payload: ModerateAnswerPayload) -> IntResult<AnswerPayload> {
+    let ModerateAnswerPayload {
+        thread_id,
+        comment_id,
+    } = payload;
+
+    trace!("moderate_answer: {:?}", payload);
+
+    db::threads::set_answer(&con, thread_id, None, comment_id).map(|t| {
+        invalidate(t.id);
+        t.into()
+    })
+}
+
+pub fn get_answer(con: &DbConn, payload: GetThreadPayload) -> IntResult<AnswerPayload> {
+    let GetThreadPayload { id, include_hidden } = payload;
+    trace!("get_answer: {:?}", payload);
+
+    db::threads::get_thread(&con, id, include_hidden).map(|t| t.into())
+}
@@ -1,5 +1,8 @@
 use crate::db::{self, DbConn};
-use crate::types::User;
+use crate::payloads::*;
+use crate::server::cache::CACHE;
+use crate::tools;
+use crate::types::{InsertUser, User, UserStats};
 use crate::{IntErrorKind, IntResult};
 
 use datatypes::content::requests::*;
@@ -7,20 +10,48 @@ use datatypes::content::responses::*;
 
 use failure::ResultExt;
 use std::convert::TryInto;
+use std::io::{self, Write};
+
+/// The size of the largest document returned by `export_user_data`, larger
+/// ones have to be exported with the `export-user` command
+const MAX_EXPORT_SIZE: usize = 16 * 1024 * 1024;
+
+/// A buffer which refuses to grow past `MAX_EXPORT_SIZE`
+#[derive(Default)]
+struct ExportBuffer {
+    data: Vec<u8>,
+    exceeded: bool,
+}
+
+impl Write for ExportBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.data.len() + buf.len() > MAX_EXPORT_SIZE {
+            self.exceeded = true;
+            return Err(io::Error::new(io::ErrorKind::Other, "export too large"));
+        }
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
 pub fn get_user(con: &DbConn, payload: GetUserPayload) -> IntResult<UserPayload> {
     let GetUserPayload { id } = payload;
     trace!("get_user: {:?}", payload);
 
-    db::users::get_user(&con, id).and_then(|p| {
-        trace!("got payload from db: {:?}", p);
-        <User as TryInto<UserPayload>>::try_into(p)
-            .context(IntErrorKind::ServerError)
-            .map_err(|e| {
-                error!("Unable to convert user ({}) to payload: {}", id, e);
-                e.into()
-            })
-    })
+    CACHE
+        .users
+        .get_or_load(*id, || db::users::get_user(&con, id))
+        .and_then(|p| {
+            <User as TryInto<UserPayload>>::try_into(p)
+                .context(IntErrorKind::ServerError)
+                .map_err(|e| {
+                    error!("Unable to convert user ({}) to payload: {}", id, e);
+                    e.into()
+                })
+        })
 }
 
 pub fn add_user(con: &DbConn, payload: AddUserPayload) -> IntResult<UserPayload> {
@@ -43,6 +74,7 @@ pub fn edit_user(con: &DbConn, payload: EditUserPayload) -> IntResult<UserPayloa
     let user_id = payload.id.ok_or(IntErrorKind::InvalidId)?;
 
     db::users::update_user(con, user_id, payload).and_then(|p| {
+        CACHE.users.invalidate(|&cached| cached == p.id);
         trace!("got payload from db: {:?}", p);
         <User as TryInto<UserPayload>>::try_into(p)
             .context(IntErrorKind::ServerError)
@@ -52,3 +84,160 @@ pub fn edit_user(con: &DbConn, payload: EditUserPayload) -> IntResult<UserPayloa
             })
     })
 }
+
+pub fn get_user_by_username(
+    con: &DbConn,
+    payload: GetUserByUsernamePayload,
+) -> IntResult<UserPayload> {
+    trace!("get_user_by_username: {:?}", payload);
+
+    let GetUserByUsernamePayload { username } = payload;
+
+    db::users::get_user_by_username(&con, &username).and_then(|p| {
+        trace!("got payload from db: {:?}", p);
+        <User as TryInto<UserPayload>>::try_into(p)
+            .context(IntErrorKind::ServerError)
+            .map_err(|e| {
+                error!("Unable to convert user ({}) to payload: {}", username, e);
+                e.into()
+            })
+    })
+}
+
+pub fn get_users(con: &DbConn, payload: GetUsersPayload) -> IntResult<Vec<UserPayload>> {
+    trace!("get_users: {:?}", payload);
+
+    let ids: Vec<u32> = payload.ids.iter().map(|id| **id).collect();
+
+    db::users::get_users(&con, &ids).and_then(|users| {
+        users
+            .into_iter()
+            .map(|user| user.try_into())
+            .collect::<Result<Vec<UserPayload>, _>>()
+            .context(IntErrorKind::ServerError)
+            .map_err(|e| {
+                error!("Unable to convert user to payload: {}", e);
+                e.into()
+            })
+    })
+}
+
+pub fn rename_user(con: &DbConn, payload: RenameUserPayload) -> IntResult<UserPayload> {
+    trace!("rename_user: {:?}", payload);
+
+    payload.id.ok_or(IntErrorKind::InvalidId)?;
+
+    let InsertUser { id, username } = payload.try_into().context(IntErrorKind::InvalidId)?;
+
+    db::users::rename_user(&con, id.into(), &username).and_then(|p| {
+        CACHE.users.invalidate(|&cached| cached == p.id);
+        trace!("got payload from db: {:?}", p);
+        <User as TryInto<UserPayload>>::try_into(p)
+            .context(IntErrorKind::ServerError)
+            .map_err(|e| {
+                error!("Unable to convert user ({}) to payload: {}", id, e);
+                e.into()
+            })
+    })
+}
+
+pub fn get_username_history(
+    con: &DbConn,
+    payload: GetUserPayload,
+) -> IntResult<Vec<UsernameChangePayload>> {
+    let GetUserPayload { id } = payload;
+    trace!("get_username_history: {:?}", payload);
+
+    db::users::get_username_history(&con, id)
+        .map(|changes| changes.into_iter().map(|c| c.into()).collect())
+}
+
+pub fn delete_user(con: &DbConn, payload: DeleteUserPayload) -> IntResult<()> {
+    let DeleteUserPayload {
+        id,
+        actor_id,
+        remove_content,
+    } = payload;
+    trace!("delete_user: {:?}", payload);
+
+    let id = id.ok_or(IntErrorKind::InvalidId)?;
+
+    db::users::delete_user(&con, id, actor_id, remove_content).map(|_| {
+        // The threads of the user now belong to the placeholder user
+        CACHE.users.invalidate(|&cached| cached == *id);
+        CACHE.threads.clear();
+    })
+}
+
+/// Exports everything stored about a user, refusing documents larger than
+/// `MAX_EXPORT_SIZE`, which are built in memory
+pub fn export_user_data(con: &DbConn, payload: GetUserPayload) -> IntResult<String> {
+    let GetUserPayload { id } = payload;
+    trace!("export_user_data: {:?}", payload);
+
+    let mut data = ExportBuffer::default();
+    let result = db::export::in_snapshot(&con, || {
+        tools::user_data::write_user_data(&con, id, &mut data)
+    });
+    if data.exceeded {
+        error!(
+            "Data of user ({}) is larger than {} bytes, it has to be exported with the \
+             export-user command",
+            id, MAX_EXPORT_SIZE
+        );
+        return Err(IntErrorKind::Refused.into());
+    }
+    result?;
+
+    String::from_utf8(data.data)
+        .context(IntErrorKind::ServerError)
+        .map_err(|e| {
+            error!("Unable to convert data of user ({}) to a string: {}", id, e);
+            e.into()
+        })
+}
+
+pub fn get_user_profile(con: &DbConn, payload: GetUserPayload) -> IntResult<UserProfilePayload> {
+    let GetUserPayload { id } = payload;
+    trace!("get_user_profile: {:?}", payload);
+
+    // The profile only shows the content which is visible to everyone
+    let stats = db::activity::get_user_stats(&con, id, false)?;
+
+    db::users::get_user(&con, id).and_then(|p| {
+        trace!("got payload from db: {:?}", p);
+        <(User, UserStats) as TryInto<UserProfilePayload>>::try_into((p, stats))
+            .context(IntErrorKind::ServerError)
+            .map_err(|e| {
+                error!("Unable to convert user ({}) to profile payload: {}", id, e);
+                e.into()
+            })
+    })
+}
+
+pub fn get_user_activity(
+    con: &DbConn,
+    payload: GetUserActivityPayload,
+) -> IntResult<Vec<ActivityPayload>> {
+    let GetUserActivityPayload {
+        user_id,
+        include_hidden,
+        offset,
+        limit,
+    } = payload;
+    trace!("get_user_activity: {:?}", payload);
+
+    db::activity::get_user_activity(&con, user_id, include_hidden, offset, limit).and_then(
+        |activity| {
+            activity
+                .into_iter()
+                .map(|a| a.try_into())
+                .collect::<Result<Vec<ActivityPayload>, _>>()
+                .context(IntErrorKind::ServerError)
+                .map_err(|e| {
+                    error!("Unable to convert activity to payload: {}", e);
+                    e.into()
+                })
+        },
+    )
+}
@@ -0,0 +1,83 @@
+//! Graceful shutdown of the server on SIGTERM or SIGINT
+use futures::{Future, Stream};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio_core::reactor::Handle;
+use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+/// Counts the requests which are queued or running on the worker pool, so
+/// that a shutdown can wait for them to finish
+#[derive(Debug, Default)]
+pub struct Jobs {
+    running: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+impl Jobs {
+    /// Counts a job as running until the returned guard is dropped
+    pub fn start(jobs: &Arc<Jobs>) -> JobGuard {
+        jobs.running.fetch_add(1, Ordering::SeqCst);
+        JobGuard(jobs.clone())
+    }
+
+    /// The number of jobs which are queued or running
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// The number of jobs which have finished
+    pub fn finished(&self) -> usize {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
+
+/// Marks a job as finished when dropped
+#[derive(Debug)]
+pub struct JobGuard(Arc<Jobs>);
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::SeqCst);
+        self.0.finished.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Resolves with the name of the first SIGTERM or SIGINT received
+pub fn signal(handle: &Handle) -> impl Future<Item = &'static str, Error = io::Error> {
+    let handle = handle.new_tokio_handle();
+    let wait = |signal, name: &'static str| {
+        Signal::with_handle(signal, &handle)
+            .flatten_stream()
+            .into_future()
+            .map(move |_| name)
+            .map_err(|(e, _)| e)
+    };
+
+    wait(SIGTERM, "SIGTERM")
+        .select(wait(SIGINT, "SIGINT"))
+        .map(|(name, _)| name)
+        .map_err(|(e, _)| e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_jobs() {
+        let jobs = Arc::new(Jobs::default());
+
+        let first = Jobs::start(&jobs);
+        let second = Jobs::start(&jobs);
+        assert_eq!(jobs.running(), 2);
+
+        drop(first);
+        assert_eq!(jobs.running(), 1);
+        assert_eq!(jobs.finished(), 1);
+
+        drop(second);
+        assert_eq!(jobs.running(), 0);
+        assert_eq!(jobs.finished(), 2);
+    }
+}
@@ -0,0 +1,98 @@
+//! The health, readiness and version of the server, for orchestrators and
+//! monitoring, and its log levels
+use futures::future;
+use log::LevelFilter;
+use std::convert::TryFrom;
+
+use super::workers::Work;
+use super::Server;
+use crate::migration::{get_schema_version, SCHEMA_VERSION};
+use crate::payloads::*;
+use crate::{IntError, IntErrorKind};
+
+impl Server {
+    /// Answers whether the server is alive
+    pub(super) fn health_status(&self) -> HealthPayload {
+        HealthPayload {
+            uptime: self.started.elapsed().as_secs(),
+        }
+    }
+
+    /// Checks whether the server is able to serve requests
+    ///
+    /// The check runs on the read pool, so a full read pool also delays or
+    /// rejects the answer. The check itself is not counted in the queue depth.
+    pub(super) fn readiness_status(&self) -> Work<ReadinessPayload> {
+        let db_pool = self.db_pool.clone();
+        let queue_depth = self.jobs.running();
+        let max_queue_depth = self.max_queue_depth;
+
+        self.read_pool.spawn("readiness", future::lazy(move || {
+            let state = db_pool.state();
+            let (pool_available, schema_version) = match db_pool.try_get() {
+                Some(con) => (true, get_schema_version(&con).ok().and_then(|v| v)),
+                None => (false, None),
+            };
+
+            let ready = pool_available
+                && schema_version.as_ref().map(String::as_str) == Some(SCHEMA_VERSION)
+                && queue_depth < max_queue_depth;
+            if !ready {
+                warn!(
+                    "Not ready, pool available: {}, schema version: {:?}, queue depth: {}",
+                    pool_available, schema_version, queue_depth
+                );
+            }
+
+            Ok(ReadinessPayload {
+                ready,
+                pool_available,
+                connections: state.connections,
+                idle_connections: state.idle_connections,
+                schema_version,
+                expected_schema_version: SCHEMA_VERSION.to_string(),
+                queue_depth: u32::try_from(queue_depth).unwrap_or(u32::max_value()),
+                max_queue_depth: u32::try_from(max_queue_depth).unwrap_or(u32::max_value()),
+            })
+        }))
+    }
+
+    /// Changes a log level and answers with the current log levels
+    pub(super) fn change_log_level(
+        &self,
+        payload: SetLogLevelPayload,
+    ) -> Result<LogLevelsPayload, ServiceError> {
+        let invalid = || -> ServiceError { IntError::from(IntErrorKind::InvalidLogLevel).into() };
+        let level = match payload.level {
+            Some(level) => Some(level.parse::<LevelFilter>().map_err(|_| invalid())?),
+            None => None,
+        };
+        if payload.module.is_none() && level.is_none() {
+            return Err(invalid());
+        }
+        self.log_levels.set(payload.module.as_ref().map(String::as_str), level);
+
+        let (level, modules) = self.log_levels.get();
+        Ok(LogLevelsPayload {
+            level: level.to_string(),
+            modules: modules
+                .into_iter()
+                .map(|(module, level)| (module, level.to_string()))
+                .collect(),
+        })
+    }
+}
+
+/// Describes the build of the server
+pub(super) fn version() -> VersionPayload {
+    VersionPayload {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: env!("CONTROLLER_GIT_HASH").to_string(),
+        schema_version: SCHEMA_VERSION.to_string(),
+        features: env!("CONTROLLER_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(String::from)
+            .collect(),
+    }
+}
@@ -0,0 +1,220 @@
+//! Bounded pools of threads running requests, and the deadlines of requests
+use diesel::r2d2::{self, PooledConnection};
+use futures::future::{self, Either, FutureResult, MapErr};
+use futures::Future;
+use futures_cpupool::{CpuFuture, CpuPool};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::metrics::Metrics;
+use crate::config::ServerConfig;
+use crate::db::{self, DbConnectionManager, DbPool};
+use crate::payloads::ServiceError;
+use crate::{IntError, IntErrorKind};
+
+use datatypes::content::responses::ContentError;
+
+/// The result of a request run on a worker pool, which fails right away if
+/// the pool is full
+pub type Work<T> = Either<CpuFuture<T, ServiceError>, FutureResult<T, ServiceError>>;
+
+/// The result of an RPC of `datatypes`, whose clients only know the errors
+/// which a `ContentError` can express
+pub type ContentWork<W> = MapErr<W, fn(ServiceError) -> ContentError>;
+
+/// A pool of threads with a bounded queue of requests
+///
+/// Requests are rejected as overloaded when all the threads are busy and
+/// `queue_size` requests are already waiting, instead of queueing until the
+/// clients give up.
+#[derive(Clone)]
+pub struct WorkerPool {
+    name: &'static str,
+    pool: CpuPool,
+    /// The number of requests queued or running
+    queued: Arc<AtomicUsize>,
+    capacity: usize,
+    metrics: Arc<Metrics>,
+}
+
+/// Removes a request from the queue of its pool when dropped
+struct QueueGuard(Arc<AtomicUsize>);
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WorkerPool {
+    /// Makes a pool with the given number of threads, or one per cpu if 0
+    pub fn new(
+        name: &'static str,
+        threads: usize,
+        queue_size: usize,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let threads = match threads {
+            0 => num_cpus::get(),
+            threads => threads,
+        };
+
+        WorkerPool {
+            name,
+            pool: CpuPool::new(threads),
+            queued: Arc::new(AtomicUsize::new(0)),
+            capacity: threads + queue_size,
+            metrics,
+        }
+    }
+
+    /// Runs a request of `rpc` on the pool, unless the pool is full
+    pub fn spawn<F, T>(&self, rpc: &'static str, f: F) -> Work<T>
+    where
+        F: Future<Item = T, Error = ServiceError> + Send + 'static,
+        T: Send + 'static,
+    {
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.capacity {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            warn!("The {} pool is full, rejecting {}", self.name, rpc);
+
+            let error: ServiceError = IntError::from(IntErrorKind::Overloaded).into();
+            self.metrics.record_overloaded();
+            self.metrics.record(rpc, Duration::from_secs(0), Some(&error));
+            return Either::B(future::err(error));
+        }
+
+        let guard = QueueGuard(self.queued.clone());
+        Either::A(self.pool.spawn(f.then(move |result| {
+            drop(guard);
+            result
+        })))
+    }
+
+    /// The number of requests queued or running
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+/// The time each RPC may take, including the time spent queued, waiting for a
+/// database connection and running statements
+#[derive(Debug, Clone)]
+pub struct Deadlines {
+    default: Option<Duration>,
+    rpcs: BTreeMap<String, Option<Duration>>,
+}
+
+/// Converts a number of milliseconds to a deadline, where 0 means none
+fn millis(millis: u64) -> Option<Duration> {
+    if millis == 0 {
+        None
+    } else {
+        Some(Duration::from_millis(millis))
+    }
+}
+
+impl Deadlines {
+    pub fn new(config: &ServerConfig) -> Self {
+        Deadlines {
+            default: millis(config.deadline_ms),
+            rpcs: config
+                .deadlines
+                .iter()
+                .map(|(rpc, &ms)| (rpc.clone(), millis(ms)))
+                .collect(),
+        }
+    }
+
+    /// The deadline of an RPC, if it has any
+    pub fn get(&self, rpc: &str) -> Option<Duration> {
+        self.rpcs.get(rpc).cloned().unwrap_or(self.default)
+    }
+}
+
+/// Gets a database connection for a request, waiting at most until the
+/// deadline of the request
+///
+/// Fails with `DeadlineExceeded` if the request was queued past its deadline
+/// and with `ServiceBusy` if no connection became available in time. Each
+/// statement of the request is limited to the time left until the deadline,
+/// and fails once it runs past it.
+pub fn connection(
+    db_pool: &DbPool,
+    metrics: &Metrics,
+    started: Instant,
+    deadline: Option<Duration>,
+) -> Result<PooledConnection<DbConnectionManager>, ServiceError> {
+    let busy = |e: r2d2::Error| -> ServiceError {
+        warn!("no database connection available, service busy: {}", e);
+        metrics.record_busy();
+        IntError::from(IntErrorKind::ServiceBusy).into()
+    };
+
+    let con = match deadline.map(|deadline| deadline.checked_sub(started.elapsed())) {
+        Some(Some(remaining)) => db_pool.get_timeout(remaining).map_err(busy)?,
+        Some(None) => {
+            warn!("deadline exceeded while queued");
+            metrics.record_timeout();
+            return Err(IntError::from(IntErrorKind::DeadlineExceeded).into());
+        }
+        None => db_pool.get().map_err(busy)?,
+    };
+
+    let remaining = deadline.map(|deadline| {
+        deadline
+            .checked_sub(started.elapsed())
+            .unwrap_or_else(|| Duration::from_millis(0))
+    });
+    db::limit_statement_time(&con, remaining).map_err(|e| -> ServiceError { e.into() })?;
+
+    Ok(con)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn reject_when_full() {
+        let pool = WorkerPool::new("test", 1, 1, Arc::new(Metrics::default()));
+        let (release, wait) = mpsc::channel::<()>();
+
+        // One request runs and one waits, filling the pool
+        let running = pool.spawn(
+            "test",
+            future::lazy(move || {
+                wait.recv().ok();
+                Ok(())
+            }),
+        );
+        let queued = pool.spawn("test", future::ok(()));
+        assert_eq!(pool.queued(), 2);
+
+        match pool.spawn("test", future::ok(())).wait() {
+            Err(ServiceError::Overloaded) => {}
+            other => panic!("expected the request to be rejected, got {:?}", other),
+        }
+
+        release.send(()).unwrap();
+        assert!(running.wait().is_ok());
+        assert!(queued.wait().is_ok());
+        assert_eq!(pool.queued(), 0);
+    }
+
+    #[test]
+    fn deadlines() {
+        let mut config = ServerConfig::default();
+        config.deadline_ms = 1000;
+        config.deadlines.insert("search".to_string(), 5000);
+        config.deadlines.insert("export_user_data".to_string(), 0);
+        let deadlines = Deadlines::new(&config);
+
+        assert_eq!(deadlines.get("get_user"), Some(Duration::from_secs(1)));
+        assert_eq!(deadlines.get("search"), Some(Duration::from_secs(5)));
+        assert_eq!(deadlines.get("export_user_data"), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Deterministic pseudonymization of exported rows
+//!
+//! Usernames, user descriptions, avatars, thread titles and descriptions and
+//! the content of comments are replaced with generated text of the same
+//! length. Ids, parents, timestamps and hidden flags are kept, so that the
+//! structure of the forum is the same as in the original. The generated text
+//! only depends on the salt, the id of the row and the field, so the same
+//! salt always gives the same snapshot.
+use super::rng::{hash, Rng};
+use crate::types::{Comment, Thread, User};
+
+/// The longest username (the width of the column)
+const MAX_USERNAME_LEN: usize = 20;
+
+const WORDS: &[&str] = &[
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit", "sed", "do",
+    "eiusmod", "tempor", "incididunt", "ut", "labore", "et", "dolore", "magna", "aliqua", "enim",
+    "ad", "minim", "veniam", "quis", "nostrud", "exercitation", "ullamco", "laboris", "nisi",
+    "aliquip", "ex", "ea", "commodo", "consequat",
+];
+
+pub struct Anonymizer {
+    salt: String,
+}
+
+impl Anonymizer {
+    pub fn new(salt: &str) -> Anonymizer {
+        Anonymizer {
+            salt: salt.to_owned(),
+        }
+    }
+
+    fn rng(&self, field: &str, id: u32) -> Rng {
+        let id = id.to_string();
+        Rng::new(hash(&[self.salt.as_bytes(), field.as_bytes(), id.as_bytes()]))
+    }
+
+    pub fn user(&self, user: User) -> User {
+        let id = user.id;
+        User {
+            username: username(&mut self.rng("username", id), id, user.username.len()),
+            description: user
+                .description
+                .map(|d| text(&mut self.rng("user.description", id), d.chars().count())),
+            avatar: user.avatar.map(|a| hex(&mut self.rng("avatar", id), &a)),
+            ..user
+        }
+    }
+
+    pub fn thread(&self, thread: Thread) -> Thread {
+        let id = thread.id;
+        Thread {
+            title: text(&mut self.rng("thread.title", id), thread.title.chars().count()),
+            description: text(
+                &mut self.rng("thread.description", id),
+                thread.description.chars().count(),
+            ),
+            ..thread
+        }
+    }
+
+    pub fn comment(&self, comment: Comment) -> Comment {
+        let id = comment.id;
+        Comment {
+            content: text(&mut self.rng("comment", id), comment.content.chars().count()),
+            ..comment
+        }
+    }
+}
+
+/// Generates a username of about `len` characters
+///
+/// The username is a word followed by the id of the user, which keeps the
+/// usernames unique.
+pub(super) fn username(rng: &mut Rng, id: u32, len: usize) -> String {
+    let suffix = id.to_string();
+    let letters = len
+        .min(MAX_USERNAME_LEN)
+        .saturating_sub(suffix.len())
+        .max(1);
+
+    let mut username = String::with_capacity(letters + suffix.len());
+    for _ in 0..letters {
+        username.push((b'a' + rng.below(26) as u8) as char);
+    }
+    username.push_str(&suffix);
+    username
+}
+
+/// Generates text of words which is exactly `len` characters long
+pub(super) fn text(rng: &mut Rng, len: usize) -> String {
+    let mut text = String::with_capacity(len + 16);
+    while text.len() < len {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(WORDS[rng.below(WORDS.len() as u64) as usize]);
+    }
+    text.truncate(len);
+
+    // Avoid trailing whitespace, which might not be valid content
+    if text.ends_with(' ') {
+        text.pop();
+        text.push('a');
+    }
+    text
+}
+
+/// Replaces every hexadecimal digit in a string with a random one, keeping
+/// the rest (such as the dashes of a UUID)
+fn hex(rng: &mut Rng, original: &str) -> String {
+    original
+        .chars()
+        .map(|c| {
+            if c.is_ascii_hexdigit() {
+                b"0123456789abcdef"[rng.below(16) as usize] as char
+            } else {
+                c
+            }
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_length() {
+        let mut rng = Rng::new(1);
+        for len in 0..100 {
+            assert_eq!(text(&mut rng, len).len(), len);
+        }
+        assert_eq!(username(&mut rng, 123, 8).len(), 8);
+        assert_eq!(username(&mut rng, 123, 40).len(), MAX_USERNAME_LEN);
+    }
+
+    #[test]
+    fn deterministic() {
+        let a = Anonymizer::new("salt");
+        let b = Anonymizer::new("salt");
+        let c = Anonymizer::new("pepper");
+        assert_eq!(
+            text(&mut a.rng("comment", 1), 50),
+            text(&mut b.rng("comment", 1), 50)
+        );
+        assert_ne!(
+            text(&mut a.rng("comment", 1), 50),
+            text(&mut c.rng("comment", 1), 50)
+        );
+    }
+}
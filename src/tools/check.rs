@@ -0,0 +1,89 @@
+//! The `db check` subcommand, which reports and optionally repairs
+//! inconsistencies in the database
+use std::io::{self, Write};
+
+use failure::ResultExt;
+
+use crate::db;
+use crate::db::check::{Severity, Violations};
+use crate::{IntErrorKind, IntResult};
+
+/// The number of ids of violating rows listed per check in the text report
+const LISTED_IDS: usize = 10;
+
+/// The report written by `--json`
+#[derive(Serialize)]
+struct Report<'a> {
+    /// Whether there were no errors left after repairs
+    ok: bool,
+    checks: &'a [Violations],
+}
+
+/// Writes a line per check, listing some of the violating rows
+fn write_text(mut out: impl Write, checks: &[Violations]) -> io::Result<()> {
+    for violations in checks {
+        if violations.ids.is_empty() {
+            writeln!(out, "ok      {}", violations.check)?;
+            continue;
+        }
+
+        let status = match (violations.severity, violations.fixed) {
+            (_, Some(_)) => "fixed",
+            (Severity::Error, None) => "error",
+            (Severity::Warning, None) => "warning",
+        };
+        let ids = violations
+            .ids
+            .iter()
+            .take(LISTED_IDS)
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let more = if violations.ids.len() > LISTED_IDS {
+            ", ..."
+        } else {
+            ""
+        };
+
+        writeln!(
+            out,
+            "{:<7} {}: {} {} ({}{})",
+            status,
+            violations.check,
+            violations.ids.len(),
+            violations.description,
+            ids,
+            more
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs the `db check` subcommand
+///
+/// Fails if errors are left after any repairs, so that it can be used as a
+/// step in CI.
+pub fn run(database_url: &str, args: &clap::ArgMatches) -> IntResult<()> {
+    let con = db::establish_connection(database_url)?;
+    let checks = db::check::check(&con, args.is_present("fix"))?;
+    let ok = !checks.iter().any(Violations::failed);
+
+    let stdout = io::stdout();
+    if args.is_present("json") {
+        let report = Report {
+            ok,
+            checks: &checks,
+        };
+        serde_json::to_writer(stdout.lock(), &report).context(IntErrorKind::IoError)?;
+        println!();
+    } else {
+        write_text(stdout.lock(), &checks).context(IntErrorKind::IoError)?;
+    }
+
+    if ok {
+        Ok(())
+    } else {
+        error!("The database is inconsistent, use --fix to repair it");
+        Err(IntErrorKind::InvalidData.into())
+    }
+}
@@ -0,0 +1,38 @@
+//! Maintenance subcommands of the controller, which are run instead of the
+//! server
+pub mod anonymize;
+pub mod check;
+pub mod reset;
+pub mod rng;
+pub mod seed;
+pub mod snapshot;
+pub mod user_data;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+use crate::{IntErrorKind, IntResult};
+use failure::ResultExt;
+
+/// The number of rows which are read from the database at a time
+const BATCH_SIZE: i64 = 500;
+
+/// Opens a file for writing, or stdout if the path is `-`
+fn open_output(path: &str) -> IntResult<Box<dyn Write>> {
+    if path == "-" {
+        Ok(Box::new(BufWriter::new(io::stdout())))
+    } else {
+        let file = File::create(path).context(IntErrorKind::IoError)?;
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+/// Opens a file for reading, or stdin if the path is `-`
+fn open_input(path: &str) -> IntResult<Box<dyn BufRead>> {
+    if path == "-" {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        let file = File::open(path).context(IntErrorKind::IoError)?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
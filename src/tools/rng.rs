@@ -0,0 +1,72 @@
+//! A small deterministic random number generator
+//!
+//! Generated data has to be the same for the same seed on every platform and
+//! with every version of the controller, which is why neither the standard
+//! library hashers nor an external generator are used.
+
+/// The SplitMix64 generator
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, where `n` has to be above zero
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// A number in `0.0..1.0`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Whether an event with probability `p` happens
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    /// An index in `0..n`, where low indices are much more likely than high
+    /// ones, following a power law with the given exponent
+    pub fn power_law(&mut self, n: usize, exponent: f64) -> usize {
+        ((n as f64 * self.next_f64().powf(exponent)) as usize).min(n - 1)
+    }
+}
+
+/// Hashes a sequence of byte strings with 64 bit FNV-1a, to seed a generator
+pub fn hash(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.iter().chain(&[0xff]) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let mut a = Rng::new(hash(&[b"seed"]));
+        let mut b = Rng::new(hash(&[b"seed"]));
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(hash(&[b"a", b"bc"]), hash(&[b"ab", b"c"]));
+    }
+}
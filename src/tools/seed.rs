@@ -0,0 +1,358 @@
+//! Generation of synthetic forums for testing
+//!
+//! The generated forum is the same for the same scale and seed, except for
+//! its ids, which follow the ids of the existing content. Activity follows a
+//! power law, so that a few users write most of the threads and comments and
+//! a few threads get most of the comments. Threads are mostly written in
+//! bursts of activity, comments reply to each other in deep chains,
+//! categories are nested in each other, and a share of the categories,
+//! threads and comments is hidden.
+use chrono::naive::NaiveDateTime;
+use chrono::Duration;
+use diesel::Connection;
+
+use super::anonymize::{text, username};
+use super::rng::{hash, Rng};
+use super::snapshot::Counts;
+use super::BATCH_SIZE;
+use crate::db::{self, DbConn};
+use crate::types::{Category, Comment, Thread, User};
+use crate::{IntError, IntErrorKind, IntResult};
+
+const USERS_PER_SCALE: usize = 100;
+const CATEGORIES_PER_SCALE: usize = 4;
+const THREADS_PER_SCALE: usize = 250;
+/// The most comments in a single thread
+const MAX_COMMENTS: usize = 200;
+
+/// The exponent of the power law of the authors and categories
+const ACTIVITY_EXPONENT: f64 = 2.5;
+/// The exponent of the power law of the number of comments in a thread
+const COMMENTS_EXPONENT: f64 = 4.0;
+
+/// The share of hidden categories, threads and comments
+const HIDDEN_SHARE: f64 = 0.04;
+/// The share of threads written in bursts of activity
+const BURST_SHARE: f64 = 0.7;
+/// The number of bursts of activity per scale
+const BURSTS_PER_SCALE: usize = 6;
+/// The chance that a comment replies to another comment
+const REPLY_CHANCE: f64 = 0.6;
+/// The chance that a category is a subcategory of another category
+const SUBCATEGORY_CHANCE: f64 = 0.6;
+/// The most levels of subcategories below a top-level category
+const MAX_CATEGORY_DEPTH: usize = 2;
+
+/// The time the generated activity starts, which is fixed so that the
+/// timestamps are the same for the same seed (2017-01-01T00:00:00)
+const START: i64 = 1_483_228_800;
+/// The number of days the generated activity spans
+const DAYS: i64 = 365;
+
+/// Generates a forum of the given scale and inserts it in a single
+/// transaction after the existing content
+pub fn seed(con: &DbConn, scale: usize, seed: u64) -> IntResult<Counts> {
+    trace!("Seeding with scale {} and seed {}", scale, seed);
+
+    if scale == 0 {
+        return Err(IntErrorKind::InvalidData.into());
+    }
+
+    con.transaction::<_, IntError, _>(|| {
+        let max_ids = db::import::get_max_ids(con)?;
+        let forum = Generator::new(scale, seed, max_ids).generate();
+
+        for users in forum.users.chunks(BATCH_SIZE as usize) {
+            db::import::insert_users(con, users)?;
+        }
+        for categories in forum.categories.chunks(BATCH_SIZE as usize) {
+            db::import::insert_categories(con, categories)?;
+        }
+        for threads in forum.threads.chunks(BATCH_SIZE as usize) {
+            db::import::insert_threads(con, threads)?;
+        }
+        for comments in forum.comments.chunks(BATCH_SIZE as usize) {
+            db::import::insert_comments(con, comments)?;
+        }
+
+        Ok(Counts {
+            users: forum.users.len(),
+            categories: forum.categories.len(),
+            threads: forum.threads.len(),
+            comments: forum.comments.len(),
+        })
+    })
+}
+
+struct Forum {
+    users: Vec<User>,
+    categories: Vec<Category>,
+    threads: Vec<Thread>,
+    comments: Vec<Comment>,
+}
+
+struct Generator {
+    rng: Rng,
+    scale: usize,
+    /// The highest existing ids of users, categories, threads and comments
+    max_ids: (u32, u32, u32, u32),
+}
+
+impl Generator {
+    fn new(scale: usize, seed: u64, max_ids: (u32, u32, u32, u32)) -> Generator {
+        Generator {
+            rng: Rng::new(hash(&[seed.to_string().as_bytes()])),
+            scale,
+            max_ids,
+        }
+    }
+
+    fn generate(mut self) -> Forum {
+        let mut users = self.users();
+        let categories = self.categories();
+        let threads = self.threads(&mut users, &categories);
+        let comments = self.comments(&mut users, &threads);
+
+        Forum {
+            users,
+            categories,
+            threads,
+            comments,
+        }
+    }
+
+    fn timestamp(&self, seconds: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp(START + seconds, 0)
+    }
+
+    fn text(&mut self, min: usize, max: usize) -> String {
+        let len = min + self.rng.below((max - min) as u64 + 1) as usize;
+        text(&mut self.rng, len)
+    }
+
+    fn users(&mut self) -> Vec<User> {
+        let first_id = self.max_ids.0 + 1;
+        let duration = DAYS * 24 * 60 * 60;
+        (0..USERS_PER_SCALE * self.scale)
+            .map(|i| {
+                let id = first_id + i as u32;
+                let len = 4 + self.rng.below(12) as usize;
+                User {
+                    id,
+                    username: username(&mut self.rng, id, len),
+                    description: if self.rng.chance(0.3) {
+                        Some(self.text(10, 120))
+                    } else {
+                        None
+                    },
+                    avatar: None,
+                    joined: self.timestamp(self.rng.below(duration as u64) as i64),
+                }
+            }).collect()
+    }
+
+    fn categories(&mut self) -> Vec<Category> {
+        let first_id = self.max_ids.1 + 1;
+        let mut categories = Vec::new();
+        let mut depths = Vec::new();
+
+        for i in 0..CATEGORIES_PER_SCALE * self.scale {
+            // Subcategories are nested in earlier categories, so that parents
+            // are inserted first
+            let parent = if i > 0 && self.rng.chance(SUBCATEGORY_CHANCE) {
+                Some(self.rng.below(i as u64) as usize)
+            } else {
+                None
+            };
+            let parent = parent.filter(|&parent| depths[parent] < MAX_CATEGORY_DEPTH);
+            depths.push(parent.map_or(0, |parent| depths[parent] + 1));
+
+            categories.push(Category {
+                id: first_id + i as u32,
+                title: self.text(5, 30),
+                description: self.text(20, 200),
+                hidden: self.rng.chance(HIDDEN_SHARE),
+                qa: false,
+                parent_id: parent.map(|parent| first_id + parent as u32),
+            });
+        }
+
+        categories
+    }
+
+    fn threads(&mut self, users: &mut [User], categories: &[Category]) -> Vec<Thread> {
+        let first_id = self.max_ids.2 + 1;
+        let duration = DAYS * 24 * 60 * 60;
+        let bursts = (0..BURSTS_PER_SCALE * self.scale)
+            .map(|_| self.rng.below(duration as u64) as i64)
+            .collect::<Vec<_>>();
+
+        // Spread the threads in time, mostly in bursts of a few hours
+        let mut times = (0..THREADS_PER_SCALE * self.scale)
+            .map(|_| {
+                if self.rng.chance(BURST_SHARE) {
+                    let burst = bursts[self.rng.below(bursts.len() as u64) as usize];
+                    (burst + self.rng.below(6 * 60 * 60) as i64).min(duration)
+                } else {
+                    self.rng.below(duration as u64) as i64
+                }
+            }).collect::<Vec<_>>();
+        times.sort();
+
+        times
+            .into_iter()
+            .enumerate()
+            .map(|(i, time)| {
+                let user = self.rng.power_law(users.len(), ACTIVITY_EXPONENT);
+                let user = &mut users[user];
+                let category = self.rng.power_law(categories.len(), ACTIVITY_EXPONENT);
+                let category = &categories[category];
+                let timestamp = self.timestamp(time);
+                record(user, timestamp);
+
+                Thread {
+                    id: first_id + i as u32,
+                    category_id: category.id,
+                    user_id: user.id,
+                    title: self.text(5, 40),
+                    description: self.text(20, 1000),
+                    timestamp,
+                    hidden: self.rng.chance(HIDDEN_SHARE),
+                    answer_id: None,
+                }
+            }).collect()
+    }
+
+    fn comments(&mut self, users: &mut [User], threads: &[Thread]) -> Vec<Comment> {
+        let mut comments: Vec<Comment> = Vec::new();
+        let end = self.timestamp(DAYS * 24 * 60 * 60);
+
+        for thread in threads {
+            let count = self.rng.power_law(MAX_COMMENTS + 1, COMMENTS_EXPONENT);
+            let first = comments.len();
+            let mut time = thread.timestamp;
+
+            for _ in 0..count {
+                // Comments come quickly at first, and then slow down
+                let gap = 60 + self.rng.below(60 * 60 * (1 + (comments.len() - first) as u64));
+                time = (time + Duration::seconds(gap as i64)).min(end);
+
+                // Replies mostly go to recent comments, which makes deep chains
+                let parent_id = if comments.len() > first && self.rng.chance(REPLY_CHANCE) {
+                    let back = self.rng.power_law(comments.len() - first, ACTIVITY_EXPONENT);
+                    Some(comments[comments.len() - 1 - back].id)
+                } else {
+                    None
+                };
+
+                let user = self.rng.power_law(users.len(), ACTIVITY_EXPONENT);
+                let user = &mut users[user];
+                record(user, time);
+
+                comments.push(Comment {
+                    id: self.max_ids.3 + 1 + comments.len() as u32,
+                    thread_id: thread.id,
+                    parent_id,
+                    user_id: user.id,
+                    content: self.text(10, 500),
+                    timestamp: time,
+                    hidden: self.rng.chance(HIDDEN_SHARE),
+                });
+            }
+        }
+
+        // Ids follow time, which the comments are not in across threads
+        comments.sort_by_key(|c| c.timestamp);
+        let new_ids = comments
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.id, self.max_ids.3 + 1 + i as u32))
+            .collect::<std::collections::HashMap<_, _>>();
+        for comment in &mut comments {
+            comment.id = new_ids[&comment.id];
+            comment.parent_id = comment.parent_id.map(|id| new_ids[&id]);
+        }
+
+        comments
+    }
+}
+
+/// Moves the join date of a user back to the time of new content of the user,
+/// if the content is older
+fn record(user: &mut User, timestamp: NaiveDateTime) {
+    user.joined = user.joined.min(timestamp);
+}
+
+/// Runs the `seed` subcommand
+pub fn run(database_url: &str, args: &clap::ArgMatches) -> IntResult<()> {
+    let scale = args
+        .value_of("scale")
+        .unwrap_or("1")
+        .parse()
+        .map_err(|_| IntErrorKind::InvalidData)?;
+    let rng_seed = args
+        .value_of("seed")
+        .unwrap_or("0")
+        .parse()
+        .map_err(|_| IntErrorKind::InvalidData)?;
+
+    let con = db::establish_connection(database_url)?;
+    let counts = seed(&con, scale, rng_seed)?;
+
+    info!("Seeded {}", counts);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let a = Generator::new(1, 42, (0, 0, 0, 0)).generate();
+        let b = Generator::new(1, 42, (0, 0, 0, 0)).generate();
+
+        assert_eq!(a.users.len(), USERS_PER_SCALE);
+        assert_eq!(a.threads.len(), THREADS_PER_SCALE);
+        assert_eq!(a.comments.len(), b.comments.len());
+        for (a, b) in a.comments.iter().zip(&b.comments) {
+            assert_eq!(
+                (a.id, a.parent_id, &a.content, a.timestamp),
+                (b.id, b.parent_id, &b.content, b.timestamp)
+            );
+        }
+    }
+
+    #[test]
+    fn nested_categories() {
+        let forum = Generator::new(4, 3, (0, 20, 0, 0)).generate();
+
+        let depth = |category: &Category| {
+            let (mut id, mut parent_id, mut depth) = (category.id, category.parent_id, 0);
+            while let Some(next_id) = parent_id {
+                assert!(next_id < id);
+                id = next_id;
+                parent_id = forum.categories[(next_id - 21) as usize].parent_id;
+                depth += 1;
+            }
+            depth
+        };
+        let depths = forum.categories.iter().map(depth).collect::<Vec<_>>();
+        assert!(depths.iter().any(|&depth| depth > 0));
+        assert!(depths.iter().all(|&depth| depth <= MAX_CATEGORY_DEPTH));
+    }
+
+    #[test]
+    fn parents_first() {
+        let forum = Generator::new(1, 7, (10, 10, 10, 10)).generate();
+
+        for (i, comment) in forum.comments.iter().enumerate() {
+            assert_eq!(comment.id, 11 + i as u32);
+            if let Some(parent_id) = comment.parent_id {
+                let parent = &forum.comments[(parent_id - 11) as usize];
+                assert!(parent.id < comment.id);
+                assert_eq!(parent.thread_id, comment.thread_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! Export and import of a whole forum as JSON Lines
+//!
+//! Every line of a snapshot is a record of the form
+//! `{"type":"user","data":{..}}`. The first record is a header with the
+//! version of the format, which is followed by all the users, categories,
+//! threads and comments, in that order and each in order of id. Rows are
+//! written as they are stored, so that ids, parents, timestamps and hidden
+//! flags are kept when the snapshot is imported.
+//!
+//! A snapshot can also be exported with an `Anonymizer`, which replaces the
+//! names and text in it with generated text.
+use std::fmt::{self, Display};
+use std::io::{BufRead, Write};
+
+use diesel::connection::TransactionManager;
+use diesel::Connection;
+use failure::ResultExt;
+use serde::Serialize;
+
+use super::anonymize::Anonymizer;
+use super::{open_input, open_output, BATCH_SIZE};
+use crate::db::{self, DbConn};
+use crate::types::{Category, Comment, Thread, User};
+use crate::{IntError, IntErrorKind, IntResult};
+
+/// The name of the format in the header of a snapshot
+const SNAPSHOT_FORMAT: &str = "controller-snapshot";
+/// The version of the format of a snapshot
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Record {
+    Header { format: String, version: u32 },
+    User(User),
+    Category(Category),
+    Thread(Thread),
+    Comment(Comment),
+}
+
+/// The number of rows of each kind in a snapshot
+#[derive(Default, Debug, PartialEq)]
+pub struct Counts {
+    pub users: usize,
+    pub categories: usize,
+    pub threads: usize,
+    pub comments: usize,
+}
+
+impl Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} users, {} categories, {} threads and {} comments",
+            self.users, self.categories, self.threads, self.comments
+        )
+    }
+}
+
+/// Writes all the users, categories, threads and comments as a snapshot,
+/// which is anonymized if an `Anonymizer` is given
+pub fn export<W: Write>(
+    con: &DbConn,
+    mut out: W,
+    anonymizer: Option<&Anonymizer>,
+) -> IntResult<Counts> {
+    trace!("Exporting snapshot, anonymized: {}", anonymizer.is_some());
+
+    write_record(
+        &mut out,
+        &Record::Header {
+            format: SNAPSHOT_FORMAT.to_string(),
+            version: SNAPSHOT_VERSION,
+        },
+    )?;
+
+    let counts = Counts {
+        users: write_table(
+            &mut out,
+            |after| db::export::get_users_after(con, after, BATCH_SIZE),
+            |user| user.id,
+            |user| match anonymizer {
+                Some(anonymizer) => Record::User(anonymizer.user(user)),
+                None => Record::User(user),
+            },
+        )?,
+        categories: write_table(
+            &mut out,
+            |after| db::export::get_categories_after(con, after, BATCH_SIZE),
+            |category| category.id,
+            Record::Category,
+        )?,
+        threads: write_table(
+            &mut out,
+            |after| db::export::get_threads_after(con, after, BATCH_SIZE),
+            |thread| thread.id,
+            |thread| match anonymizer {
+                Some(anonymizer) => Record::Thread(anonymizer.thread(thread)),
+                None => Record::Thread(thread),
+            },
+        )?,
+        comments: write_table(
+            &mut out,
+            |after| db::export::get_comments_after(con, after, BATCH_SIZE),
+            |comment| comment.id,
+            |comment| match anonymizer {
+                Some(anonymizer) => Record::Comment(anonymizer.comment(comment)),
+                None => Record::Comment(comment),
+            },
+        )?,
+    };
+
+    out.flush().context(IntErrorKind::IoError)?;
+    Ok(counts)
+}
+
+/// Writes the rows of a table, which are read in batches ordered by id, as
+/// records
+///
+/// `next_batch` is given the id of the last row of the previous batch, or
+/// `None` for the first batch.
+fn write_table<W, T, F, I, R>(out: &mut W, mut next_batch: F, id: I, record: R) -> IntResult<usize>
+where
+    W: Write,
+    F: FnMut(Option<u32>) -> IntResult<Vec<T>>,
+    I: Fn(&T) -> u32,
+    R: Fn(T) -> Record,
+{
+    let mut after = None;
+    let mut count = 0;
+
+    loop {
+        let batch = next_batch(after)?;
+        match batch.last() {
+            Some(last) => after = Some(id(last)),
+            None => break,
+        }
+
+        count += batch.len();
+        for row in batch {
+            write_record(out, &record(row))?;
+        }
+    }
+
+    Ok(count)
+}
+
+fn write_record<W: Write, T: Serialize>(out: &mut W, record: &T) -> IntResult<()> {
+    serde_json::to_writer(&mut *out, record).context(IntErrorKind::IoError)?;
+    out.write_all(b"\n").context(IntErrorKind::IoError)?;
+    Ok(())
+}
+
+/// Imports a snapshot in a single transaction
+///
+/// With `dry_run` set, the snapshot is imported and checked against the
+/// database, but the transaction is always rolled back.
+pub fn import<R: BufRead>(con: &DbConn, input: R, dry_run: bool) -> IntResult<Counts> {
+    trace!("Importing snapshot, dry run: {}", dry_run);
+
+    let transaction_manager = con.transaction_manager();
+    transaction_manager.begin_transaction(con)?;
+
+    let result = import_records(con, input);
+
+    if result.is_ok() && !dry_run {
+        transaction_manager.commit_transaction(con)?;
+    } else {
+        transaction_manager.rollback_transaction(con)?;
+    }
+
+    result
+}
+
+/// Rows which are waiting to be inserted in a batch
+#[derive(Default)]
+struct Pending {
+    users: Vec<User>,
+    categories: Vec<Category>,
+    threads: Vec<Thread>,
+    comments: Vec<Comment>,
+    /// The accepted answers, which are set once all the comments are inserted
+    answers: Vec<(u32, u32)>,
+    counts: Counts,
+}
+
+impl Pending {
+    /// Inserts all the pending rows
+    fn flush(&mut self, con: &DbConn) -> IntResult<()> {
+        if !self.users.is_empty() {
+            self.counts.users += db::import::insert_users(con, &self.users)?;
+            self.users.clear();
+        }
+        if !self.categories.is_empty() {
+            self.counts.categories += db::import::insert_categories(con, &self.categories)?;
+            self.categories.clear();
+        }
+        if !self.threads.is_empty() {
+            self.counts.threads += db::import::insert_threads(con, &self.threads)?;
+            self.threads.clear();
+        }
+        if !self.comments.is_empty() {
+            self.counts.comments += db::import::insert_comments(con, &self.comments)?;
+            self.comments.clear();
+        }
+        Ok(())
+    }
+
+    /// The number of rows which are pending, if they are all of the same kind
+    /// as `record`, and otherwise `None`
+    fn pending_of_kind(&self, record: &Record) -> Option<usize> {
+        let (same, total) = match record {
+            Record::User(_) => (self.users.len(), self.len()),
+            Record::Category(_) => (self.categories.len(), self.len()),
+            Record::Thread(_) => (self.threads.len(), self.len()),
+            Record::Comment(_) => (self.comments.len(), self.len()),
+            Record::Header { .. } => (0, self.len()),
+        };
+        if same == total {
+            Some(same)
+        } else {
+            None
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.users.len() + self.categories.len() + self.threads.len() + self.comments.len()
+    }
+}
+
+fn import_records<R: BufRead>(con: &DbConn, input: R) -> IntResult<Counts> {
+    let mut pending = Pending::default();
+    let mut header = false;
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.context(IntErrorKind::IoError)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: Record = serde_json::from_str(&line)
+            .context(IntErrorKind::InvalidData)
+            .map_err(|e| {
+                error!("Unable to parse line {} of snapshot: {}", i + 1, e);
+                IntError::from(e)
+            })?;
+
+        if let Record::Header { format, version } = &record {
+            if header || format != SNAPSHOT_FORMAT || *version != SNAPSHOT_VERSION {
+                error!(
+                    "Unexpected header on line {}: {} version {}",
+                    i + 1,
+                    format,
+                    version
+                );
+                return Err(IntErrorKind::InvalidData.into());
+            }
+            header = true;
+            continue;
+        } else if !header {
+            error!("The snapshot does not start with a header");
+            return Err(IntErrorKind::InvalidData.into());
+        }
+
+        // Rows are inserted in batches of a single kind, in the order of the
+        // snapshot, so that rows are inserted after the rows they refer to
+        match pending.pending_of_kind(&record) {
+            Some(n) if n < BATCH_SIZE as usize => {}
+            _ => pending.flush(con)?,
+        }
+
+        match record {
+            Record::User(user) => pending.users.push(user),
+            Record::Category(category) => pending.categories.push(category),
+            Record::Thread(mut thread) => {
+                if let Some(answer_id) = thread.answer_id.take() {
+                    pending.answers.push((thread.id, answer_id));
+                }
+                pending.threads.push(thread);
+            }
+            Record::Comment(comment) => pending.comments.push(comment),
+            Record::Header { .. } => unreachable!(),
+        }
+    }
+
+    if !header {
+        error!("The snapshot is empty");
+        return Err(IntErrorKind::InvalidData.into());
+    }
+
+    pending.flush(con)?;
+    db::import::set_answers(con, &pending.answers)?;
+
+    Ok(pending.counts)
+}
+
+/// Runs the `export` subcommand
+pub fn run_export(database_url: &str, args: &clap::ArgMatches) -> IntResult<()> {
+    let out = open_output(args.value_of("FILE").unwrap_or("-"))?;
+    let anonymizer = if args.is_present("anonymize") {
+        Some(Anonymizer::new(args.value_of("salt").unwrap_or("")))
+    } else {
+        None
+    };
+
+    let con = db::establish_connection(database_url)?;
+    let counts = db::export::in_snapshot(&con, || export(&con, out, anonymizer.as_ref()))?;
+
+    if anonymizer.is_some() {
+        info!("Exported {}, anonymized", counts);
+    } else {
+        info!("Exported {}", counts);
+    }
+    Ok(())
+}
+
+/// Runs the `import` subcommand
+pub fn run_import(database_url: &str, args: &clap::ArgMatches) -> IntResult<()> {
+    let input = open_input(args.value_of("FILE").unwrap_or("-"))?;
+    let dry_run = args.is_present("dry-run");
+
+    let con = db::establish_connection(database_url)?;
+    let counts = import(&con, input, dry_run)?;
+
+    if dry_run {
+        info!("Dry run: would have imported {}", counts);
+    } else {
+        info!("Imported {}", counts);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::establish_connection;
+
+    #[test]
+    fn export_lines() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        let mut data = Vec::new();
+        let returned_data = db::export::in_snapshot(&con, || export(&con, &mut data, None));
+        assert!(returned_data.is_ok());
+        let counts = returned_data.unwrap();
+
+        let data = String::from_utf8(data).unwrap();
+        let records = data
+            .lines()
+            .map(|line| serde_json::from_str::<Record>(line).unwrap())
+            .collect::<Vec<_>>();
+        match records[0] {
+            Record::Header { version, .. } => assert_eq!(version, SNAPSHOT_VERSION),
+            _ => panic!("missing header"),
+        }
+        assert_eq!(
+            records.len(),
+            1 + counts.users + counts.categories + counts.threads + counts.comments
+        );
+    }
+
+    #[test]
+    fn import_invalid_header() {
+        let con = establish_connection(&std::env::var("CONTROLLER_DATABASE_URL").unwrap()).unwrap();
+
+        let data = "{\"type\":\"header\",\"data\":{\"format\":\"controller-snapshot\",\"version\":0}}\n";
+        let returned_data = import(&con, data.as_bytes(), true);
+        assert_eq!(returned_data.unwrap_err().kind(), IntErrorKind::InvalidData);
+
+        let data = "{\"type\":\"category\",\"data\":{}}\n";
+        let returned_data = import(&con, data.as_bytes(), true);
+        assert_eq!(returned_data.unwrap_err().kind(), IntErrorKind::InvalidData);
+    }
+}
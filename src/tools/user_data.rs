@@ -0,0 +1,107 @@
+//! Export of everything stored about a single user as one JSON document
+//!
+//! The document has the following fields, in order:
+//!
+//! ```text
+//! {"version":1,"user":{..},"records":{..},"threads":[..],"comments":[..]}
+//! ```
+//!
+//! The records include the audit entries of actions taken by or on the user.
+//! Threads and comments, hidden ones included, are read and written in
+//! batches so that the document is streamed rather than built in memory.
+use std::io::Write;
+
+use failure::ResultExt;
+use serde::Serialize;
+
+use super::{open_output, BATCH_SIZE};
+use crate::db::{self, DbConn};
+use crate::{IntErrorKind, IntResult};
+
+use datatypes::valid::ids::*;
+
+/// The version of the format of the document
+const USER_DATA_VERSION: u32 = 1;
+
+/// Writes everything stored about a user as a JSON document
+///
+/// The data is read in many queries, which should run in
+/// `db::export::in_snapshot` for the document to be consistent.
+pub fn write_user_data<W: Write>(con: &DbConn, id: UserId, mut out: W) -> IntResult<()> {
+    trace!("Exporting data of user ({})", id);
+
+    let user = db::users::get_user(con, id)?;
+    let records = db::export::get_user_records(con, id)?;
+
+    write!(out, "{{\"version\":{},\"user\":", USER_DATA_VERSION)
+        .context(IntErrorKind::IoError)?;
+    serde_json::to_writer(&mut out, &user).context(IntErrorKind::IoError)?;
+    out.write_all(b",\"records\":").context(IntErrorKind::IoError)?;
+    serde_json::to_writer(&mut out, &records).context(IntErrorKind::IoError)?;
+
+    out.write_all(b",\"threads\":").context(IntErrorKind::IoError)?;
+    write_batches(
+        &mut out,
+        |after| db::export::get_user_threads(con, id, after, BATCH_SIZE),
+        |thread| thread.id,
+    )?;
+
+    out.write_all(b",\"comments\":").context(IntErrorKind::IoError)?;
+    write_batches(
+        &mut out,
+        |after| db::export::get_user_comments(con, id, after, BATCH_SIZE),
+        |comment| comment.id,
+    )?;
+
+    out.write_all(b"}\n").context(IntErrorKind::IoError)?;
+    out.flush().context(IntErrorKind::IoError)?;
+    Ok(())
+}
+
+/// Writes a JSON array of rows which are read in batches ordered by id, until
+/// an empty batch is read
+fn write_batches<W, T, F, I>(out: &mut W, mut next_batch: F, id: I) -> IntResult<()>
+where
+    W: Write,
+    T: Serialize,
+    F: FnMut(u32) -> IntResult<Vec<T>>,
+    I: Fn(&T) -> u32,
+{
+    let mut after = 0;
+    let mut first = true;
+
+    out.write_all(b"[").context(IntErrorKind::IoError)?;
+    loop {
+        let batch = next_batch(after)?;
+        match batch.last() {
+            Some(last) => after = id(last),
+            None => break,
+        }
+
+        for row in &batch {
+            if !first {
+                out.write_all(b",").context(IntErrorKind::IoError)?;
+            }
+            first = false;
+            serde_json::to_writer(&mut *out, row).context(IntErrorKind::IoError)?;
+        }
+    }
+    out.write_all(b"]").context(IntErrorKind::IoError)?;
+
+    Ok(())
+}
+
+/// Runs the `export-user` subcommand
+pub fn run(database_url: &str, args: &clap::ArgMatches) -> IntResult<()> {
+    let id: u32 = args
+        .value_of("ID")
+        .and_then(|id| id.parse().ok())
+        .ok_or(IntErrorKind::InvalidId)?;
+    let out = open_output(args.value_of("FILE").unwrap_or("-"))?;
+
+    let con = db::establish_connection(database_url)?;
+    db::export::in_snapshot(&con, || write_user_data(&con, id.into(), out))?;
+
+    info!("Exported data of user ({})", id);
+    Ok(())
+}
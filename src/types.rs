@@ -1,18 +1,21 @@
 use crate::db::schema::*;
+use crate::payloads::*;
 
 use datatypes::content::requests::*;
 use datatypes::content::responses::*;
 use datatypes::valid::ValidationError;
 
 use chrono::naive::NaiveDateTime;
+use diesel::sql_types::{BigInt, Datetime, Integer, Nullable, Text, Unsigned};
 use std::convert::TryInto;
 
-#[derive(Identifiable, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Identifiable, Queryable, Insertable, Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct User {
     pub id: u32,
     pub username: String,
     pub description: Option<String>,
     pub avatar: Option<String>,
+    pub joined: NaiveDateTime,
 }
 
 impl TryInto<UserPayload> for User {
@@ -28,6 +31,21 @@ impl TryInto<UserPayload> for User {
     }
 }
 
+impl TryInto<UserProfilePayload> for (User, UserStats) {
+    type Error = ValidationError;
+    fn try_into(self) -> Result<UserProfilePayload, Self::Error> {
+        let (user, stats) = self;
+        let joined = user.joined;
+        Ok(UserProfilePayload {
+            user: user.try_into()?,
+            joined,
+            thread_count: stats.thread_count as u32,
+            comment_count: stats.comment_count as u32,
+            last_active: stats.last_active,
+        })
+    }
+}
+
 #[derive(Identifiable, AsChangeset, Debug)]
 #[table_name = "users"]
 pub struct UpdateUser {
@@ -62,13 +80,16 @@ impl From<AddUserPayload> for InsertUser {
     }
 }
 
-#[derive(Identifiable, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Identifiable, Queryable, Insertable, Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[table_name = "categories"]
 pub struct Category {
     pub id: u32,
     pub title: String,
     pub description: String,
     pub hidden: bool,
+    pub qa: bool,
+    /// The category this one is a subcategory of, if any
+    pub parent_id: Option<u32>,
 }
 
 impl TryInto<CategoryPayload> for Category {
@@ -132,7 +153,17 @@ impl From<AddCategoryPayload> for InsertCategory {
     }
 }
 
-#[derive(Identifiable, Associations, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(
+    Identifiable,
+    Associations,
+    Queryable,
+    Insertable,
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    PartialEq,
+)]
 #[belongs_to(Category)]
 #[belongs_to(User)]
 pub struct Thread {
@@ -143,6 +174,7 @@ pub struct Thread {
     pub description: String,
     pub timestamp: NaiveDateTime,
     pub hidden: bool,
+    pub answer_id: Option<u32>,
 }
 
 impl TryInto<ThreadPayload> for Thread {
@@ -162,6 +194,15 @@ impl TryInto<ThreadPayload> for Thread {
     }
 }
 
+impl From<Thread> for AnswerPayload {
+    fn from(t: Thread) -> AnswerPayload {
+        AnswerPayload {
+            thread_id: t.id.into(),
+            comment_id: t.answer_id.map(|id| id.into()),
+        }
+    }
+}
+
 #[derive(Identifiable, AsChangeset, Debug)]
 #[table_name = "threads"]
 pub struct UpdateThread {
@@ -213,7 +254,9 @@ impl From<AddThreadPayload> for InsertThread {
     }
 }
 
-#[derive(Identifiable, Associations, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(
+    Identifiable, Associations, Queryable, Insertable, Debug, Serialize, Deserialize, PartialEq,
+)]
 #[belongs_to(Thread)]
 #[belongs_to(User)]
 pub struct Comment {
@@ -290,6 +333,282 @@ impl From<AddCommentPayload> for InsertComment {
     }
 }
 
+/// A new thread (`comment_id` is `None`) or comment whose subscribers are
+/// still to be notified
+#[derive(Queryable, Debug, PartialEq)]
+pub struct QueuedNotification {
+    pub id: u32,
+    pub thread_id: u32,
+    pub comment_id: Option<u32>,
+    /// The number of failed attempts to deliver the notifications
+    pub attempts: u32,
+}
+
+#[derive(Queryable, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Notification {
+    pub id: u32,
+    pub user_id: u32,
+    pub thread_id: u32,
+    pub comment_id: Option<u32>,
+    pub timestamp: NaiveDateTime,
+    pub seen: bool,
+    pub mention: bool,
+}
+
+impl From<Notification> for NotificationPayload {
+    fn from(n: Notification) -> NotificationPayload {
+        NotificationPayload {
+            id: n.id,
+            user_id: n.user_id.into(),
+            thread_id: n.thread_id.into(),
+            comment_id: n.comment_id.map(|id| id.into()),
+            timestamp: n.timestamp,
+            seen: n.seen,
+            mention: n.mention,
+        }
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "notifications"]
+pub struct InsertNotification {
+    pub user_id: u32,
+    pub thread_id: u32,
+    pub comment_id: Option<u32>,
+    pub mention: bool,
+}
+
+#[derive(Queryable, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Mention {
+    pub comment_id: u32,
+    pub user_id: u32,
+    pub timestamp: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "mentions"]
+pub struct InsertMention {
+    pub comment_id: u32,
+    pub user_id: u32,
+}
+
+#[derive(Insertable, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[table_name = "thread_subscriptions"]
+pub struct ThreadSubscription {
+    pub user_id: u32,
+    pub thread_id: u32,
+}
+
+#[derive(Insertable, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[table_name = "category_subscriptions"]
+pub struct CategorySubscription {
+    pub user_id: u32,
+    pub category_id: u32,
+}
+
+#[derive(Queryable, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ThreadRead {
+    pub user_id: u32,
+    pub thread_id: u32,
+    pub comment_id: u32,
+}
+
+#[derive(Identifiable, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Bookmark {
+    pub id: u32,
+    pub user_id: u32,
+    pub thread_id: Option<u32>,
+    pub comment_id: Option<u32>,
+    pub note: Option<String>,
+    pub timestamp: NaiveDateTime,
+}
+
+impl From<Bookmark> for BookmarkPayload {
+    fn from(b: Bookmark) -> BookmarkPayload {
+        let target = match (b.thread_id, b.comment_id) {
+            (_, Some(comment_id)) => BookmarkTarget::Comment(comment_id.into()),
+            (thread_id, None) => BookmarkTarget::Thread(thread_id.unwrap_or(0).into()),
+        };
+        BookmarkPayload {
+            id: b.id,
+            user_id: b.user_id.into(),
+            target,
+            note: b.note,
+            timestamp: b.timestamp,
+        }
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "bookmarks"]
+pub struct InsertBookmark {
+    pub user_id: u32,
+    pub thread_id: Option<u32>,
+    pub comment_id: Option<u32>,
+    pub note: Option<String>,
+}
+
+impl From<AddBookmarkPayload> for InsertBookmark {
+    fn from(p: AddBookmarkPayload) -> InsertBookmark {
+        let (thread_id, comment_id) = match p.target {
+            BookmarkTarget::Thread(id) => (Some(*id), None),
+            BookmarkTarget::Comment(id) => (None, Some(*id)),
+        };
+        InsertBookmark {
+            user_id: p.user_id.map_or(0, |id| *id),
+            thread_id,
+            comment_id,
+            note: p.note,
+        }
+    }
+}
+
+#[derive(Identifiable, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[table_name = "username_history"]
+pub struct UsernameChange {
+    pub id: u32,
+    pub user_id: u32,
+    pub username: String,
+    pub changed: NaiveDateTime,
+}
+
+impl From<UsernameChange> for UsernameChangePayload {
+    fn from(c: UsernameChange) -> UsernameChangePayload {
+        UsernameChangePayload {
+            user_id: c.user_id.into(),
+            username: c.username,
+            changed: c.changed,
+        }
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "username_history"]
+pub struct InsertUsernameChange {
+    pub user_id: u32,
+    pub username: String,
+    pub changed: NaiveDateTime,
+}
+
+/// A rename is validated through `AddUserPayload`, so that the same username
+/// rules apply to renamed users as to new ones
+impl TryInto<InsertUser> for RenameUserPayload {
+    type Error = ValidationError;
+    fn try_into(self) -> Result<InsertUser, Self::Error> {
+        let p = AddUserPayload {
+            id: self.id.unwrap_or_else(|| 0.into()),
+            username: self.username.try_into()?,
+        };
+        Ok(p.into())
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "audit_log"]
+pub struct InsertAuditEntry {
+    pub action: String,
+    pub user_id: u32,
+    pub actor_id: Option<u32>,
+    pub details: String,
+}
+
+#[derive(Identifiable, Queryable, Debug, Serialize, Deserialize, PartialEq)]
+#[table_name = "audit_log"]
+pub struct AuditEntry {
+    pub id: u32,
+    pub action: String,
+    pub user_id: u32,
+    pub actor_id: Option<u32>,
+    pub details: String,
+    pub timestamp: NaiveDateTime,
+}
+
+/// The records of a user which are neither threads nor comments, as exported
+/// along with the profile of the user
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UserRecords {
+    pub username_history: Vec<UsernameChange>,
+    pub thread_subscriptions: Vec<ThreadSubscription>,
+    pub category_subscriptions: Vec<CategorySubscription>,
+    pub thread_reads: Vec<ThreadRead>,
+    pub notifications: Vec<Notification>,
+    pub mentions: Vec<Mention>,
+    pub bookmarks: Vec<Bookmark>,
+    /// The audit entries of actions taken by or on the user
+    pub audit_log: Vec<AuditEntry>,
+}
+
+/// A thread or comment in the activity of a user
+#[derive(Serialize, Debug, PartialEq)]
+pub enum Activity {
+    Thread(Thread),
+    Comment(Comment),
+}
+
+impl TryInto<ActivityPayload> for Activity {
+    type Error = ValidationError;
+    fn try_into(self) -> Result<ActivityPayload, Self::Error> {
+        Ok(match self {
+            Activity::Thread(thread) => ActivityPayload::Thread(thread.try_into()?),
+            Activity::Comment(comment) => ActivityPayload::Comment(comment.try_into()?),
+        })
+    }
+}
+
+/// A reference to a thread (`kind` 0) or comment (`kind` 1) in the activity of
+/// a user
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct ActivityItem {
+    #[sql_type = "BigInt"]
+    pub kind: i64,
+    #[sql_type = "Unsigned<Integer>"]
+    pub id: u32,
+    #[sql_type = "Datetime"]
+    pub timestamp: NaiveDateTime,
+}
+
+/// The number of threads and comments of a user, and the time of the latest
+/// of them
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct UserStats {
+    #[sql_type = "BigInt"]
+    pub thread_count: i64,
+    #[sql_type = "BigInt"]
+    pub comment_count: i64,
+    #[sql_type = "Nullable<Datetime>"]
+    pub last_active: Option<NaiveDateTime>,
+}
+
+/// The id of a row selected by a raw query
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct RowId {
+    #[sql_type = "Unsigned<Integer>"]
+    pub id: u32,
+}
+
+/// The latest migration applied to the database
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct SchemaVersion {
+    #[sql_type = "Nullable<Text>"]
+    pub version: Option<String>,
+}
+
+/// The number of tables, columns or indexes of the schema matching a query
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct SchemaObjects {
+    #[sql_type = "BigInt"]
+    pub count: i64,
+}
+
+/// The number of unread comments in a thread for some user
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct UnreadCount {
+    #[sql_type = "Unsigned<Integer>"]
+    pub thread_id: u32,
+    #[sql_type = "BigInt"]
+    pub unread: i64,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct SearchResults {
     pub categories: Vec<Category>,